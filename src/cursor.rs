@@ -0,0 +1,53 @@
+use definitions::*;
+use schema::*;
+use plan::select_plan::ResultSet;
+
+// Streams the rows of a query one at a time. The cursor owns a snapshot of the
+// schema taken when the query was started so statements executed while it is
+// open do not affect the rows it returns.
+pub struct Cursor {
+    columns: Vec<ColumnDefinition>,
+    schema: Schema,
+    plan: ResultSet,
+    finished: bool
+}
+
+impl Cursor {
+    pub fn new(columns: Vec<ColumnDefinition>, schema: Schema, plan: ResultSet) -> Cursor {
+        Cursor {
+            columns: columns,
+            schema: schema,
+            plan: plan,
+            finished: false
+        }
+    }
+
+    pub fn columns(&self) -> &[ColumnDefinition] {
+        &self.columns[..]
+    }
+
+    pub fn column_names(&self) -> Vec<String> {
+        self.columns.iter().map(|c| c.name.clone()).collect()
+    }
+}
+
+impl Iterator for Cursor {
+    type Item = SqlError<Vec<LiteralValue>>;
+
+    fn next(&mut self) -> Option<SqlError<Vec<LiteralValue>>> {
+        if self.finished {
+            return None;
+        }
+        match self.plan.get_next_row(&self.schema) {
+            Ok(Some(row)) => Some(Ok(row)),
+            Ok(None) => {
+                self.finished = true;
+                None
+            },
+            Err(e) => {
+                self.finished = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
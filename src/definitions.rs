@@ -1,12 +1,19 @@
-pub type SqlError<T> = Result<T,String>;
+pub use error::Error;
+use std::cmp::Ordering;
+
+pub type SqlError<T> = Result<T,Error>;
 
 #[derive(Debug,Clone,PartialEq)]
 pub enum SqlStmt {
     Select(SelectStmt),
+    SetOperation(SetOperationStmt),
+    With(WithStmt),
     DumpTables,
     CreateTable(CreateTableStmt),
     DropTable(DropTableStmt),
-    Insert(InsertStmt)
+    Insert(InsertStmt),
+    Update(UpdateStmt),
+    Delete(DeleteStmt)
 }
 
 #[derive(Debug,Clone,PartialEq)]
@@ -22,10 +29,50 @@ pub enum SqlResult {
 pub enum LiteralValue {
     Null,
     Text(String),
-    Bool(bool)
+    Bool(bool),
+    Integer(i64),
+    Real(f64)
+}
+
+// Compares an integer with a real exactly. Converting the integer to a real
+// would round the integers beyond 2^53, making distinct integers equal to the
+// same real.
+fn compare_integer_real(i: i64, r: f64) -> Option<Ordering> {
+    if r.is_nan() {
+        return None;
+    }
+    // 2^63 is above every i64 while -2^63 is the smallest one
+    if r >= 9223372036854775808.0 {
+        return Some(Ordering::Less);
+    }
+    if r < -9223372036854775808.0 {
+        return Some(Ordering::Greater);
+    }
+    let floor = r.floor();
+    match i.cmp(&(floor as i64)) {
+        Ordering::Equal if r > floor => Some(Ordering::Less),
+        ordering => Some(ordering)
+    }
 }
 
 impl LiteralValue {
+    // The value of a numeric literal matched by the parser. Integers too large
+    // for an Integer are read as Real instead.
+    pub fn from_numeric(text: &str) -> LiteralValue {
+        let integral = !text.contains('.') && !text.contains('e') && !text.contains('E');
+        if integral {
+            if let Ok(i) = text.parse() {
+                return LiteralValue::Integer(i);
+            }
+        }
+        // Digits with an optional sign, fraction and exponent always parse
+        // as an f64, overflowing to infinity
+        match text.parse() {
+            Ok(r) => LiteralValue::Real(r),
+            Err(_) => LiteralValue::Real(::std::f64::NAN)
+        }
+    }
+
     pub fn as_string(&self) -> SqlError<String> {
         match *self {
             LiteralValue::Null => {
@@ -36,6 +83,12 @@ impl LiteralValue {
             },
             LiteralValue::Bool(b) => {
                 Ok(format!("{}",b))
+            },
+            LiteralValue::Integer(i) => {
+                Ok(format!("{}",i))
+            },
+            LiteralValue::Real(r) => {
+                Ok(format!("{}",r))
             }
         }
     }
@@ -43,13 +96,65 @@ impl LiteralValue {
     pub fn as_bool(&self) -> SqlError<bool> {
         match *self {
             LiteralValue::Null => {
-                Err("Cannot convert Null value to bool".to_string())
+                Err(Error::Type("Cannot convert Null value to bool".to_string()))
             },
             LiteralValue::Text(ref s) => {
-                Err(format!("Cannot convert Text value ({}) to bool",s))
+                Err(Error::Type(format!("Cannot convert Text value ({}) to bool",s)))
             },
             LiteralValue::Bool(b) => {
                 Ok(b)
+            },
+            LiteralValue::Integer(i) => {
+                Err(Error::Type(format!("Cannot convert Integer value ({}) to bool",i)))
+            },
+            LiteralValue::Real(r) => {
+                Err(Error::Type(format!("Cannot convert Real value ({}) to bool",r)))
+            }
+        }
+    }
+
+    pub fn as_integer(&self) -> SqlError<i64> {
+        match *self {
+            LiteralValue::Null => {
+                Err(Error::Type("Cannot convert Null value to integer".to_string()))
+            },
+            LiteralValue::Text(ref s) => {
+                s.trim().parse::<i64>().map_err(|_| Error::Type(format!("Cannot convert Text value ({}) to integer",s)))
+            },
+            LiteralValue::Bool(b) => {
+                Err(Error::Type(format!("Cannot convert Bool value ({}) to integer",b)))
+            },
+            LiteralValue::Integer(i) => {
+                Ok(i)
+            },
+            LiteralValue::Real(r) => {
+                // Only allow reals that can be represented exactly
+                if r.fract() == 0.0 && r >= (i64::min_value() as f64) && r < (i64::max_value() as f64) {
+                    Ok(r as i64)
+                }
+                else {
+                    Err(Error::Type(format!("Cannot convert Real value ({}) to integer",r)))
+                }
+            }
+        }
+    }
+
+    pub fn as_real(&self) -> SqlError<f64> {
+        match *self {
+            LiteralValue::Null => {
+                Err(Error::Type("Cannot convert Null value to real".to_string()))
+            },
+            LiteralValue::Text(ref s) => {
+                s.trim().parse::<f64>().map_err(|_| Error::Type(format!("Cannot convert Text value ({}) to real",s)))
+            },
+            LiteralValue::Bool(b) => {
+                Err(Error::Type(format!("Cannot convert Bool value ({}) to real",b)))
+            },
+            LiteralValue::Integer(i) => {
+                Ok(i as f64)
+            },
+            LiteralValue::Real(r) => {
+                Ok(r)
             }
         }
     }
@@ -61,6 +166,56 @@ impl LiteralValue {
             },
             ColumnType::Bool => {
                 Ok(LiteralValue::Bool(try!(self.as_bool())))
+            },
+            ColumnType::Integer => {
+                Ok(LiteralValue::Integer(try!(self.as_integer())))
+            },
+            ColumnType::Real => {
+                Ok(LiteralValue::Real(try!(self.as_real())))
+            }
+        }
+    }
+
+    // Compares two values of compatible types. Numbers compare numerically
+    // regardless of whether they are integers or reals. Returns None if
+    // either value is NULL or the types cannot be compared.
+    pub fn compare(&self, other: &LiteralValue) -> Option<Ordering> {
+        match (self, other) {
+            (&LiteralValue::Text(ref a), &LiteralValue::Text(ref b)) => Some(a.cmp(b)),
+            (&LiteralValue::Bool(a), &LiteralValue::Bool(b)) => Some(a.cmp(&b)),
+            (&LiteralValue::Integer(a), &LiteralValue::Integer(b)) => Some(a.cmp(&b)),
+            (&LiteralValue::Integer(a), &LiteralValue::Real(b)) => compare_integer_real(a, b),
+            (&LiteralValue::Real(a), &LiteralValue::Integer(b)) => compare_integer_real(b, a).map(|o| o.reverse()),
+            (&LiteralValue::Real(a), &LiteralValue::Real(b)) => a.partial_cmp(&b),
+            _ => None
+        }
+    }
+
+    // Total order used when sorting values: NULL first, then booleans,
+    // numbers and text. NaN sorts after every other number.
+    pub fn sort_cmp(&self, other: &LiteralValue) -> Ordering {
+        fn rank(value: &LiteralValue) -> u8 {
+            match *value {
+                LiteralValue::Null => 0,
+                LiteralValue::Bool(_) => 1,
+                LiteralValue::Integer(_) | LiteralValue::Real(_) => 2,
+                LiteralValue::Text(_) => 3
+            }
+        }
+        fn is_nan(value: &LiteralValue) -> bool {
+            match *value {
+                LiteralValue::Real(r) => r.is_nan(),
+                _ => false
+            }
+        }
+
+        match self.compare(other) {
+            Some(ordering) => ordering,
+            None => {
+                match rank(self).cmp(&rank(other)) {
+                    Ordering::Equal => is_nan(self).cmp(&is_nan(other)),
+                    ordering => ordering
+                }
             }
         }
     }
@@ -80,34 +235,419 @@ pub struct FunctionCall {
 
 #[derive(Debug,Clone,PartialEq)]
 pub struct SelectStmt {
+    // Whether duplicate result rows are removed
+    pub distinct: bool,
     pub projection: Vec<SelectProjectionColumn>,
     pub from: Vec<SelectFromTable>,
-    pub filter: Vec<SelectWhereFilter>
+    pub filter: Option<Expr>,
+    pub group_by: Vec<Expr>,
+    pub having: Option<Expr>,
+    pub order_by: Vec<OrderByItem>,
+    pub limit: Option<Expr>,
+    pub offset: Option<Expr>
+}
+
+// A statement producing rows, either a single SELECT or SELECTs combined by
+// set operations
+#[derive(Debug,Clone,PartialEq)]
+pub enum Query {
+    Select(SelectStmt),
+    SetOperation(SetOperationStmt),
+    With(WithStmt)
+}
+
+impl From<SelectStmt> for Query {
+    fn from(stmt: SelectStmt) -> Query {
+        Query::Select(stmt)
+    }
+}
+
+impl From<SetOperationStmt> for Query {
+    fn from(stmt: SetOperationStmt) -> Query {
+        Query::SetOperation(stmt)
+    }
+}
+
+impl From<WithStmt> for Query {
+    fn from(stmt: WithStmt) -> Query {
+        Query::With(stmt)
+    }
+}
+
+impl Query {
+    // Builds a left associative chain of set operations
+    pub fn fold_set_operations(first: Query, rest: Vec<((SetOperator, bool), Query)>) -> Query {
+        rest.into_iter().fold(first, |left, ((operator, all), right)| {
+            Query::SetOperation(SetOperationStmt {
+                operator: operator,
+                all: all,
+                left: Box::new(left),
+                right: Box::new(right),
+                order_by: Vec::new(),
+                limit: None,
+                offset: None
+            })
+        })
+    }
+
+    // Applies the ORDER BY and LIMIT clauses following a query. A parenthesized
+    // query keeps its own clauses unless they are overridden.
+    pub fn with_modifiers(self, order_by: Vec<OrderByItem>, limit: Option<Expr>, offset: Option<Expr>) -> Query {
+        match self {
+            Query::Select(mut stmt) => {
+                if !order_by.is_empty() {
+                    stmt.order_by = order_by;
+                }
+                stmt.limit = limit.or(stmt.limit);
+                stmt.offset = offset.or(stmt.offset);
+                Query::Select(stmt)
+            },
+            Query::SetOperation(mut stmt) => {
+                if !order_by.is_empty() {
+                    stmt.order_by = order_by;
+                }
+                stmt.limit = limit.or(stmt.limit);
+                stmt.offset = offset.or(stmt.offset);
+                Query::SetOperation(stmt)
+            },
+            Query::With(mut stmt) => {
+                stmt.query = Box::new((*stmt.query).with_modifiers(order_by, limit, offset));
+                Query::With(stmt)
+            }
+        }
+    }
+
+    pub fn into_stmt(self) -> SqlStmt {
+        match self {
+            Query::Select(stmt) => SqlStmt::Select(stmt),
+            Query::SetOperation(stmt) => SqlStmt::SetOperation(stmt),
+            Query::With(stmt) => SqlStmt::With(stmt)
+        }
+    }
+}
+
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum SetOperator {
+    Union,
+    Intersect,
+    Except
+}
+
+impl SetOperator {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            SetOperator::Union => "UNION",
+            SetOperator::Intersect => "INTERSECT",
+            SetOperator::Except => "EXCEPT"
+        }
+    }
+}
+
+// Combines the rows of two queries. Duplicate rows are removed from the result
+// unless all is set. ORDER BY and LIMIT apply to the combined rows.
+#[derive(Debug,Clone,PartialEq)]
+pub struct SetOperationStmt {
+    pub operator: SetOperator,
+    pub all: bool,
+    pub left: Box<Query>,
+    pub right: Box<Query>,
+    pub order_by: Vec<OrderByItem>,
+    pub limit: Option<Expr>,
+    pub offset: Option<Expr>
+}
+
+// Names queries that the main query can read like tables. With recursive set,
+// a query of the form anchor UNION [ALL] recursive term can read itself.
+#[derive(Debug,Clone,PartialEq)]
+pub struct WithStmt {
+    pub recursive: bool,
+    pub ctes: Vec<CommonTableExpr>,
+    pub query: Box<Query>
+}
+
+// The columns are named after the query's unless column_names is given
+#[derive(Debug,Clone,PartialEq)]
+pub struct CommonTableExpr {
+    pub name: String,
+    pub column_names: Vec<String>,
+    pub query: Query
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct OrderByItem {
+    pub expr: Expr,
+    pub descending: bool,
+    // Where NULLs go when not stated: last when ascending, first when descending
+    pub nulls_first: Option<bool>
 }
 
 #[derive(Debug,Clone,PartialEq)]
 pub enum SelectProjectionColumn {
-    Named(String),
-    LiteralValue(LiteralValue),
-    Wildcard
+    Expr(Expr),
+    Wildcard,
+    // All the columns of a single table, as in t.*
+    TableWildcard(String),
+    Aliased(Box<SelectProjectionColumn>, String)
 }
 
 
 #[derive(Debug,Clone,PartialEq)]
 pub enum SelectFromTable {
     Function(FunctionCall),
-    NamedTable(String)
+    NamedTable(String),
+    // A derived table, which must be given an alias
+    Subquery(Box<Query>),
+    Join(Box<SelectJoin>),
+    Aliased(Box<SelectFromTable>, String)
+}
+
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum JoinType {
+    Cross,
+    Inner,
+    LeftOuter,
+    RightOuter,
+    FullOuter
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct SelectJoin {
+    pub join_type: JoinType,
+    pub left: SelectFromTable,
+    pub right: SelectFromTable,
+    pub on: Option<Expr>
+}
+
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum UnaryOperator {
+    Not,
+    Minus,
+    Plus
+}
+
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum BinaryOperator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+    Concat,
+    Equals,
+    NotEquals,
+    Less,
+    LessEquals,
+    Greater,
+    GreaterEquals,
+    And,
+    Or
+}
+
+impl BinaryOperator {
+    pub fn symbol(&self) -> &'static str {
+        match *self {
+            BinaryOperator::Add => "+",
+            BinaryOperator::Subtract => "-",
+            BinaryOperator::Multiply => "*",
+            BinaryOperator::Divide => "/",
+            BinaryOperator::Modulo => "%",
+            BinaryOperator::Concat => "||",
+            BinaryOperator::Equals => "=",
+            BinaryOperator::NotEquals => "<>",
+            BinaryOperator::Less => "<",
+            BinaryOperator::LessEquals => "<=",
+            BinaryOperator::Greater => ">",
+            BinaryOperator::GreaterEquals => ">=",
+            BinaryOperator::And => "AND",
+            BinaryOperator::Or => "OR"
+        }
+    }
+
+    pub fn is_arithmetic(&self) -> bool {
+        match *self {
+            BinaryOperator::Add | BinaryOperator::Subtract | BinaryOperator::Multiply |
+            BinaryOperator::Divide | BinaryOperator::Modulo => true,
+            _ => false
+        }
+    }
+
+    pub fn is_comparison(&self) -> bool {
+        match *self {
+            BinaryOperator::Equals | BinaryOperator::NotEquals | BinaryOperator::Less |
+            BinaryOperator::LessEquals | BinaryOperator::Greater | BinaryOperator::GreaterEquals => true,
+            _ => false
+        }
+    }
+}
+
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum AggregateFunction {
+    Count,
+    Sum,
+    Avg,
+    Min,
+    Max
+}
+
+impl AggregateFunction {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            AggregateFunction::Count => "count",
+            AggregateFunction::Sum => "sum",
+            AggregateFunction::Avg => "avg",
+            AggregateFunction::Min => "min",
+            AggregateFunction::Max => "max"
+        }
+    }
+}
+
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum WindowFunction {
+    RowNumber,
+    Rank,
+    DenseRank,
+    Lag,
+    Lead,
+    FirstValue,
+    LastValue,
+    Aggregate(AggregateFunction)
+}
+
+impl WindowFunction {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            WindowFunction::RowNumber => "row_number",
+            WindowFunction::Rank => "rank",
+            WindowFunction::DenseRank => "dense_rank",
+            WindowFunction::Lag => "lag",
+            WindowFunction::Lead => "lead",
+            WindowFunction::FirstValue => "first_value",
+            WindowFunction::LastValue => "last_value",
+            WindowFunction::Aggregate(function) => function.name()
+        }
+    }
+}
+
+// Rows are split into partitions by the PARTITION BY keys and ordered within
+// them. The frame selects the rows aggregates and FIRST_VALUE or LAST_VALUE
+// are computed over; without one it runs from the start of the partition to
+// the last row sorting equal to the current one.
+#[derive(Debug,Clone,PartialEq)]
+pub struct WindowSpec {
+    pub partition_by: Vec<Expr>,
+    pub order_by: Vec<OrderByItem>,
+    pub frame: Option<WindowFrame>
+}
+
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum FrameUnits {
+    // Offsets count rows
+    Rows,
+    // Offsets apply to the value of the single ORDER BY key, and rows sorting
+    // equal to the current one are all part of the frame
+    Range
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct WindowFrame {
+    pub units: FrameUnits,
+    pub start: FrameBound,
+    pub end: FrameBound
 }
 
 #[derive(Debug,Clone,PartialEq)]
-pub enum Comparator {
-    Equals
+pub enum FrameBound {
+    UnboundedPreceding,
+    Preceding(Box<Expr>),
+    CurrentRow,
+    Following(Box<Expr>),
+    UnboundedFollowing
 }
 
 #[derive(Debug,Clone,PartialEq)]
-pub enum SelectWhereFilter {
-    ColumnColumn(String,Comparator,String),
-    ColumnLiteral(String,Comparator,LiteralValue)
+pub enum Expr {
+    // A column name, optionally qualified by a table name or alias as in t.col
+    Column(String),
+    LiteralValue(LiteralValue),
+    Unary(UnaryOperator, Box<Expr>),
+    Binary(Box<Expr>, BinaryOperator, Box<Expr>),
+    IsNull {
+        expr: Box<Expr>,
+        negated: bool
+    },
+    Between {
+        expr: Box<Expr>,
+        negated: bool,
+        low: Box<Expr>,
+        high: Box<Expr>
+    },
+    InList {
+        expr: Box<Expr>,
+        negated: bool,
+        list: Vec<Expr>
+    },
+    Like {
+        expr: Box<Expr>,
+        negated: bool,
+        pattern: Box<Expr>
+    },
+    // An aggregate function call, the argument is None for COUNT(*)
+    Aggregate {
+        function: AggregateFunction,
+        distinct: bool,
+        arg: Option<Box<Expr>>
+    },
+    // A function computed over the rows the window relates to the current row
+    Window {
+        function: WindowFunction,
+        args: Vec<Expr>,
+        window: WindowSpec
+    },
+    // A query returning a single value
+    Subquery(Box<Query>),
+    InSubquery {
+        expr: Box<Expr>,
+        negated: bool,
+        query: Box<Query>
+    },
+    Exists(Box<Query>)
+}
+
+impl Expr {
+    pub fn binary(left: Expr, operator: BinaryOperator, right: Expr) -> Expr {
+        Expr::Binary(Box::new(left), operator, Box::new(right))
+    }
+
+    // Builds a left associative chain of binary operations
+    pub fn fold_binary(first: Expr, rest: Vec<(BinaryOperator, Expr)>) -> Expr {
+        rest.into_iter().fold(first, |left, (operator, right)| Expr::binary(left, operator, right))
+    }
+}
+
+// A predicate following an expression, used by the parser to avoid parsing
+// the expression once per kind of predicate
+#[derive(Debug,Clone,PartialEq)]
+pub enum PredicateSuffix {
+    Compare(BinaryOperator, Expr),
+    IsNull(bool),
+    Between(bool, Expr, Expr),
+    InList(bool, Vec<Expr>),
+    InSubquery(bool, Query),
+    Like(bool, Expr)
+}
+
+impl PredicateSuffix {
+    pub fn apply(self, expr: Expr) -> Expr {
+        let expr = Box::new(expr);
+        match self {
+            PredicateSuffix::Compare(operator, right) => Expr::Binary(expr, operator, Box::new(right)),
+            PredicateSuffix::IsNull(negated) => Expr::IsNull { expr: expr, negated: negated },
+            PredicateSuffix::Between(negated, low, high) => Expr::Between { expr: expr, negated: negated, low: Box::new(low), high: Box::new(high) },
+            PredicateSuffix::InList(negated, list) => Expr::InList { expr: expr, negated: negated, list: list },
+            PredicateSuffix::InSubquery(negated, query) => Expr::InSubquery { expr: expr, negated: negated, query: Box::new(query) },
+            PredicateSuffix::Like(negated, pattern) => Expr::Like { expr: expr, negated: negated, pattern: Box::new(pattern) }
+        }
+    }
 }
 
 #[derive(Debug,Clone,PartialEq)]
@@ -125,13 +665,34 @@ pub struct DropTableStmt {
 pub struct InsertStmt {
     pub table_name: String,
     pub column_names: Vec<String>,
-    pub column_values: Vec<LiteralValue>
+    pub column_values: Vec<Expr>
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct UpdateStmt {
+    pub table_name: String,
+    pub assignments: Vec<UpdateAssignment>,
+    pub filter: Option<Expr>
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct UpdateAssignment {
+    pub column_name: String,
+    pub value: Expr
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct DeleteStmt {
+    pub table_name: String,
+    pub filter: Option<Expr>
 }
 
 #[derive(Clone,PartialEq,Debug,Copy)]
 pub enum ColumnType {
     Text,
-    Bool
+    Bool,
+    Integer,
+    Real
 }
 
 impl ColumnType {
@@ -145,9 +706,33 @@ impl ColumnType {
             },
             &LiteralValue::Bool(_) => {
                 ColumnType::Bool
+            },
+            &LiteralValue::Integer(_) => {
+                ColumnType::Integer
+            },
+            &LiteralValue::Real(_) => {
+                ColumnType::Real
             }
         }
     }
+
+    // The narrowest type able to hold values of both types, if any
+    pub fn widen(self, other: ColumnType) -> Option<ColumnType> {
+        match (self, other) {
+            (a, b) if a == b => Some(a),
+            (ColumnType::Integer, ColumnType::Real) => Some(ColumnType::Real),
+            (ColumnType::Real, ColumnType::Integer) => Some(ColumnType::Real),
+            _ => None
+        }
+    }
+
+    // The type a column of this type must take to also store the literal
+    pub fn accomodate(self, val: &LiteralValue) -> Option<ColumnType> {
+        match val {
+            &LiteralValue::Null => Some(self),
+            _ => self.widen(ColumnType::accomodate_literal(val))
+        }
+    }
 }
 
 #[derive(Clone,PartialEq,Debug)]
@@ -2,33 +2,108 @@ use definitions::*;
 use plan::*;
 use schema::*;
 use ir::*;
+use cursor::*;
+use functions::TableFunction;
+use tables::{Table,MemoryTable};
+use storage::DatabaseFile;
+use std::rc::Rc;
+use std::path::Path;
 
 
 pub struct SqlEngine {
-    schema: Schema
+    schema: Schema,
+    options: PlanOptions,
+    // The database file the tables are saved to, None when kept in memory
+    file: Option<DatabaseFile>
 }
 
 
 impl SqlEngine {
 
     pub fn new() -> SqlEngine {
+        SqlEngine::with_options(PlanOptions::new())
+    }
+
+    pub fn with_options(options: PlanOptions) -> SqlEngine {
         SqlEngine {
-            schema: Schema::new()
+            schema: Schema::new(),
+            options: options,
+            file: None
+        }
+    }
+
+    // Opens an engine whose tables are saved to the given database file after
+    // every statement changing them. The file is created if it doesn't exist.
+    pub fn open<P: AsRef<Path>>(path: P) -> SqlError<SqlEngine> {
+        let (file, schema) = try!(DatabaseFile::open(path.as_ref()));
+        let mut engine = SqlEngine::new();
+        engine.schema = schema;
+        engine.file = Some(file);
+        Ok(engine)
+    }
+
+    fn save(&mut self) -> SqlError<()> {
+        match self.file {
+            Some(ref mut file) => file.save(&self.schema),
+            None => Ok(())
         }
     }
 
+    pub fn options_mut(&mut self) -> &mut PlanOptions {
+        &mut self.options
+    }
+
+    // Makes a table function callable in the FROM clause of queries, replacing
+    // any function of the same name
+    pub fn register_table_function<F: TableFunction + 'static>(&mut self, name: &str, function: F) {
+        self.schema.register_table_function(name, Rc::new(function));
+    }
+
     pub fn excecute_stmt(&mut self,stmt: SqlStmt) -> SqlError<SqlResult> {
+        let modifies_tables = match stmt {
+            SqlStmt::CreateTable(_) | SqlStmt::DropTable(_) | SqlStmt::Insert(_) |
+            SqlStmt::Update(_) | SqlStmt::Delete(_) => true,
+            _ => false
+        };
+        if !modifies_tables || self.file.is_none() {
+            return self.excecute_in_memory(stmt);
+        }
+        // The tables as they were are kept in a snapshot sharing them, so the
+        // statement copies the tables it changes. The changes are kept only if
+        // the whole statement succeeds and is saved, otherwise the snapshot is
+        // restored and the file keeps its previous content. Without a file
+        // nothing is snapshotted, so in-memory statements never copy tables.
+        let snapshot = self.schema.snapshot();
+        let mut result = self.excecute_in_memory(stmt);
+        if result.is_ok() {
+            if let Err(e) = self.save() {
+                result = Err(e);
+            }
+        }
+        if result.is_err() {
+            self.schema = snapshot;
+        }
+        result
+    }
+
+    fn excecute_in_memory(&mut self, stmt: SqlStmt) -> SqlError<SqlResult> {
         match stmt {
             SqlStmt::Select(sel) => {
                 Ok(SqlResult::Rows(try!(self.select(sel))))
             },
+            SqlStmt::SetOperation(operation) => {
+                Ok(SqlResult::Rows(try!(self.select(operation))))
+            },
+            SqlStmt::With(with) => {
+                Ok(SqlResult::Rows(try!(self.select(with))))
+            },
             SqlStmt::DumpTables => {
-                println!("{:?}",self.schema);
-                Ok(SqlResult::None)
+                Ok(SqlResult::Message(format!("{:?}",self.schema)))
             }
             SqlStmt::CreateTable(table) => {
+                let table_name = table.table_name.clone();
                 try!(self.create_table(table));
-                Ok(SqlResult::None)
+                Ok(SqlResult::Message(format!("Table {} created",table_name)))
             },
             SqlStmt::DropTable(table) => {
                 try!(self.drop_table(table));
@@ -38,28 +113,55 @@ impl SqlEngine {
                 try!(self.insert(insert));
                 Ok(SqlResult::None)
             },
+            SqlStmt::Update(update) => {
+                Ok(SqlResult::RowsUpdated(try!(self.update(update))))
+            },
+            SqlStmt::Delete(delete) => {
+                Ok(SqlResult::RowsUpdated(try!(self.delete(delete))))
+            },
         }
     }
 
-    fn select(&mut self,stmt: SelectStmt) -> SqlError<Vec<Vec<LiteralValue>>> {
-        let ir = try!(select_ir::ir_from_select_stmt(&stmt, &self.schema));
-        let mut plan = try!(select_plan::build_select_plan(&ir, &self.schema));
-        let row = try!(plan.get_next_row(&mut self.schema));
+    // Adds a table stored by the given backend, which can then be queried and
+    // modified like the tables created with SQL
+    pub fn register_table<T: Table + 'static>(&mut self, name: &str, table: T) -> SqlError<()> {
+        try!(self.schema.create_table(&name.to_lowercase(), Box::new(table)));
+        Ok(())
+    }
+
+    pub fn table_names(&self) -> Vec<String> {
+        self.schema.table_names()
+    }
+
+    pub fn table_columns(&self, table_name: &str) -> SqlError<Vec<ColumnDefinition>> {
+        let table_index = try!(self.schema.find_table_or_err(table_name));
+        self.schema.map_on_table(table_index, |table| Ok(table.columns().to_owned()))
+    }
 
-        println!("{:?}",&row);
-        Ok(vec!(row))
+    // Starts a query and returns a cursor over its rows. The cursor works on a
+    // snapshot of the tables so other statements can be run while it is open.
+    pub fn query<Q: Into<Query>>(&self, query: Q) -> SqlError<Cursor> {
+        let ir = try!(query_ir::ir_from_query(&query.into(), &self.schema));
+        let plan = try!(select_plan::build_query_plan(&ir, &self.schema, &self.options));
+        Ok(Cursor::new(ir.column_defs().to_vec(), self.schema.snapshot(), plan))
+    }
+
+    fn select<Q: Into<Query>>(&mut self, query: Q) -> SqlError<Vec<Vec<LiteralValue>>> {
+        let cursor = try!(self.query(query));
+        let mut rows = Vec::new();
+        for row in cursor {
+            rows.push(try!(row));
+        }
+
+        Ok(rows)
     }
 
     fn create_table(&mut self, stmt: CreateTableStmt) -> SqlError<()> {
-        let table_index = try!(self.schema.create_table(&stmt.table_name));
-        try!(self.schema.map_on_table_mut(table_index, |table|
-            {
-                for col in stmt.column_defs.iter() {
-                    table.add_column(col.clone());
-                }
-                Ok(())
-            }));
-        println!("Table {} created",stmt.table_name);
+        let mut table = MemoryTable::new();
+        for col in stmt.column_defs.iter() {
+            table.add_column(col.clone());
+        }
+        try!(self.schema.create_table(&stmt.table_name, Box::new(table)));
         Ok(())
     }
 
@@ -70,9 +172,21 @@ impl SqlEngine {
 
     fn insert(&mut self,stmt: InsertStmt) -> SqlError<()> {
         let ir = try!(insert_ir::ir_from_insert_stmt(&stmt, &self.schema));
-        let mut plan = try!(insert_plan::build_insert_plan(&ir, &self.schema));
+        let mut plan = try!(insert_plan::build_insert_plan(&ir, &self.schema, &self.options));
         let _ = try!(plan.run(&mut self.schema));
 
         Ok(())
     }
+
+    fn update(&mut self,stmt: UpdateStmt) -> SqlError<usize> {
+        let ir = try!(update_ir::ir_from_update_stmt(&stmt, &self.schema));
+        let mut plan = try!(update_plan::build_update_plan(&ir, &self.schema, &self.options));
+        plan.run(&mut self.schema)
+    }
+
+    fn delete(&mut self,stmt: DeleteStmt) -> SqlError<usize> {
+        let ir = try!(delete_ir::ir_from_delete_stmt(&stmt, &self.schema));
+        let mut plan = try!(delete_plan::build_delete_plan(&ir, &self.schema, &self.options));
+        plan.run(&mut self.schema)
+    }
 }
@@ -0,0 +1,138 @@
+use std::fmt;
+use std::error;
+
+#[derive(Debug,Clone,PartialEq)]
+pub enum Error {
+    // The statement text could not be parsed
+    Parse {
+        line: usize,
+        column: usize,
+        offset: usize,
+        expected: Vec<String>
+    },
+    // The statement parsed but is not valid, e.g. mismatched column and value counts
+    Syntax(String),
+    UnknownTable(String),
+    UnknownColumn(String),
+    AmbiguousColumn(String),
+    DuplicateTable(String),
+    DuplicateColumn(String),
+    DuplicateAlias(String),
+    UnknownFunction(String),
+    Constraint(String),
+    Type(String),
+    // A column is used outside of an aggregate without being grouped on,
+    // or an aggregate is used where it is not allowed
+    Grouping(String),
+    // A window function is used where it is not allowed or its window is invalid
+    Windowing(String),
+    // A subquery used as a value returned more than one row
+    Cardinality(String),
+    // A recursive query ran its recursive term more times than allowed
+    RecursionLimit(String),
+    DivisionByZero,
+    NumericOverflow(String),
+    // A LIMIT or OFFSET evaluated to a negative row count
+    InvalidRowCount(String),
+    // A value could not be parsed, e.g. malformed JSON or CSV
+    InvalidInput(String),
+    // Reading or writing a file failed
+    Io(String),
+    // A database file failed its checks
+    Corrupted(String),
+    Unsupported(String),
+    Internal(String)
+}
+
+impl Error {
+    // SQLSTATE-like code identifying the class of error
+    pub fn code(&self) -> &'static str {
+        match *self {
+            Error::Parse{..} => "42601",
+            Error::Syntax(_) => "42601",
+            Error::UnknownTable(_) => "42P01",
+            Error::UnknownColumn(_) => "42703",
+            Error::AmbiguousColumn(_) => "42702",
+            Error::DuplicateTable(_) => "42P07",
+            Error::DuplicateColumn(_) => "42701",
+            Error::DuplicateAlias(_) => "42712",
+            Error::UnknownFunction(_) => "42883",
+            Error::Constraint(_) => "23000",
+            Error::Type(_) => "42804",
+            Error::Grouping(_) => "42803",
+            Error::Windowing(_) => "42P20",
+            Error::Cardinality(_) => "21000",
+            Error::RecursionLimit(_) => "54001",
+            Error::DivisionByZero => "22012",
+            Error::NumericOverflow(_) => "22003",
+            Error::InvalidRowCount(_) => "2201W",
+            Error::InvalidInput(_) => "22P02",
+            Error::Io(_) => "58030",
+            Error::Corrupted(_) => "XX001",
+            Error::Unsupported(_) => "0A000",
+            Error::Internal(_) => "XX000"
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Parse{line, column, ref expected, ..} => {
+                write!(f, "Parse error at line {}, column {}: expected one of {}", line, column, expected.join(", "))
+            },
+            Error::Syntax(ref msg) => write!(f, "{}", msg),
+            Error::UnknownTable(ref name) => write!(f, "Table {} doesn't exist", name),
+            Error::UnknownColumn(ref name) => write!(f, "Column {} not found", name),
+            Error::AmbiguousColumn(ref name) => write!(f, "Ambiguous column {}", name),
+            Error::DuplicateTable(ref name) => write!(f, "Table {} already exists", name),
+            Error::DuplicateColumn(ref name) => write!(f, "Duplicated column {}", name),
+            Error::DuplicateAlias(ref name) => write!(f, "Table name {} specified more than once", name),
+            Error::UnknownFunction(ref name) => write!(f, "Function {} doesn't exist", name),
+            Error::Constraint(ref msg) => write!(f, "{}", msg),
+            Error::Type(ref msg) => write!(f, "{}", msg),
+            Error::Grouping(ref msg) => write!(f, "{}", msg),
+            Error::Windowing(ref msg) => write!(f, "{}", msg),
+            Error::Cardinality(ref msg) => write!(f, "{}", msg),
+            Error::RecursionLimit(ref msg) => write!(f, "{}", msg),
+            Error::DivisionByZero => write!(f, "Division by zero"),
+            Error::NumericOverflow(ref msg) => write!(f, "Numeric overflow in {}", msg),
+            Error::InvalidRowCount(ref msg) => write!(f, "{}", msg),
+            Error::InvalidInput(ref msg) => write!(f, "{}", msg),
+            Error::Io(ref msg) => write!(f, "I/O error: {}", msg),
+            Error::Corrupted(ref msg) => write!(f, "Corrupted database file: {}", msg),
+            Error::Unsupported(ref msg) => write!(f, "{} not implemented", msg),
+            Error::Internal(ref msg) => write!(f, "Internal Error: {}", msg)
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::Parse{..} => "parse error",
+            Error::Syntax(_) => "invalid statement",
+            Error::UnknownTable(_) => "unknown table",
+            Error::UnknownColumn(_) => "unknown column",
+            Error::AmbiguousColumn(_) => "ambiguous column",
+            Error::DuplicateTable(_) => "duplicate table",
+            Error::DuplicateColumn(_) => "duplicate column",
+            Error::DuplicateAlias(_) => "duplicate table alias",
+            Error::UnknownFunction(_) => "unknown function",
+            Error::Constraint(_) => "constraint violation",
+            Error::Type(_) => "type error",
+            Error::Grouping(_) => "grouping error",
+            Error::Windowing(_) => "windowing error",
+            Error::Cardinality(_) => "cardinality violation",
+            Error::RecursionLimit(_) => "recursion limit exceeded",
+            Error::DivisionByZero => "division by zero",
+            Error::NumericOverflow(_) => "numeric overflow",
+            Error::InvalidRowCount(_) => "invalid row count",
+            Error::InvalidInput(_) => "invalid input syntax",
+            Error::Io(_) => "i/o error",
+            Error::Corrupted(_) => "data corrupted",
+            Error::Unsupported(_) => "unsupported feature",
+            Error::Internal(_) => "internal error"
+        }
+    }
+}
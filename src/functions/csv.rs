@@ -0,0 +1,271 @@
+use definitions::*;
+use std::fs::File;
+use std::io::{BufReader,Bytes,Read};
+use super::{TableFunction,FunctionRows,check_arg_count,text_arg,bool_arg};
+
+// csv_table(path, has_header) reads a CSV file. Every column is Text, named
+// by the header line when there is one and column1, column2... otherwise.
+// Fields are separated by commas and can be quoted to hold commas, quotes
+// written twice or line breaks. An empty unquoted field is NULL.
+pub struct CsvTable;
+
+impl CsvTable {
+    // Opens the file and reads its first record, along with whether it is
+    // the header
+    fn open(&self, args: &[LiteralValue]) -> SqlError<(CsvReader<BufReader<File>>, Vec<Option<String>>, bool)> {
+        try!(check_arg_count("csv_table", args, 2, 2));
+        let path = match try!(text_arg("csv_table", args, 0)) {
+            Some(path) => path,
+            None => {
+                return Err(Error::Syntax("csv_table path cannot be NULL".to_string()));
+            }
+        };
+        let has_header = try!(bool_arg("csv_table", args, 1)).unwrap_or(false);
+
+        let file = try!(File::open(path).map_err(|err| Error::Io(format!("Cannot read {}: {}", path, err))));
+        let mut reader = CsvReader::new(BufReader::new(file), path);
+        match try!(reader.record()) {
+            Some(record) => Ok((reader, record, has_header)),
+            None => Err(Error::InvalidInput(format!("CSV file {} is empty", path)))
+        }
+    }
+}
+
+impl TableFunction for CsvTable {
+    fn columns(&self, args: &[LiteralValue]) -> SqlError<Vec<ColumnDefinition>> {
+        let (_, first, has_header) = try!(self.open(args));
+        let mut columns: Vec<ColumnDefinition> = Vec::new();
+        for (i, field) in first.into_iter().enumerate() {
+            let name = match field {
+                Some(ref name) if has_header => name.to_lowercase(),
+                _ => format!("column{}", i + 1)
+            };
+            if columns.iter().any(|c| c.name == name) {
+                return Err(Error::DuplicateColumn(name));
+            }
+            columns.push(ColumnDefinition {
+                name: name,
+                ctype: ColumnType::Text
+            });
+        }
+        Ok(columns)
+    }
+
+    fn rows(&self, args: &[LiteralValue]) -> SqlError<FunctionRows> {
+        let (reader, first, has_header) = try!(self.open(args));
+        Ok(Box::new(CsvRows {
+            reader: reader,
+            width: first.len(),
+            first: if has_header { None } else { Some(first) },
+            done: false
+        }))
+    }
+}
+
+// The records following the first one, read from the file as they are needed
+struct CsvRows {
+    reader: CsvReader<BufReader<File>>,
+    width: usize,
+    // The first record when it is not the header
+    first: Option<Vec<Option<String>>>,
+    done: bool
+}
+
+impl Iterator for CsvRows {
+    type Item = SqlError<Vec<LiteralValue>>;
+
+    fn next(&mut self) -> Option<SqlError<Vec<LiteralValue>>> {
+        if self.done {
+            return None;
+        }
+        let record = match self.first.take() {
+            Some(record) => record,
+            None => {
+                match self.reader.record() {
+                    Ok(Some(record)) => record,
+                    Ok(None) => {
+                        self.done = true;
+                        return None;
+                    },
+                    Err(e) => {
+                        self.done = true;
+                        return Some(Err(e));
+                    }
+                }
+            }
+        };
+        if record.len() != self.width {
+            self.done = true;
+            return Some(Err(Error::InvalidInput(format!("Record {} of {} has {} fields, expected {}",
+                                                        self.reader.records, self.reader.name, record.len(), self.width))));
+        }
+        Some(Ok(record.into_iter().map(|field| match field {
+            Some(text) => LiteralValue::Text(text),
+            None => LiteralValue::Null
+        }).collect()))
+    }
+}
+
+// Splits CSV text into records one at a time. A line break ending the last
+// record is optional, \r\n line breaks are accepted and blank lines at the
+// end are ignored.
+struct CsvReader<R: Read> {
+    bytes: Bytes<R>,
+    peeked: Option<u8>,
+    // The name of the input in error messages
+    name: String,
+    // The number of records read so far
+    records: usize,
+    // Blank lines read before the next record, each an empty record
+    blank_lines: usize
+}
+
+impl<R: Read> CsvReader<R> {
+    fn new(input: R, name: &str) -> CsvReader<R> {
+        CsvReader {
+            bytes: input.bytes(),
+            peeked: None,
+            name: name.to_string(),
+            records: 0,
+            blank_lines: 0
+        }
+    }
+
+    fn peek(&mut self) -> SqlError<Option<u8>> {
+        if self.peeked.is_none() {
+            self.peeked = match self.bytes.next() {
+                Some(Ok(byte)) => Some(byte),
+                Some(Err(err)) => {
+                    return Err(Error::Io(format!("Cannot read {}: {}", self.name, err)));
+                },
+                None => None
+            };
+        }
+        Ok(self.peeked)
+    }
+
+    fn next(&mut self) -> SqlError<Option<u8>> {
+        let byte = try!(self.peek());
+        self.peeked = None;
+        Ok(byte)
+    }
+
+    // Reads a line break, returning false if the input is not at one
+    fn line_break(&mut self) -> SqlError<bool> {
+        match try!(self.peek()) {
+            Some(b'\n') => {
+                self.peeked = None;
+                Ok(true)
+            },
+            Some(b'\r') => {
+                self.peeked = None;
+                if try!(self.peek()) == Some(b'\n') {
+                    self.peeked = None;
+                }
+                Ok(true)
+            },
+            _ => Ok(false)
+        }
+    }
+
+    fn invalid(&self, message: &str) -> Error {
+        Error::InvalidInput(format!("{} in record {} of {}", message, self.records + 1, self.name))
+    }
+
+    // Reads the next record, None at the end of the input
+    fn record(&mut self) -> SqlError<Option<Vec<Option<String>>>> {
+        while try!(self.line_break()) {
+            self.blank_lines += 1;
+        }
+        if try!(self.peek()).is_none() {
+            return Ok(None);
+        }
+        self.records += 1;
+        if self.blank_lines > 0 {
+            self.blank_lines -= 1;
+            return Ok(Some(vec![None]));
+        }
+
+        let mut record = Vec::new();
+        loop {
+            // Reads a field, then the separator or line break following it
+            let mut field = Vec::new();
+            let mut quoted = false;
+            if try!(self.peek()) == Some(b'"') {
+                self.peeked = None;
+                quoted = true;
+                loop {
+                    match try!(self.next()) {
+                        Some(b'"') => {
+                            if try!(self.peek()) == Some(b'"') {
+                                self.peeked = None;
+                                field.push(b'"');
+                            }
+                            else {
+                                break;
+                            }
+                        },
+                        Some(byte) => field.push(byte),
+                        None => {
+                            return Err(self.invalid("Unterminated quoted field"));
+                        }
+                    }
+                }
+            }
+            else {
+                while let Some(byte) = try!(self.peek()) {
+                    if byte == b',' || byte == b'\n' || byte == b'\r' {
+                        break;
+                    }
+                    field.push(byte);
+                    self.peeked = None;
+                }
+            }
+            let field = match String::from_utf8(field) {
+                Ok(field) => field,
+                Err(_) => {
+                    return Err(self.invalid("Invalid UTF-8"));
+                }
+            };
+            record.push(if field.is_empty() && !quoted { None } else { Some(field) });
+
+            if try!(self.peek()) == Some(b',') {
+                self.peeked = None;
+            }
+            else if try!(self.line_break()) || try!(self.peek()).is_none() {
+                return Ok(Some(record));
+            }
+            else {
+                let byte = try!(self.peek()).unwrap();
+                return Err(self.invalid(&format!("Unexpected {:?} after quoted field", byte as char)));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use definitions::*;
+    use super::CsvReader;
+
+    fn parse_csv(text: &str) -> SqlError<Vec<Vec<Option<String>>>> {
+        let mut reader = CsvReader::new(text.as_bytes(), "text");
+        let mut records = Vec::new();
+        while let Some(record) = try!(reader.record()) {
+            records.push(record);
+        }
+        Ok(records)
+    }
+
+    #[test]
+    fn records() {
+        let text = |s: &str| Some(s.to_string());
+        assert_eq!(parse_csv("a,b\r\n\"c,\"\"d\"\"\",\n").unwrap(), vec![vec![text("a"), text("b")], vec![text("c,\"d\""), None]]);
+        assert_eq!(parse_csv("a,b\n\n\r\n").unwrap(), vec![vec![text("a"), text("b")]]);
+        assert_eq!(parse_csv("a\n\nb").unwrap(), vec![vec![text("a")], vec![None], vec![text("b")]]);
+        assert_eq!(parse_csv("\"a\nb\"").unwrap(), vec![vec![text("a\nb")]]);
+        assert_eq!(parse_csv("").unwrap(), Vec::<Vec<Option<String>>>::new());
+        assert!(parse_csv("\"a").is_err());
+        assert!(parse_csv("\"a\"b").is_err());
+    }
+}
@@ -0,0 +1,362 @@
+use definitions::*;
+use std::char;
+use std::iter::Peekable;
+use std::str::Chars;
+use super::{TableFunction,FunctionRows,check_arg_count,text_arg};
+
+// json_each(text) returns a row for each member of a JSON object, or each
+// element of a JSON array, with its key and value. Array keys are the element
+// positions from 0. String values are returned unquoted, null as NULL and
+// other values as JSON text. A NULL argument returns no rows.
+pub struct JsonEach;
+
+impl JsonEach {
+    fn members(&self, args: &[LiteralValue]) -> SqlError<Vec<(String, JsonValue)>> {
+        try!(check_arg_count("json_each", args, 1, 1));
+        let text = match try!(text_arg("json_each", args, 0)) {
+            Some(text) => text,
+            None => {
+                return Ok(Vec::new());
+            }
+        };
+        match try!(parse_json(text)) {
+            JsonValue::Object(members) => Ok(members),
+            JsonValue::Array(elements) => {
+                Ok(elements.into_iter().enumerate().map(|(i, element)| (i.to_string(), element)).collect())
+            },
+            _ => Err(Error::InvalidInput("json_each expects a JSON object or array".to_string()))
+        }
+    }
+}
+
+impl TableFunction for JsonEach {
+    // The columns are the same for any text, which is only parsed once the
+    // rows are read
+    fn columns(&self, args: &[LiteralValue]) -> SqlError<Vec<ColumnDefinition>> {
+        try!(check_arg_count("json_each", args, 1, 1));
+        try!(text_arg("json_each", args, 0));
+        Ok(vec![
+            ColumnDefinition {
+                name: "key".to_string(),
+                ctype: ColumnType::Text
+            },
+            ColumnDefinition {
+                name: "value".to_string(),
+                ctype: ColumnType::Text
+            }
+        ])
+    }
+
+    fn rows(&self, args: &[LiteralValue]) -> SqlError<FunctionRows> {
+        let members = try!(self.members(args));
+        Ok(Box::new(members.into_iter().map(|(key, value)| -> SqlError<Vec<LiteralValue>> {
+            let value = match value {
+                JsonValue::Null => LiteralValue::Null,
+                JsonValue::String(text) => LiteralValue::Text(text),
+                value => LiteralValue::Text(value.to_json())
+            };
+            Ok(vec![LiteralValue::Text(key), value])
+        })))
+    }
+}
+
+#[derive(Debug,Clone,PartialEq)]
+enum JsonValue {
+    Null,
+    Bool(bool),
+    // Numbers keep their text so they are returned as written
+    Number(String),
+    String(String),
+    Array(Vec<JsonValue>),
+    Object(Vec<(String, JsonValue)>)
+}
+
+impl JsonValue {
+    // The value as compact JSON text
+    fn to_json(&self) -> String {
+        match *self {
+            JsonValue::Null => "null".to_string(),
+            JsonValue::Bool(b) => b.to_string(),
+            JsonValue::Number(ref number) => number.clone(),
+            JsonValue::String(ref text) => quote(text),
+            JsonValue::Array(ref elements) => {
+                let elements: Vec<String> = elements.iter().map(|e| e.to_json()).collect();
+                format!("[{}]", elements.join(","))
+            },
+            JsonValue::Object(ref members) => {
+                let members: Vec<String> = members.iter().map(|&(ref key, ref value)| format!("{}:{}", quote(key), value.to_json())).collect();
+                format!("{{{}}}", members.join(","))
+            }
+        }
+    }
+}
+
+fn quote(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c)
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+// The number of arrays and objects a value can be nested in, parsing them
+// recursively
+const MAX_DEPTH: usize = 512;
+
+fn invalid(message: &str) -> Error {
+    Error::InvalidInput(format!("Invalid JSON: {}", message))
+}
+
+fn parse_json(text: &str) -> SqlError<JsonValue> {
+    let mut parser = JsonParser {
+        chars: text.chars().peekable(),
+        depth: 0
+    };
+    let value = try!(parser.value());
+    parser.skip_whitespace();
+    if parser.chars.peek().is_some() {
+        return Err(invalid("unexpected text after the value"));
+    }
+    Ok(value)
+}
+
+struct JsonParser<'a> {
+    chars: Peekable<Chars<'a>>,
+    // The number of arrays and objects the current value is in
+    depth: usize
+}
+
+impl<'a> JsonParser<'a> {
+    fn skip_whitespace(&mut self) {
+        while let Some(&c) = self.chars.peek() {
+            if c != ' ' && c != '\t' && c != '\n' && c != '\r' {
+                break;
+            }
+            self.chars.next();
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> SqlError<()> {
+        self.skip_whitespace();
+        match self.chars.next() {
+            Some(c) if c == expected => Ok(()),
+            _ => Err(invalid(&format!("expected {:?}", expected)))
+        }
+    }
+
+    // Consumes the given keyword, whose first character was already read
+    fn keyword(&mut self, rest: &str, value: JsonValue) -> SqlError<JsonValue> {
+        for expected in rest.chars() {
+            if self.chars.next() != Some(expected) {
+                return Err(invalid("unknown keyword"));
+            }
+        }
+        Ok(value)
+    }
+
+    fn value(&mut self) -> SqlError<JsonValue> {
+        self.skip_whitespace();
+        match self.chars.next() {
+            Some('n') => self.keyword("ull", JsonValue::Null),
+            Some('t') => self.keyword("rue", JsonValue::Bool(true)),
+            Some('f') => self.keyword("alse", JsonValue::Bool(false)),
+            Some('"') => Ok(JsonValue::String(try!(self.string()))),
+            Some(c) if c == '[' || c == '{' => {
+                if self.depth == MAX_DEPTH {
+                    return Err(invalid(&format!("values are nested more than {} levels deep", MAX_DEPTH)));
+                }
+                self.depth += 1;
+                let value = if c == '[' { self.array() } else { self.object() };
+                self.depth -= 1;
+                value
+            },
+            Some(c) if c == '-' || c.is_digit(10) => self.number(c),
+            Some(c) => Err(invalid(&format!("unexpected {:?}", c))),
+            None => Err(invalid("unexpected end of text"))
+        }
+    }
+
+    // Reads an array whose opening bracket was already read
+    fn array(&mut self) -> SqlError<JsonValue> {
+        let mut elements = Vec::new();
+        self.skip_whitespace();
+        if self.chars.peek() == Some(&']') {
+            self.chars.next();
+            return Ok(JsonValue::Array(elements));
+        }
+        loop {
+            elements.push(try!(self.value()));
+            self.skip_whitespace();
+            match self.chars.next() {
+                Some(',') => {},
+                Some(']') => {
+                    return Ok(JsonValue::Array(elements));
+                },
+                _ => {
+                    return Err(invalid("expected ',' or ']' in array"));
+                }
+            }
+        }
+    }
+
+    // Reads an object whose opening brace was already read
+    fn object(&mut self) -> SqlError<JsonValue> {
+        let mut members = Vec::new();
+        self.skip_whitespace();
+        if self.chars.peek() == Some(&'}') {
+            self.chars.next();
+            return Ok(JsonValue::Object(members));
+        }
+        loop {
+            try!(self.expect('"'));
+            let key = try!(self.string());
+            try!(self.expect(':'));
+            members.push((key, try!(self.value())));
+            self.skip_whitespace();
+            match self.chars.next() {
+                Some(',') => {},
+                Some('}') => {
+                    return Ok(JsonValue::Object(members));
+                },
+                _ => {
+                    return Err(invalid("expected ',' or '}' in object"));
+                }
+            }
+        }
+    }
+
+    // Reads the digits following a number's first character
+    fn digits(&mut self, number: &mut String) -> usize {
+        let mut count = 0;
+        while let Some(&c) = self.chars.peek() {
+            if !c.is_digit(10) {
+                break;
+            }
+            number.push(c);
+            self.chars.next();
+            count += 1;
+        }
+        count
+    }
+
+    fn number(&mut self, first: char) -> SqlError<JsonValue> {
+        let mut number = String::new();
+        number.push(first);
+        let leading = if first == '-' {
+            match self.chars.next() {
+                Some(c) if c.is_digit(10) => {
+                    number.push(c);
+                    c
+                },
+                _ => {
+                    return Err(invalid("expected a digit after '-'"));
+                }
+            }
+        }
+        else {
+            first
+        };
+        // Only 0 can start with a 0
+        if leading != '0' {
+            self.digits(&mut number);
+        }
+        if self.chars.peek() == Some(&'.') {
+            self.chars.next();
+            number.push('.');
+            if self.digits(&mut number) == 0 {
+                return Err(invalid("expected a digit after '.'"));
+            }
+        }
+        if self.chars.peek() == Some(&'e') || self.chars.peek() == Some(&'E') {
+            number.push(self.chars.next().unwrap());
+            if self.chars.peek() == Some(&'+') || self.chars.peek() == Some(&'-') {
+                number.push(self.chars.next().unwrap());
+            }
+            if self.digits(&mut number) == 0 {
+                return Err(invalid("expected a digit in exponent"));
+            }
+        }
+        Ok(JsonValue::Number(number))
+    }
+
+    fn hex_escape(&mut self) -> SqlError<u32> {
+        let mut code = 0;
+        for _ in 0..4 {
+            match self.chars.next().and_then(|c| c.to_digit(16)) {
+                Some(digit) => {
+                    code = code * 16 + digit;
+                },
+                None => {
+                    return Err(invalid("expected 4 hexadecimal digits after \\u"));
+                }
+            }
+        }
+        Ok(code)
+    }
+
+    // Reads a string whose opening quote was already read
+    fn string(&mut self) -> SqlError<String> {
+        let mut text = String::new();
+        loop {
+            match self.chars.next() {
+                Some('"') => {
+                    return Ok(text);
+                },
+                Some('\\') => {
+                    let c = match self.chars.next() {
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('/') => '/',
+                        Some('b') => '\u{8}',
+                        Some('f') => '\u{c}',
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some('u') => {
+                            let mut code = try!(self.hex_escape());
+                            // Characters outside the basic plane are escaped as
+                            // a surrogate pair
+                            if code >= 0xD800 && code < 0xDC00 {
+                                if self.chars.next() != Some('\\') || self.chars.next() != Some('u') {
+                                    return Err(invalid("expected a low surrogate"));
+                                }
+                                let low = try!(self.hex_escape());
+                                if low < 0xDC00 || low >= 0xE000 {
+                                    return Err(invalid("expected a low surrogate"));
+                                }
+                                code = 0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00);
+                            }
+                            match char::from_u32(code) {
+                                Some(c) => c,
+                                None => {
+                                    return Err(invalid("escaped character is not valid"));
+                                }
+                            }
+                        },
+                        _ => {
+                            return Err(invalid("unknown escape sequence"));
+                        }
+                    };
+                    text.push(c);
+                },
+                Some(c) if (c as u32) < 0x20 => {
+                    return Err(invalid("control character in string"));
+                },
+                Some(c) => text.push(c),
+                None => {
+                    return Err(invalid("unterminated string"));
+                }
+            }
+        }
+    }
+}
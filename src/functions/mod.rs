@@ -0,0 +1,167 @@
+use definitions::*;
+use std::fmt;
+use std::rc::Rc;
+
+mod csv;
+mod json;
+
+// The rows returned by a table function, read one at a time
+pub type FunctionRows = Box<Iterator<Item=SqlError<Vec<LiteralValue>>>>;
+
+// A function read like a table in the FROM clause. Its arguments are
+// constants, so its columns are known before any row is read.
+pub trait TableFunction {
+    // The columns of the rows returned for the given arguments. Invalid
+    // arguments are reported here.
+    fn columns(&self, args: &[LiteralValue]) -> SqlError<Vec<ColumnDefinition>>;
+
+    // The rows returned for the given arguments, which must match the columns
+    fn rows(&self, args: &[LiteralValue]) -> SqlError<FunctionRows>;
+}
+
+// Table functions by name, starting with the built-in ones. Functions are
+// shared by every copy of the schema.
+#[derive(Clone)]
+pub struct TableFunctions {
+    functions: Vec<(String, Rc<TableFunction>)>
+}
+
+impl TableFunctions {
+    pub fn new() -> TableFunctions {
+        let mut functions = TableFunctions {
+            functions: Vec::new()
+        };
+        functions.register("csv_table", Rc::new(csv::CsvTable));
+        functions.register("generate_series", Rc::new(GenerateSeries));
+        functions.register("json_each", Rc::new(json::JsonEach));
+        functions
+    }
+
+    // Registers a function, replacing any function of the same name
+    pub fn register(&mut self, name: &str, function: Rc<TableFunction>) {
+        let name = name.to_lowercase();
+        match self.functions.iter().position(|&(ref n, _)| *n == name) {
+            Some(index) => {
+                self.functions[index].1 = function;
+            },
+            None => {
+                self.functions.push((name, function));
+            }
+        }
+    }
+
+    pub fn find(&self, name: &str) -> Option<Rc<TableFunction>> {
+        self.functions.iter().find(|&&(ref n, _)| n == name).map(|&(_, ref function)| function.clone())
+    }
+
+    pub fn find_or_err(&self, name: &str) -> SqlError<Rc<TableFunction>> {
+        self.find(name).ok_or(Error::UnknownFunction(name.to_string()))
+    }
+}
+
+impl fmt::Debug for TableFunctions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let names: Vec<&str> = self.functions.iter().map(|&(ref name, _)| &name[..]).collect();
+        write!(f, "TableFunctions {:?}", names)
+    }
+}
+
+// Checks the number of arguments given to a function
+pub fn check_arg_count(function: &str, args: &[LiteralValue], min: usize, max: usize) -> SqlError<()> {
+    if args.len() < min || args.len() > max {
+        if min == max {
+            return Err(Error::Syntax(format!("{} expects {} arguments, got {}", function, min, args.len())));
+        }
+        return Err(Error::Syntax(format!("{} expects {} to {} arguments, got {}", function, min, max, args.len())));
+    }
+    Ok(())
+}
+
+// The argument as a string, None if it is NULL
+pub fn text_arg<'a>(function: &str, args: &'a [LiteralValue], index: usize) -> SqlError<Option<&'a str>> {
+    match args[index] {
+        LiteralValue::Null => Ok(None),
+        LiteralValue::Text(ref text) => Ok(Some(&text[..])),
+        ref value => Err(Error::Type(format!("Argument {} of {} must be Text, got {:?}", index + 1, function, value)))
+    }
+}
+
+// The argument as an integer, None if it is NULL
+pub fn integer_arg(function: &str, args: &[LiteralValue], index: usize) -> SqlError<Option<i64>> {
+    match args[index] {
+        LiteralValue::Null => Ok(None),
+        LiteralValue::Integer(i) => Ok(Some(i)),
+        ref value => Err(Error::Type(format!("Argument {} of {} must be an Integer, got {:?}", index + 1, function, value)))
+    }
+}
+
+// The argument as a boolean, None if it is NULL
+pub fn bool_arg(function: &str, args: &[LiteralValue], index: usize) -> SqlError<Option<bool>> {
+    match args[index] {
+        LiteralValue::Null => Ok(None),
+        LiteralValue::Bool(b) => Ok(Some(b)),
+        ref value => Err(Error::Type(format!("Argument {} of {} must be a Bool, got {:?}", index + 1, function, value)))
+    }
+}
+
+// generate_series(start, stop[, step]) returns the integers from start up to
+// stop, or down to it when step is negative. A NULL argument returns no rows.
+struct GenerateSeries;
+
+impl GenerateSeries {
+    // The first value, the last one and the step, None if an argument is NULL
+    fn bounds(&self, args: &[LiteralValue]) -> SqlError<Option<(i64, i64, i64)>> {
+        try!(check_arg_count("generate_series", args, 2, 3));
+        let start = try!(integer_arg("generate_series", args, 0));
+        let stop = try!(integer_arg("generate_series", args, 1));
+        let step = if args.len() == 3 { try!(integer_arg("generate_series", args, 2)) } else { Some(1) };
+        if step == Some(0) {
+            return Err(Error::Syntax("generate_series step cannot be 0".to_string()));
+        }
+        Ok(match (start, stop, step) {
+            (Some(start), Some(stop), Some(step)) => Some((start, stop, step)),
+            _ => None
+        })
+    }
+}
+
+impl TableFunction for GenerateSeries {
+    fn columns(&self, args: &[LiteralValue]) -> SqlError<Vec<ColumnDefinition>> {
+        try!(self.bounds(args));
+        Ok(vec![ColumnDefinition {
+            name: "generate_series".to_string(),
+            ctype: ColumnType::Integer
+        }])
+    }
+
+    fn rows(&self, args: &[LiteralValue]) -> SqlError<FunctionRows> {
+        Ok(Box::new(Series {
+            next: try!(self.bounds(args))
+        }))
+    }
+}
+
+struct Series {
+    // The next value along with the last one and the step, None once done
+    next: Option<(i64, i64, i64)>
+}
+
+impl Iterator for Series {
+    type Item = SqlError<Vec<LiteralValue>>;
+
+    fn next(&mut self) -> Option<SqlError<Vec<LiteralValue>>> {
+        let (value, stop, step) = match self.next {
+            Some(next) => next,
+            None => {
+                return None;
+            }
+        };
+        if (step > 0 && value > stop) || (step < 0 && value < stop) {
+            self.next = None;
+            return None;
+        }
+        // Stops instead of overflowing past the last representable value
+        self.next = value.checked_add(step).map(|next| (next, stop, step));
+        Some(Ok(vec![LiteralValue::Integer(value)]))
+    }
+}
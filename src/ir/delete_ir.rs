@@ -0,0 +1,19 @@
+use definitions::*;
+use schema::*;
+use super::{TableRef,ExprIr,resolve_table_reference};
+use super::expr_ir::resolve_conjuncts;
+
+pub struct DeleteIr {
+    pub table: TableRef,
+    pub filters: Vec<ExprIr>
+}
+
+pub fn ir_from_delete_stmt(stmt: &DeleteStmt, schema: &Schema) -> SqlError<DeleteIr> {
+    let table_ref = try!(resolve_table_reference(&stmt.table_name, 0, schema));
+    let filters = try!(resolve_conjuncts(&stmt.filter, &[table_ref.clone()], schema));
+
+    Ok(DeleteIr {
+        table: table_ref,
+        filters: filters
+    })
+}
@@ -0,0 +1,469 @@
+use definitions::*;
+use schema::*;
+use super::{TableRef,ColumnRef,resolve_single_column,column_definition};
+use super::query_ir::{SubqueryIr,resolve_subquery};
+use super::select_ir::{WindowIr,resolve_window};
+
+// An expression whose column names have been resolved to column references
+#[derive(Debug,Clone,PartialEq)]
+pub enum ExprIr {
+    Column(ColumnRef),
+    LiteralValue(LiteralValue),
+    Unary(UnaryOperator, Box<ExprIr>),
+    Binary(Box<ExprIr>, BinaryOperator, Box<ExprIr>),
+    IsNull {
+        expr: Box<ExprIr>,
+        negated: bool
+    },
+    Between {
+        expr: Box<ExprIr>,
+        negated: bool,
+        low: Box<ExprIr>,
+        high: Box<ExprIr>
+    },
+    InList {
+        expr: Box<ExprIr>,
+        negated: bool,
+        list: Vec<ExprIr>
+    },
+    Like {
+        expr: Box<ExprIr>,
+        negated: bool,
+        pattern: Box<ExprIr>
+    },
+    Aggregate(Box<AggregateIr>),
+    Window(Box<WindowIr>),
+    // A value of the rows produced by an earlier step of the query rather than
+    // of the tables, such as the aggregated rows made of the group keys
+    // followed by the aggregate values
+    Position(usize),
+    // A value of the enclosing query a subquery is run with
+    Parameter(usize),
+    // A subquery producing a single value
+    Subquery(Box<SubqueryIr>),
+    InSubquery {
+        expr: Box<ExprIr>,
+        negated: bool,
+        subquery: Box<SubqueryIr>
+    },
+    Exists(Box<SubqueryIr>)
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct AggregateIr {
+    pub function: AggregateFunction,
+    pub distinct: bool,
+    // None for COUNT(*)
+    pub arg: Option<ExprIr>
+}
+
+impl ExprIr {
+    // The expressions this expression is directly made of
+    pub fn children(&self) -> Vec<&ExprIr> {
+        match *self {
+            ExprIr::Column(_) | ExprIr::LiteralValue(_) | ExprIr::Position(_) | ExprIr::Parameter(_) => vec![],
+            ExprIr::Unary(_, ref expr) | ExprIr::IsNull{ref expr, ..} => vec![&**expr],
+            ExprIr::Binary(ref left, _, ref right) => vec![&**left, &**right],
+            ExprIr::Between{ref expr, ref low, ref high, ..} => vec![&**expr, &**low, &**high],
+            ExprIr::InList{ref expr, ref list, ..} => {
+                let mut children = vec![&**expr];
+                children.extend(list.iter());
+                children
+            },
+            ExprIr::Like{ref expr, ref pattern, ..} => vec![&**expr, &**pattern],
+            ExprIr::Aggregate(ref aggregate) => aggregate.arg.iter().collect(),
+            ExprIr::Window(ref window) => window.exprs(),
+            // The parameters of a subquery are evaluated in the enclosing query
+            ExprIr::Subquery(ref subquery) | ExprIr::Exists(ref subquery) => subquery.params.iter().collect(),
+            ExprIr::InSubquery{ref expr, ref subquery, ..} => {
+                let mut children = vec![&**expr];
+                children.extend(subquery.params.iter());
+                children
+            }
+        }
+    }
+
+    // Rebuilds the expression with each of its children replaced by the result of f
+    pub fn map_children<F>(self, mut f: F) -> SqlError<ExprIr> where F: FnMut(ExprIr) -> SqlError<ExprIr> {
+        Ok(match self {
+            ExprIr::Unary(operator, expr) => ExprIr::Unary(operator, Box::new(try!(f(*expr)))),
+            ExprIr::Binary(left, operator, right) => {
+                let left = try!(f(*left));
+                ExprIr::Binary(Box::new(left), operator, Box::new(try!(f(*right))))
+            },
+            ExprIr::IsNull{expr, negated} => ExprIr::IsNull { expr: Box::new(try!(f(*expr))), negated: negated },
+            ExprIr::Between{expr, negated, low, high} => {
+                let expr = try!(f(*expr));
+                let low = try!(f(*low));
+                ExprIr::Between { expr: Box::new(expr), negated: negated, low: Box::new(low), high: Box::new(try!(f(*high))) }
+            },
+            ExprIr::InList{expr, negated, list} => {
+                let expr = try!(f(*expr));
+                let mut items = Vec::new();
+                for item in list.into_iter() {
+                    items.push(try!(f(item)));
+                }
+                ExprIr::InList { expr: Box::new(expr), negated: negated, list: items }
+            },
+            ExprIr::Like{expr, negated, pattern} => {
+                let expr = try!(f(*expr));
+                ExprIr::Like { expr: Box::new(expr), negated: negated, pattern: Box::new(try!(f(*pattern))) }
+            },
+            ExprIr::Aggregate(aggregate) => {
+                let aggregate = *aggregate;
+                let arg = match aggregate.arg {
+                    Some(arg) => Some(try!(f(arg))),
+                    None => None
+                };
+                ExprIr::Aggregate(Box::new(AggregateIr { function: aggregate.function, distinct: aggregate.distinct, arg: arg }))
+            },
+            ExprIr::Window(window) => ExprIr::Window(Box::new(try!((*window).map_exprs(&mut f)))),
+            ExprIr::Subquery(subquery) => ExprIr::Subquery(Box::new(try!((*subquery).map_params(&mut f)))),
+            ExprIr::InSubquery{expr, negated, subquery} => {
+                let expr = try!(f(*expr));
+                ExprIr::InSubquery { expr: Box::new(expr), negated: negated, subquery: Box::new(try!((*subquery).map_params(&mut f))) }
+            },
+            ExprIr::Exists(subquery) => ExprIr::Exists(Box::new(try!((*subquery).map_params(&mut f)))),
+            expr => expr
+        })
+    }
+
+    // Appends every column referenced by the expression
+    pub fn collect_columns(&self, columns: &mut Vec<ColumnRef>) {
+        match *self {
+            ExprIr::Column(ref column_ref) => {
+                columns.push(column_ref.clone());
+            },
+            _ => {
+                for child in self.children().into_iter() {
+                    child.collect_columns(columns);
+                }
+            }
+        }
+    }
+
+    pub fn contains_aggregate(&self) -> bool {
+        match *self {
+            ExprIr::Aggregate(_) => true,
+            _ => self.children().iter().any(|child| child.contains_aggregate())
+        }
+    }
+
+    pub fn contains_window(&self) -> bool {
+        match *self {
+            ExprIr::Window(_) => true,
+            _ => self.children().iter().any(|child| child.contains_window())
+        }
+    }
+
+    pub fn contains_subquery(&self) -> bool {
+        match *self {
+            ExprIr::Subquery(_) | ExprIr::InSubquery{..} | ExprIr::Exists(_) => true,
+            _ => self.children().iter().any(|child| child.contains_subquery())
+        }
+    }
+
+    // The table refs whose columns the expression references
+    pub fn table_refs(&self) -> Vec<usize> {
+        let mut columns = Vec::new();
+        self.collect_columns(&mut columns);
+        let mut table_refs: Vec<usize> = columns.iter().map(|c| c.table_ref_index).collect();
+        table_refs.sort();
+        table_refs.dedup();
+        table_refs
+    }
+}
+
+pub fn resolve_expr(expr: &Expr, table_refs: &[TableRef], schema: &Schema) -> SqlError<ExprIr> {
+    let resolve = |expr: &Expr| resolve_expr(expr, table_refs, schema).map(Box::new);
+    Ok(match *expr {
+        Expr::Column(ref column_name) => {
+            ExprIr::Column(try!(resolve_single_column(column_name, table_refs)))
+        },
+        Expr::LiteralValue(ref value) => {
+            ExprIr::LiteralValue(value.clone())
+        },
+        Expr::Unary(operator, ref expr) => {
+            ExprIr::Unary(operator, try!(resolve(expr)))
+        },
+        Expr::Binary(ref left, operator, ref right) => {
+            ExprIr::Binary(try!(resolve(left)), operator, try!(resolve(right)))
+        },
+        Expr::IsNull{ref expr, negated} => {
+            ExprIr::IsNull { expr: try!(resolve(expr)), negated: negated }
+        },
+        Expr::Between{ref expr, negated, ref low, ref high} => {
+            ExprIr::Between { expr: try!(resolve(expr)), negated: negated, low: try!(resolve(low)), high: try!(resolve(high)) }
+        },
+        Expr::InList{ref expr, negated, ref list} => {
+            let mut items = Vec::new();
+            for item in list.iter() {
+                items.push(try!(resolve_expr(item, table_refs, schema)));
+            }
+            ExprIr::InList { expr: try!(resolve(expr)), negated: negated, list: items }
+        },
+        Expr::Like{ref expr, negated, ref pattern} => {
+            ExprIr::Like { expr: try!(resolve(expr)), negated: negated, pattern: try!(resolve(pattern)) }
+        },
+        Expr::Aggregate{function, distinct, ref arg} => {
+            let arg = match *arg {
+                Some(ref arg) => {
+                    let arg = try!(resolve_expr(arg, table_refs, schema));
+                    if arg.contains_aggregate() {
+                        return Err(Error::Grouping("Aggregate function calls cannot be nested".to_string()));
+                    }
+                    try!(reject_windows(&arg, "aggregate function calls"));
+                    Some(arg)
+                },
+                None => None
+            };
+            ExprIr::Aggregate(Box::new(AggregateIr { function: function, distinct: distinct, arg: arg }))
+        },
+        Expr::Window{function, ref args, ref window} => {
+            ExprIr::Window(Box::new(try!(resolve_window(function, args, window, table_refs, schema))))
+        },
+        Expr::Subquery(ref query) => {
+            ExprIr::Subquery(Box::new(try!(resolve_value_subquery(query, table_refs, schema))))
+        },
+        Expr::InSubquery{ref expr, negated, ref query} => {
+            let subquery = try!(resolve_value_subquery(query, table_refs, schema));
+            ExprIr::InSubquery { expr: try!(resolve(expr)), negated: negated, subquery: Box::new(subquery) }
+        },
+        Expr::Exists(ref query) => {
+            ExprIr::Exists(Box::new(try!(resolve_subquery(query, table_refs, schema))))
+        }
+    })
+}
+
+// Resolves a subquery whose values are used in an expression, which requires
+// it to return a single column
+fn resolve_value_subquery(query: &Query, table_refs: &[TableRef], schema: &Schema) -> SqlError<SubqueryIr> {
+    let subquery = try!(resolve_subquery(query, table_refs, schema));
+    let columns = subquery.query.column_defs().len();
+    if columns != 1 {
+        return Err(Error::Syntax(format!("Subquery must return only one column, got {}", columns)));
+    }
+    Ok(subquery)
+}
+
+// Fails if the expression uses an aggregate function, for clauses that are
+// evaluated before any aggregation
+pub fn reject_aggregates(expr: &ExprIr, clause: &str) -> SqlError<()> {
+    if expr.contains_aggregate() {
+        return Err(Error::Grouping(format!("Aggregate functions are not allowed in {}", clause)));
+    }
+    Ok(())
+}
+
+// Fails if the expression uses a window function, for clauses that are
+// evaluated before window functions are computed
+pub fn reject_windows(expr: &ExprIr, clause: &str) -> SqlError<()> {
+    if expr.contains_window() {
+        return Err(Error::Windowing(format!("Window functions are not allowed in {}", clause)));
+    }
+    Ok(())
+}
+
+fn type_name(ctype: Option<ColumnType>) -> String {
+    match ctype {
+        Some(ctype) => format!("{:?}", ctype),
+        None => "Null".to_string()
+    }
+}
+
+pub fn is_numeric(ctype: Option<ColumnType>) -> bool {
+    match ctype {
+        None | Some(ColumnType::Integer) | Some(ColumnType::Real) => true,
+        _ => false
+    }
+}
+
+// Two values can be compared if they have the same type or are both numbers.
+// NULL can be compared with anything.
+fn comparable(left: Option<ColumnType>, right: Option<ColumnType>) -> bool {
+    match (left, right) {
+        (Some(left), Some(right)) => left.widen(right).is_some(),
+        _ => true
+    }
+}
+
+fn expect_type(ctype: Option<ColumnType>, expected: ColumnType, context: &str) -> SqlError<()> {
+    match ctype {
+        None => Ok(()),
+        Some(ctype) if ctype == expected => Ok(()),
+        Some(ctype) => Err(Error::Type(format!("{} expects {:?} operands, got {:?}", context, expected, ctype)))
+    }
+}
+
+fn expect_comparable(left: Option<ColumnType>, right: Option<ColumnType>) -> SqlError<()> {
+    if comparable(left, right) {
+        Ok(())
+    }
+    else {
+        Err(Error::Type(format!("Cannot compare {} with {}", type_name(left), type_name(right))))
+    }
+}
+
+fn aggregate_type(function: AggregateFunction, ctype: Option<ColumnType>) -> SqlError<Option<ColumnType>> {
+    match function {
+        AggregateFunction::Count => Ok(Some(ColumnType::Integer)),
+        AggregateFunction::Min | AggregateFunction::Max => Ok(ctype),
+        AggregateFunction::Sum | AggregateFunction::Avg if !is_numeric(ctype) => {
+            Err(Error::Type(format!("{} expects a numeric argument, got {}", function.name(), type_name(ctype))))
+        },
+        AggregateFunction::Sum => Ok(ctype),
+        AggregateFunction::Avg => Ok(Some(ColumnType::Real))
+    }
+}
+
+// Checks the operand types of an expression and returns the type of its
+// result. None stands for an expression that is always NULL.
+pub fn infer_type(expr: &ExprIr, table_refs: &[TableRef], schema: &Schema) -> SqlError<Option<ColumnType>> {
+    let infer = |expr: &ExprIr| infer_type(expr, table_refs, schema);
+    match *expr {
+        ExprIr::Column(ref column_ref) => {
+            Ok(Some(column_definition(column_ref, table_refs).ctype))
+        },
+        ExprIr::LiteralValue(LiteralValue::Null) => Ok(None),
+        ExprIr::LiteralValue(ref value) => Ok(Some(ColumnType::accomodate_literal(value))),
+        ExprIr::Unary(UnaryOperator::Not, ref expr) => {
+            try!(expect_type(try!(infer(expr)), ColumnType::Bool, "NOT"));
+            Ok(Some(ColumnType::Bool))
+        },
+        ExprIr::Unary(operator, ref expr) => {
+            let ctype = try!(infer(expr));
+            if !is_numeric(ctype) {
+                return Err(Error::Type(format!("Unary {:?} expects a numeric operand, got {}", operator, type_name(ctype))));
+            }
+            Ok(ctype)
+        },
+        ExprIr::Binary(ref left, operator, ref right) => {
+            let (left, right) = (try!(infer(left)), try!(infer(right)));
+            match operator {
+                BinaryOperator::And | BinaryOperator::Or => {
+                    try!(expect_type(left, ColumnType::Bool, operator.symbol()));
+                    try!(expect_type(right, ColumnType::Bool, operator.symbol()));
+                    Ok(Some(ColumnType::Bool))
+                },
+                BinaryOperator::Concat => Ok(Some(ColumnType::Text)),
+                _ if operator.is_comparison() => {
+                    try!(expect_comparable(left, right));
+                    Ok(Some(ColumnType::Bool))
+                },
+                _ => {
+                    if !is_numeric(left) || !is_numeric(right) {
+                        return Err(Error::Type(format!("Operator {} expects numeric operands, got {} and {}",
+                                                       operator.symbol(), type_name(left), type_name(right))));
+                    }
+                    Ok(match (left, right) {
+                        (Some(left), Some(right)) => left.widen(right),
+                        (Some(ctype), None) | (None, Some(ctype)) => Some(ctype),
+                        (None, None) => None
+                    })
+                }
+            }
+        },
+        ExprIr::IsNull{ref expr, ..} => {
+            try!(infer(expr));
+            Ok(Some(ColumnType::Bool))
+        },
+        ExprIr::Between{ref expr, ref low, ref high, ..} => {
+            let ctype = try!(infer(expr));
+            try!(expect_comparable(ctype, try!(infer(low))));
+            try!(expect_comparable(ctype, try!(infer(high))));
+            Ok(Some(ColumnType::Bool))
+        },
+        ExprIr::InList{ref expr, ref list, ..} => {
+            let ctype = try!(infer(expr));
+            for item in list.iter() {
+                try!(expect_comparable(ctype, try!(infer(item))));
+            }
+            Ok(Some(ColumnType::Bool))
+        },
+        ExprIr::Like{ref expr, ref pattern, ..} => {
+            try!(expect_type(try!(infer(expr)), ColumnType::Text, "LIKE"));
+            try!(expect_type(try!(infer(pattern)), ColumnType::Text, "LIKE"));
+            Ok(Some(ColumnType::Bool))
+        },
+        ExprIr::Aggregate(ref aggregate) => {
+            let ctype = match aggregate.arg {
+                Some(ref arg) => try!(infer(arg)),
+                None => None
+            };
+            aggregate_type(aggregate.function, ctype)
+        },
+        ExprIr::Window(ref window) => {
+            match window.function {
+                WindowFunction::RowNumber | WindowFunction::Rank | WindowFunction::DenseRank => Ok(Some(ColumnType::Integer)),
+                WindowFunction::Lag | WindowFunction::Lead => {
+                    let ctype = try!(infer(&window.args[0]));
+                    if let Some(offset) = window.args.get(1) {
+                        try!(expect_type(try!(infer(offset)), ColumnType::Integer, window.function.name()));
+                    }
+                    match window.args.get(2) {
+                        Some(default) => {
+                            let default_type = try!(infer(default));
+                            try!(expect_comparable(ctype, default_type));
+                            Ok(ctype.or(default_type))
+                        },
+                        None => Ok(ctype)
+                    }
+                },
+                WindowFunction::FirstValue | WindowFunction::LastValue => infer(&window.args[0]),
+                WindowFunction::Aggregate(function) => {
+                    let ctype = match window.args.first() {
+                        Some(arg) => try!(infer(arg)),
+                        None => None
+                    };
+                    aggregate_type(function, ctype)
+                }
+            }
+        },
+        ExprIr::Position(_) => {
+            Err(Error::Internal("Type of a positional value requested".to_string()))
+        },
+        ExprIr::Parameter(_) => {
+            Err(Error::Internal("Type of a subquery parameter requested".to_string()))
+        },
+        ExprIr::Subquery(ref subquery) => Ok(subquery.value_type()),
+        ExprIr::InSubquery{ref expr, ref subquery, ..} => {
+            try!(expect_comparable(try!(infer(expr)), subquery.value_type()));
+            Ok(Some(ColumnType::Bool))
+        },
+        ExprIr::Exists(_) => Ok(Some(ColumnType::Bool))
+    }
+}
+
+// Resolves a predicate and checks it produces a boolean
+pub fn resolve_predicate(expr: &Expr, table_refs: &[TableRef], schema: &Schema) -> SqlError<ExprIr> {
+    let expr = try!(resolve_expr(expr, table_refs, schema));
+    match try!(infer_type(&expr, table_refs, schema)) {
+        None | Some(ColumnType::Bool) => Ok(expr),
+        Some(ctype) => Err(Error::Type(format!("Condition must be of type Bool, got {:?}", ctype)))
+    }
+}
+
+// Splits a predicate into the list of conditions that are AND-ed together
+pub fn split_conjuncts(expr: ExprIr, conjuncts: &mut Vec<ExprIr>) {
+    match expr {
+        ExprIr::Binary(left, BinaryOperator::And, right) => {
+            split_conjuncts(*left, conjuncts);
+            split_conjuncts(*right, conjuncts);
+        },
+        expr => {
+            conjuncts.push(expr);
+        }
+    }
+}
+
+// Resolves an optional predicate to its list of conjuncts
+pub fn resolve_conjuncts(expr: &Option<Expr>, table_refs: &[TableRef], schema: &Schema) -> SqlError<Vec<ExprIr>> {
+    let mut conjuncts = Vec::new();
+    if let Some(ref expr) = *expr {
+        let expr = try!(resolve_predicate(expr, table_refs, schema));
+        try!(reject_aggregates(&expr, "WHERE or ON"));
+        try!(reject_windows(&expr, "WHERE or ON"));
+        split_conjuncts(expr, &mut conjuncts);
+    }
+    Ok(conjuncts)
+}
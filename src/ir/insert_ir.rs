@@ -1,37 +1,52 @@
 use definitions::*;
 use schema::*;
-use super::{TableRef,ColumnRef,resolve_table_reference,resolve_column_references};
+use super::{TableRef,ColumnRef,ExprIr,resolve_table_reference,resolve_column_references,check_assignment,column_definition};
+use super::expr_ir::resolve_expr;
 
 pub struct InsertIr {
-    pub values: Vec<LiteralValue>,
+    // Values cannot reference columns, they are evaluated once when the row is built
+    pub values: Vec<ExprIr>,
     pub table: TableRef,
-    pub columns: Vec<ColumnRef>
+    pub columns: Vec<ColumnRef>,
+    pub column_defs: Vec<ColumnDefinition>
 }
 
 pub fn ir_from_insert_stmt(stmt: &InsertStmt, schema: &Schema) -> SqlError<InsertIr> {
     // First lets ensure that the columns listed are not duplicated
-    let mut col_names_deduped = stmt.column_names.clone();
-    col_names_deduped.sort_by(|a,b| a.cmp(b));
-    col_names_deduped.dedup();
-    if stmt.column_names.len() != col_names_deduped.len() {
-        return Err("Duplicated column names in insert statement".to_string());
+    for (index, name) in stmt.column_names.iter().enumerate() {
+        if stmt.column_names[..index].contains(name) {
+            return Err(Error::DuplicateColumn(name.clone()));
+        }
     }
 
     // Ensure the number of listed columns match the number of supplied values
     if stmt.column_values.len() < stmt.column_names.len() {
-        return Err("Not enough values".to_string());
+        return Err(Error::Syntax("Not enough values".to_string()));
     }
     else if stmt.column_values.len() > stmt.column_names.len() {
-        return Err("Too many values".to_string());
+        return Err(Error::Syntax("Too many values".to_string()));
     }
 
     // Now lets resolve the table and column references
-    let table_ref = try!(resolve_table_reference(&stmt.table_name,schema));
-    let column_refs = try!(resolve_column_references(&stmt.column_names, &vec![table_ref.clone()], schema));
+    let table_ref = try!(resolve_table_reference(&stmt.table_name, 0, schema));
+    let table_refs = vec![table_ref.clone()];
+    let column_refs = try!(resolve_column_references(&stmt.column_names, &table_refs[..]));
+
+    // Every value has to match the type of the column it is inserted into
+    let mut values = Vec::new();
+    let mut column_defs = Vec::new();
+    for (column_ref, value) in column_refs.iter().zip(stmt.column_values.iter()) {
+        let column_def = column_definition(column_ref, &table_refs[..]);
+        let value = try!(resolve_expr(value, &[], schema));
+        try!(check_assignment(&value, &column_def, &[], schema));
+        values.push(value);
+        column_defs.push(column_def);
+    }
 
     Ok(InsertIr {
         columns: column_refs,
+        column_defs: column_defs,
         table: table_ref,
-        values: stmt.column_values.clone()
+        values: values
     })
 }
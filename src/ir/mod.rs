@@ -1,12 +1,35 @@
 use definitions::*;
 use schema::*;
 
+pub mod expr_ir;
 pub mod select_ir;
+pub mod query_ir;
 pub mod insert_ir;
+pub mod update_ir;
+pub mod delete_ir;
+
+pub use self::expr_ir::ExprIr;
 
 #[derive(Debug,Clone,PartialEq)]
 pub struct TableRef {
-    pub table_index: usize
+    // None for a table derived from a subquery
+    pub table_index: Option<usize>,
+    // The name columns can be qualified with, either the table name or its alias
+    pub name: String,
+    pub columns: Vec<ColumnDefinition>,
+    // How deeply nested the query the table belongs to is, columns are looked
+    // up in the innermost query first
+    pub scope: usize
+}
+
+impl TableRef {
+    // The index of the stored table the ref reads
+    pub fn stored_table_index(&self) -> SqlError<usize> {
+        match self.table_index {
+            Some(table_index) => Ok(table_index),
+            None => Err(Error::Internal(format!("{} is not a stored table", self.name)))
+        }
+    }
 }
 
 #[derive(Debug,Clone,PartialEq)]
@@ -15,60 +38,65 @@ pub struct ColumnRef {
     pub table_ref_index: usize
 }
 
-fn resolve_table_reference(table_name: &str, schema: &Schema) -> SqlError<TableRef> {
-
+fn resolve_table_reference(table_name: &str, scope: usize, schema: &Schema) -> SqlError<TableRef> {
+    let table_index = try!(schema.find_table_or_err(table_name));
     Ok(
         TableRef {
-            table_index: try!(schema.find_table_or_err(table_name))
+            table_index: Some(table_index),
+            name: table_name.to_string(),
+            columns: try!(schema.map_on_table(table_index, |table| Ok(table.columns().to_owned()))),
+            scope: scope
         })
 }
 
-fn resolve_column_references(column_names: &[String], table_refs: &[TableRef], schema: &Schema) -> SqlError<Vec<ColumnRef>> {
-    #[derive(Clone)]
-    struct ColumnTableMappings {
-        table_ref_index: usize,
-        index: usize,
-        name: String
-    };
-
-
-    // First lets build a vector of all the available columns
-    let mut all_columns = Vec::new();
-    for (table_ref_index,table_ref) in table_refs.iter().enumerate() {
-        let table_columns =
-            try!(schema.map_on_table(table_ref.table_index, |table| {
-                Ok(table.columns().to_owned())
-            }));
-        for (column_index, ref column_def) in table_columns.iter().enumerate() {
-            all_columns.push(ColumnTableMappings {
-                table_ref_index: table_ref_index,
-                index: column_index,
-                name: column_def.name.to_owned()
-            });
-        }
-
+// Splits a possibly qualified column name such as orders.id into its table and column parts
+fn split_column_name(column_name: &str) -> (Option<&str>, &str) {
+    match column_name.find('.') {
+        Some(pos) => (Some(&column_name[..pos]), &column_name[pos+1..]),
+        None => (None, column_name)
     }
+}
 
-    // Now lets try to match against them
+// Looks up columns by name. The tables of a subquery hide the tables of the
+// queries it is nested in, so a name is only ambiguous when it matches several
+// tables of the same query.
+fn resolve_column_references(column_names: &[String], table_refs: &[TableRef]) -> SqlError<Vec<ColumnRef>> {
     let mut column_refs = Vec::new();
     for column_name in column_names.iter() {
-        let mut all_columns_iter = all_columns.iter();
-        match all_columns_iter.find(|&c| {c.name == *column_name}) {
-            Some(matched_col) => {
-                match all_columns_iter.find(|&c| {c.name == *column_name}) {
-                    Some(_) => {
-                        return Err(format!("Ambiguous column {}", column_name));
-                    },
-                    None => {
-                        column_refs.push(ColumnRef{
-                            column_index: matched_col.index,
-                            table_ref_index: matched_col.table_ref_index
-                            });
-                    }
+        let (table_name, name) = split_column_name(column_name);
+        let mut found: Option<(ColumnRef, usize)> = None;
+        let mut ambiguous = false;
+        for (table_ref_index, table_ref) in table_refs.iter().enumerate() {
+            if table_name.map(|t| t != table_ref.name).unwrap_or(false) {
+                continue;
+            }
+            for (column_index, column_def) in table_ref.columns.iter().enumerate() {
+                if column_def.name != name {
+                    continue;
                 }
+                let scope = found.as_ref().map(|&(_, scope)| scope);
+                if scope == Some(table_ref.scope) {
+                    ambiguous = true;
+                }
+                else if scope.map(|scope| scope < table_ref.scope).unwrap_or(true) {
+                    found = Some((ColumnRef { column_index: column_index, table_ref_index: table_ref_index }, table_ref.scope));
+                    ambiguous = false;
+                }
+            }
+        }
+        match found {
+            Some(_) if ambiguous => {
+                return Err(Error::AmbiguousColumn(column_name.clone()));
+            },
+            Some((column_ref, _)) => {
+                column_refs.push(column_ref);
             },
             None => {
-                return Err(format!("Column {} not found", column_name));
+                // A qualified name can only use the tables in scope
+                return Err(match table_name {
+                    Some(table_name) if !table_refs.iter().any(|t| t.name == table_name) => Error::UnknownTable(table_name.to_string()),
+                    _ => Error::UnknownColumn(column_name.clone())
+                });
             }
         }
     }
@@ -76,18 +104,52 @@ fn resolve_column_references(column_names: &[String], table_refs: &[TableRef], s
     Ok(column_refs)
 }
 
-fn resolve_column_wildcard(table_ref: &TableRef, table_ref_index: usize, schema: &Schema) -> SqlError<Vec<ColumnRef>> {
-    let mut column_refs = Vec::new();
-    let table_columns =
-        try!(schema.map_on_table(table_ref.table_index, |table| {
-            Ok(table.columns().to_owned())
-        }));
-    for (column_index, _) in table_columns.iter().enumerate() {
-        column_refs.push(ColumnRef {
+fn resolve_column_wildcard(table_ref: &TableRef, table_ref_index: usize) -> Vec<ColumnRef> {
+    (0..table_ref.columns.len()).map(|column_index| {
+        ColumnRef {
             table_ref_index: table_ref_index,
             column_index: column_index
-        });
+        }
+    }).collect()
+}
+
+fn resolve_single_column(column_name: &String, table_refs: &[TableRef]) -> SqlError<ColumnRef> {
+    let mut column_refs = try!(resolve_column_references(&[column_name.clone()], table_refs));
+    Ok(column_refs.remove(0))
+}
+
+// Converts a value to the declared type of the column it will be stored in.
+// Only conversions that do not lose information are allowed.
+pub fn coerce_value(value: &LiteralValue, column_def: &ColumnDefinition) -> SqlError<LiteralValue> {
+    if *value == LiteralValue::Null {
+        return Ok(LiteralValue::Null);
     }
-    Ok(column_refs)
+    match column_def.ctype.accomodate(value) {
+        Some(ctype) if ctype == column_def.ctype => {
+            value.to_type(ctype)
+        },
+        _ => {
+            Err(Error::Type(format!("Column {} of type {:?} cannot hold value {:?}", column_def.name, column_def.ctype, value)))
+        }
+    }
+}
+
+// Checks that an expression produces values a column can hold. Literals are
+// checked by value, anything else by the type of its result.
+fn check_assignment(expr: &ExprIr, column_def: &ColumnDefinition, table_refs: &[TableRef], schema: &Schema) -> SqlError<()> {
+    try!(expr_ir::reject_aggregates(expr, "VALUES or SET"));
+    try!(expr_ir::reject_windows(expr, "VALUES or SET"));
+    if let ExprIr::LiteralValue(ref value) = *expr {
+        return coerce_value(value, column_def).map(|_| ());
+    }
+    match try!(expr_ir::infer_type(expr, table_refs, schema)) {
+        Some(ctype) if column_def.ctype.widen(ctype) != Some(column_def.ctype) => {
+            Err(Error::Type(format!("Column {} of type {:?} cannot hold values of type {:?}", column_def.name, column_def.ctype, ctype)))
+        },
+        _ => Ok(())
+    }
+}
 
+fn column_definition(column_ref: &ColumnRef, table_refs: &[TableRef]) -> ColumnDefinition {
+    table_refs[column_ref.table_ref_index].columns[column_ref.column_index].clone()
 }
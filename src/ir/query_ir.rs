@@ -0,0 +1,330 @@
+use definitions::*;
+use schema::*;
+use super::{TableRef,ExprIr};
+use super::select_ir::{SelectIr,SortKeyIr,FromIr,resolve_select,resolve_row_count,map_sort_keys};
+
+// A query producing rows, either a single select or a tree of set operations
+#[derive(Debug,Clone,PartialEq)]
+pub enum QueryIr {
+    Select(SelectIr),
+    SetOperation(SetOperationIr)
+}
+
+impl QueryIr {
+    pub fn column_defs(&self) -> &[ColumnDefinition] {
+        match *self {
+            QueryIr::Select(ref ir) => &ir.column_defs[..],
+            QueryIr::SetOperation(ref ir) => &ir.column_defs[..]
+        }
+    }
+
+    // The type of each result column, None for a column that is always NULL
+    fn column_types(&self) -> Vec<Option<ColumnType>> {
+        match *self {
+            QueryIr::Select(ref ir) => {
+                ir.projections.iter().zip(ir.column_defs.iter()).map(|(projection, column_def)| {
+                    match *projection {
+                        ExprIr::LiteralValue(LiteralValue::Null) => None,
+                        _ => Some(column_def.ctype)
+                    }
+                }).collect()
+            },
+            QueryIr::SetOperation(ref ir) => ir.column_defs.iter().map(|c| Some(c.ctype)).collect()
+        }
+    }
+
+    // Rebuilds the query with each of the expressions of its selects replaced
+    // by the result of f
+    pub fn map_exprs<F>(self, f: &mut F) -> SqlError<QueryIr> where F: FnMut(ExprIr) -> SqlError<ExprIr> {
+        match self {
+            QueryIr::Select(ir) => Ok(QueryIr::Select(try!(ir.map_exprs(f)))),
+            QueryIr::SetOperation(ir) => {
+                let left = try!((*ir.left).map_exprs(f));
+                let right = try!((*ir.right).map_exprs(f));
+                let limit = match ir.limit {
+                    Some(limit) => Some(try!(f(limit))),
+                    None => None
+                };
+                let offset = match ir.offset {
+                    Some(offset) => Some(try!(f(offset))),
+                    None => None
+                };
+                Ok(QueryIr::SetOperation(SetOperationIr {
+                    operator: ir.operator,
+                    all: ir.all,
+                    left: Box::new(left),
+                    right: Box::new(right),
+                    column_defs: ir.column_defs,
+                    order_by: try!(map_sort_keys(ir.order_by, f)),
+                    limit: limit,
+                    offset: offset
+                }))
+            }
+        }
+    }
+
+    // Calls f on every FROM item of the query's selects
+    pub fn for_each_from_item(&mut self, f: &mut FnMut(&mut FromIr)) {
+        match *self {
+            QueryIr::Select(ref mut ir) => ir.from.for_each_item(f),
+            QueryIr::SetOperation(ref mut ir) => {
+                ir.left.for_each_from_item(f);
+                ir.right.for_each_from_item(f);
+            }
+        }
+    }
+}
+
+// A query nested in an expression or a FROM clause. The columns of the
+// enclosing queries it references are replaced by parameters, whose values are
+// given by evaluating params in the enclosing query.
+#[derive(Debug,Clone,PartialEq)]
+pub struct SubqueryIr {
+    pub query: QueryIr,
+    pub params: Vec<ExprIr>
+}
+
+impl SubqueryIr {
+    pub fn map_params<F>(self, f: &mut F) -> SqlError<SubqueryIr> where F: FnMut(ExprIr) -> SqlError<ExprIr> {
+        let mut params = Vec::new();
+        for param in self.params.into_iter() {
+            params.push(try!(f(param)));
+        }
+        Ok(SubqueryIr {
+            query: self.query,
+            params: params
+        })
+    }
+
+    // The type of the values of a single column subquery, None when they are
+    // always NULL
+    pub fn value_type(&self) -> Option<ColumnType> {
+        self.query.column_types()[0]
+    }
+
+    // The query to run for the given parameter values
+    pub fn bind_parameters(&self, values: &[LiteralValue]) -> SqlError<QueryIr> {
+        self.query.clone().map_exprs(&mut |expr: ExprIr| substitute_parameters(expr, values))
+    }
+}
+
+fn substitute_parameters(expr: ExprIr, values: &[LiteralValue]) -> SqlError<ExprIr> {
+    match expr {
+        ExprIr::Parameter(index) => Ok(ExprIr::LiteralValue(values[index].clone())),
+        expr => expr.map_children(|child| substitute_parameters(child, values))
+    }
+}
+
+// Result columns take their names from the left query and the widest type of
+// both queries
+#[derive(Debug,Clone,PartialEq)]
+pub struct SetOperationIr {
+    pub operator: SetOperator,
+    pub all: bool,
+    pub left: Box<QueryIr>,
+    pub right: Box<QueryIr>,
+    pub column_defs: Vec<ColumnDefinition>,
+    // Sort keys refer to the result columns by position
+    pub order_by: Vec<SortKeyIr>,
+    pub limit: Option<ExprIr>,
+    pub offset: Option<ExprIr>
+}
+
+// Checks both queries produce the same number of columns with compatible types
+fn combine_columns(operator: SetOperator, left: &QueryIr, right: &QueryIr) -> SqlError<Vec<ColumnDefinition>> {
+    let (left_types, right_types) = (left.column_types(), right.column_types());
+    if left_types.len() != right_types.len() {
+        return Err(Error::Syntax(format!("Each {} query must have the same number of columns, got {} and {}",
+                                         operator.name(), left_types.len(), right_types.len())));
+    }
+    let mut column_defs = Vec::new();
+    for (index, column_def) in left.column_defs().iter().enumerate() {
+        let ctype = match (left_types[index], right_types[index]) {
+            (Some(left_type), Some(right_type)) => {
+                match left_type.widen(right_type) {
+                    Some(ctype) => ctype,
+                    None => {
+                        return Err(Error::Type(format!("{} types {:?} and {:?} cannot be matched in column {}",
+                                                       operator.name(), left_type, right_type, index + 1)));
+                    }
+                }
+            },
+            (Some(ctype), None) | (None, Some(ctype)) => ctype,
+            (None, None) => column_def.ctype
+        };
+        column_defs.push(ColumnDefinition {
+            name: column_def.name.clone(),
+            ctype: ctype
+        });
+    }
+    Ok(column_defs)
+}
+
+// The ORDER BY clause of a set operation can only name a result column or
+// give its position
+fn resolve_result_order_by(order_by: &[OrderByItem], column_defs: &[ColumnDefinition]) -> SqlError<Vec<SortKeyIr>> {
+    let mut sort_keys = Vec::new();
+    for item in order_by.iter() {
+        let index = match item.expr {
+            Expr::Column(ref name) => {
+                match column_defs.iter().position(|c| c.name == *name) {
+                    Some(index) => index,
+                    None => {
+                        return Err(Error::UnknownColumn(name.clone()));
+                    }
+                }
+            },
+            Expr::LiteralValue(LiteralValue::Integer(position)) if position >= 1 && position as usize <= column_defs.len() => {
+                position as usize - 1
+            },
+            _ => {
+                return Err(Error::Syntax(format!("ORDER BY of a set operation must name a result column, got {:?}", item.expr)));
+            }
+        };
+        sort_keys.push(SortKeyIr {
+            expr: ExprIr::Position(index),
+            descending: item.descending,
+            nulls_first: item.nulls_first.unwrap_or(item.descending)
+        });
+    }
+    Ok(sort_keys)
+}
+
+fn resolve_set_operation(stmt: &SetOperationStmt, outer: &[TableRef], params: &mut Vec<ExprIr>, schema: &Schema) -> SqlError<SetOperationIr> {
+    let left = try!(resolve_query(&stmt.left, outer, params, schema));
+    let right = try!(resolve_query(&stmt.right, outer, params, schema));
+    let column_defs = try!(combine_columns(stmt.operator, &left, &right));
+    let order_by = try!(resolve_result_order_by(&stmt.order_by, &column_defs));
+    let limit = try!(resolve_row_count(&stmt.limit, "LIMIT", schema));
+    let offset = try!(resolve_row_count(&stmt.offset, "OFFSET", schema));
+
+    Ok(SetOperationIr {
+        operator: stmt.operator,
+        all: stmt.all,
+        left: Box::new(left),
+        right: Box::new(right),
+        column_defs: column_defs,
+        order_by: order_by,
+        limit: limit,
+        offset: offset
+    })
+}
+
+// A recursive common table expression. The rows of the anchor are returned
+// first, then the recursive term is run on the rows returned by its previous
+// iteration until it returns no new rows. Without all, rows that were already
+// returned are dropped.
+#[derive(Debug,Clone,PartialEq)]
+pub struct RecursiveIr {
+    pub all: bool,
+    pub anchor: QueryIr,
+    // Reads the previous rows through FromIr::WorkingTable
+    pub term: QueryIr,
+    pub column_defs: Vec<ColumnDefinition>
+}
+
+// How the rows of a common table are produced
+pub enum CommonTableIr {
+    Query(SubqueryIr),
+    Recursive(RecursiveIr)
+}
+
+// Renames the columns of a common table after the names given in its definition
+fn name_columns(name: &str, column_names: &[String], column_defs: &[ColumnDefinition]) -> SqlError<Vec<ColumnDefinition>> {
+    if column_names.is_empty() {
+        return Ok(column_defs.to_vec());
+    }
+    if column_names.len() != column_defs.len() {
+        return Err(Error::Syntax(format!("{} has {} columns but {} column names were given",
+                                         name, column_defs.len(), column_names.len())));
+    }
+    Ok(column_names.iter().zip(column_defs.iter()).map(|(column_name, column_def)| {
+        ColumnDefinition {
+            name: column_name.clone(),
+            ctype: column_def.ctype
+        }
+    }).collect())
+}
+
+fn reads_working_table(query: &mut QueryIr) -> bool {
+    let mut reads = false;
+    query.for_each_from_item(&mut |item: &mut FromIr| {
+        if let FromIr::WorkingTable(..) = *item {
+            reads = true;
+        }
+    });
+    reads
+}
+
+// Resolves the query of a common table, giving the columns of the table. The
+// schema only holds the common tables defined before it. A recursive query
+// must be a UNION whose right side reads the table itself.
+pub fn resolve_common_table(name: &str, column_names: &[String], query: &Query, recursive: bool, schema: &Schema) -> SqlError<(Vec<ColumnDefinition>, CommonTableIr)> {
+    if let (true, &Query::SetOperation(ref stmt)) = (recursive, query) {
+        if stmt.operator == SetOperator::Union {
+            let anchor = try!(ir_from_query(&stmt.left, schema));
+            let working_table = CommonTable::WorkingTable {
+                name: name.to_string(),
+                columns: try!(name_columns(name, column_names, anchor.column_defs()))
+            };
+            let mut term = try!(ir_from_query(&stmt.right, &schema.with_common_table(working_table)));
+            if reads_working_table(&mut term) {
+                if !stmt.order_by.is_empty() || stmt.limit.is_some() || stmt.offset.is_some() {
+                    return Err(Error::Unsupported(format!("ORDER BY or LIMIT in recursive query {}", name)));
+                }
+                let column_defs = try!(name_columns(name, column_names,
+                                                    &try!(combine_columns(SetOperator::Union, &anchor, &term))));
+                return Ok((column_defs.clone(), CommonTableIr::Recursive(RecursiveIr {
+                    all: stmt.all,
+                    anchor: anchor,
+                    term: term,
+                    column_defs: column_defs
+                })));
+            }
+        }
+    }
+    let subquery = try!(resolve_subquery(query, &[], schema));
+    let column_defs = try!(name_columns(name, column_names, subquery.query.column_defs()));
+    Ok((column_defs, CommonTableIr::Query(subquery)))
+}
+
+// Each common table is checked when it is defined, and resolved again
+// wherever it is read
+fn resolve_with(stmt: &WithStmt, outer: &[TableRef], params: &mut Vec<ExprIr>, schema: &Schema) -> SqlError<QueryIr> {
+    let mut with_schema = schema.clone();
+    for (index, cte) in stmt.ctes.iter().enumerate() {
+        if stmt.ctes[..index].iter().any(|c| c.name == cte.name) {
+            return Err(Error::DuplicateAlias(cte.name.clone()));
+        }
+        try!(resolve_common_table(&cte.name, &cte.column_names, &cte.query, stmt.recursive, &with_schema));
+        with_schema = with_schema.with_common_table(CommonTable::Query {
+            name: cte.name.clone(),
+            column_names: cte.column_names.clone(),
+            query: cte.query.clone(),
+            recursive: stmt.recursive
+        });
+    }
+    resolve_query(&stmt.query, outer, params, &with_schema)
+}
+
+fn resolve_query(query: &Query, outer: &[TableRef], params: &mut Vec<ExprIr>, schema: &Schema) -> SqlError<QueryIr> {
+    match *query {
+        Query::Select(ref stmt) => Ok(QueryIr::Select(try!(resolve_select(stmt, outer, params, schema)))),
+        Query::SetOperation(ref stmt) => Ok(QueryIr::SetOperation(try!(resolve_set_operation(stmt, outer, params, schema)))),
+        Query::With(ref stmt) => resolve_with(stmt, outer, params, schema)
+    }
+}
+
+// Resolves a query nested in another one whose tables are given by outer
+pub fn resolve_subquery(query: &Query, outer: &[TableRef], schema: &Schema) -> SqlError<SubqueryIr> {
+    let mut params = Vec::new();
+    let query = try!(resolve_query(query, outer, &mut params, schema));
+    Ok(SubqueryIr {
+        query: query,
+        params: params
+    })
+}
+
+pub fn ir_from_query(query: &Query, schema: &Schema) -> SqlError<QueryIr> {
+    resolve_query(query, &[], &mut Vec::new(), schema)
+}
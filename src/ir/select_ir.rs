@@ -1,54 +1,829 @@
 use definitions::*;
 use schema::*;
-use super::{TableRef,ColumnRef,resolve_table_reference,resolve_column_references,resolve_column_wildcard};
-
+use super::{TableRef,ColumnRef,resolve_table_reference,resolve_column_wildcard,resolve_single_column,column_definition};
+use super::expr_ir::*;
+use super::query_ir::{SubqueryIr,RecursiveIr,CommonTableIr,resolve_subquery,resolve_common_table};
+use std::rc::Rc;
 
+#[derive(Debug,Clone,PartialEq)]
 pub struct SelectIr {
-    pub columns: Vec<ColumnRef>,
-    pub tables: Vec<TableRef>
+    pub distinct: bool,
+    pub projections: Vec<ExprIr>,
+    pub column_defs: Vec<ColumnDefinition>,
+    pub tables: Vec<TableRef>,
+    pub from: FromIr,
+    // Conditions that must all hold for a row to be selected
+    pub filters: Vec<ExprIr>,
+    // Set for grouped queries, whose projections, HAVING conditions and sort
+    // keys are evaluated on the aggregated rows
+    pub aggregation: Option<AggregationIr>,
+    // Computed in order on the rows after grouping, each appending its value
+    // to them where projections and sort keys read it through ExprIr::Position
+    pub windows: Vec<WindowIr>,
+    pub order_by: Vec<SortKeyIr>,
+    pub limit: Option<ExprIr>,
+    pub offset: Option<ExprIr>
+}
+
+// Aggregated rows are made of the group keys followed by the aggregate values
+#[derive(Debug,Clone,PartialEq)]
+pub struct AggregationIr {
+    pub group_by: Vec<ExprIr>,
+    pub aggregates: Vec<AggregateIr>,
+    pub having: Vec<ExprIr>
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct SortKeyIr {
+    pub expr: ExprIr,
+    pub descending: bool,
+    pub nulls_first: bool
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct WindowIr {
+    pub function: WindowFunction,
+    pub args: Vec<ExprIr>,
+    pub partition_by: Vec<ExprIr>,
+    pub order_by: Vec<SortKeyIr>,
+    pub frame: FrameIr
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct FrameIr {
+    pub units: FrameUnits,
+    pub start: FrameBoundIr,
+    pub end: FrameBoundIr
+}
+
+// Offsets are constant expressions
+#[derive(Debug,Clone,PartialEq)]
+pub enum FrameBoundIr {
+    UnboundedPreceding,
+    Preceding(ExprIr),
+    CurrentRow,
+    Following(ExprIr),
+    UnboundedFollowing
+}
+
+impl FrameBoundIr {
+    // Bounds in the order they are met going through a partition
+    fn position(&self) -> usize {
+        match *self {
+            FrameBoundIr::UnboundedPreceding => 0,
+            FrameBoundIr::Preceding(_) => 1,
+            FrameBoundIr::CurrentRow => 2,
+            FrameBoundIr::Following(_) => 3,
+            FrameBoundIr::UnboundedFollowing => 4
+        }
+    }
+}
+
+impl WindowIr {
+    // The expressions evaluated on each row of the window's input
+    pub fn exprs(&self) -> Vec<&ExprIr> {
+        let mut exprs: Vec<&ExprIr> = self.args.iter().collect();
+        exprs.extend(self.partition_by.iter());
+        exprs.extend(self.order_by.iter().map(|key| &key.expr));
+        exprs
+    }
+
+    pub fn map_exprs<F>(self, f: &mut F) -> SqlError<WindowIr> where F: FnMut(ExprIr) -> SqlError<ExprIr> {
+        Ok(WindowIr {
+            function: self.function,
+            args: try!(map_all(self.args, f)),
+            partition_by: try!(map_all(self.partition_by, f)),
+            order_by: try!(map_sort_keys(self.order_by, f)),
+            frame: self.frame
+        })
+    }
 }
 
-fn resolve_projection_columns(stmt: &SelectStmt, table_refs: &Vec<TableRef>, schema: &Schema) -> SqlError<Vec<ColumnRef>> {
-    let mut column_names = Vec::new();
+// The tables of the FROM clause and how they are joined. Tables are numbered
+// left to right, so every subtree covers a contiguous range of table refs.
+#[derive(Debug,Clone,PartialEq)]
+pub enum FromIr {
+    Table(usize),
+    // A table whose rows are produced by a subquery
+    Derived(usize, Box<SubqueryIr>),
+    // A recursive common table expression
+    Recursive(usize, Box<RecursiveIr>),
+    // The rows of the previous iteration of a recursive query, which are only
+    // known when its recursive term is planned
+    WorkingTable(usize, Rc<Vec<Vec<LiteralValue>>>),
+    // A table function called with constant arguments
+    Function(usize, String, Vec<LiteralValue>),
+    Join {
+        join_type: JoinType,
+        left: Box<FromIr>,
+        right: Box<FromIr>,
+        on: Vec<ExprIr>
+    }
+}
+
+fn map_all<F>(exprs: Vec<ExprIr>, f: &mut F) -> SqlError<Vec<ExprIr>> where F: FnMut(ExprIr) -> SqlError<ExprIr> {
+    let mut mapped = Vec::new();
+    for expr in exprs.into_iter() {
+        mapped.push(try!(f(expr)));
+    }
+    Ok(mapped)
+}
+
+fn map_option<F>(expr: Option<ExprIr>, f: &mut F) -> SqlError<Option<ExprIr>> where F: FnMut(ExprIr) -> SqlError<ExprIr> {
+    match expr {
+        Some(expr) => Ok(Some(try!(f(expr)))),
+        None => Ok(None)
+    }
+}
+
+pub fn map_sort_keys<F>(keys: Vec<SortKeyIr>, f: &mut F) -> SqlError<Vec<SortKeyIr>> where F: FnMut(ExprIr) -> SqlError<ExprIr> {
+    let mut mapped = Vec::new();
+    for key in keys.into_iter() {
+        mapped.push(SortKeyIr {
+            expr: try!(f(key.expr)),
+            descending: key.descending,
+            nulls_first: key.nulls_first
+        });
+    }
+    Ok(mapped)
+}
+
+fn map_windows<F>(windows: Vec<WindowIr>, f: &mut F) -> SqlError<Vec<WindowIr>> where F: FnMut(ExprIr) -> SqlError<ExprIr> {
+    let mut mapped = Vec::new();
+    for window in windows.into_iter() {
+        mapped.push(try!(window.map_exprs(f)));
+    }
+    Ok(mapped)
+}
+
+impl FromIr {
+    fn map_exprs<F>(self, f: &mut F) -> SqlError<FromIr> where F: FnMut(ExprIr) -> SqlError<ExprIr> {
+        Ok(match self {
+            FromIr::Table(table_ref_index) => FromIr::Table(table_ref_index),
+            FromIr::Derived(table_ref_index, subquery) => FromIr::Derived(table_ref_index, Box::new(try!((*subquery).map_params(f)))),
+            FromIr::Recursive(table_ref_index, recursive) => FromIr::Recursive(table_ref_index, recursive),
+            FromIr::WorkingTable(table_ref_index, rows) => FromIr::WorkingTable(table_ref_index, rows),
+            FromIr::Function(table_ref_index, name, args) => FromIr::Function(table_ref_index, name, args),
+            FromIr::Join{join_type, left, right, on} => {
+                let left = try!((*left).map_exprs(f));
+                let right = try!((*right).map_exprs(f));
+                FromIr::Join {
+                    join_type: join_type,
+                    left: Box::new(left),
+                    right: Box::new(right),
+                    on: try!(map_all(on, f))
+                }
+            }
+        })
+    }
+
+    // Calls f on this item and every item below it, including those of the
+    // derived tables read. Recursive queries are not entered.
+    pub fn for_each_item(&mut self, f: &mut FnMut(&mut FromIr)) {
+        f(self);
+        match *self {
+            FromIr::Derived(_, ref mut subquery) => subquery.query.for_each_from_item(f),
+            FromIr::Join{ref mut left, ref mut right, ..} => {
+                left.for_each_item(f);
+                right.for_each_item(f);
+            },
+            _ => {}
+        }
+    }
+}
+
+impl SelectIr {
+    // Rebuilds the select with each of its top level expressions replaced by
+    // the result of f
+    pub fn map_exprs<F>(self, f: &mut F) -> SqlError<SelectIr> where F: FnMut(ExprIr) -> SqlError<ExprIr> {
+        let aggregation = match self.aggregation {
+            Some(aggregation) => {
+                let mut aggregates = Vec::new();
+                for aggregate in aggregation.aggregates.into_iter() {
+                    aggregates.push(AggregateIr {
+                        function: aggregate.function,
+                        distinct: aggregate.distinct,
+                        arg: try!(map_option(aggregate.arg, f))
+                    });
+                }
+                Some(AggregationIr {
+                    group_by: try!(map_all(aggregation.group_by, f)),
+                    aggregates: aggregates,
+                    having: try!(map_all(aggregation.having, f))
+                })
+            },
+            None => None
+        };
+        Ok(SelectIr {
+            distinct: self.distinct,
+            projections: try!(map_all(self.projections, f)),
+            column_defs: self.column_defs,
+            tables: self.tables,
+            from: try!(self.from.map_exprs(f)),
+            filters: try!(map_all(self.filters, f)),
+            aggregation: aggregation,
+            windows: try!(map_windows(self.windows, f)),
+            order_by: try!(map_sort_keys(self.order_by, f)),
+            limit: try!(map_option(self.limit, f)),
+            offset: try!(map_option(self.offset, f))
+        })
+    }
+}
+
+// Resolves a projection item to the expressions it selects. Wildcards only
+// cover the query's own tables, which follow the outer_len tables of the
+// enclosing queries.
+fn resolve_projection_column(column: &SelectProjectionColumn, table_refs: &Vec<TableRef>, outer_len: usize, schema: &Schema) -> SqlError<Vec<ExprIr>> {
+    match column {
+        &SelectProjectionColumn::Expr(ref expr) => {
+            Ok(vec![try!(resolve_expr(expr, &table_refs[..], schema))])
+        },
+        &SelectProjectionColumn::Wildcard => {
+            let mut column_refs = Vec::new();
+            for (table_ref_index, table_ref) in table_refs.iter().enumerate().skip(outer_len) {
+                column_refs.extend(resolve_column_wildcard(table_ref, table_ref_index));
+            }
+            Ok(column_refs.into_iter().map(ExprIr::Column).collect())
+        },
+        &SelectProjectionColumn::TableWildcard(ref table_name) => {
+            match table_refs[outer_len..].iter().position(|t| t.name == *table_name) {
+                Some(index) => {
+                    let table_ref_index = outer_len + index;
+                    let column_refs = resolve_column_wildcard(&table_refs[table_ref_index], table_ref_index);
+                    Ok(column_refs.into_iter().map(ExprIr::Column).collect())
+                },
+                None => Err(Error::UnknownTable(table_name.clone()))
+            }
+        },
+        &SelectProjectionColumn::Aliased(..) => {
+            Err(Error::Syntax("Nested column alias".to_string()))
+        }
+    }
+}
+
+// Resolves the projection to a list of expressions and the aliases given to them
+fn resolve_projection_columns(stmt: &SelectStmt, table_refs: &Vec<TableRef>, outer_len: usize, schema: &Schema) -> SqlError<(Vec<ExprIr>, Vec<Option<String>>)> {
+    let mut projections = Vec::new();
+    let mut aliases = Vec::new();
     for column in stmt.projection.iter() {
         match column {
-            &SelectProjectionColumn::Named(ref column_name) => {
-                column_names.push(column_name.clone());
+            &SelectProjectionColumn::Aliased(ref inner, ref alias) => {
+                let mut inner_exprs = try!(resolve_projection_column(inner, table_refs, outer_len, schema));
+                if inner_exprs.len() != 1 {
+                    return Err(Error::Syntax(format!("Cannot alias multiple columns as {}", alias)));
+                }
+                projections.push(inner_exprs.remove(0));
+                aliases.push(Some(alias.clone()));
             },
-            &SelectProjectionColumn::LiteralValue(_) => {
-                return Err("Select LiteralValue not implemented".to_string());
+            _ => {
+                let exprs = try!(resolve_projection_column(column, table_refs, outer_len, schema));
+                aliases.extend(exprs.iter().map(|_| None));
+                projections.extend(exprs);
+            }
+        }
+    }
+    Ok((projections, aliases))
+}
+
+// Describes a result column. Columns keep the name of the column they select,
+// other expressions are anonymous unless aliased.
+fn projection_definition(expr: &ExprIr, alias: Option<String>, table_refs: &[TableRef], schema: &Schema) -> SqlError<ColumnDefinition> {
+    let ctype = try!(infer_type(expr, table_refs, schema));
+    let name = match (alias, expr) {
+        (Some(alias), _) => alias,
+        (None, &ExprIr::Column(ref column_ref)) => column_definition(column_ref, table_refs).name,
+        (None, &ExprIr::Aggregate(ref aggregate)) => aggregate.function.name().to_string(),
+        (None, &ExprIr::Window(ref window)) => window.function.name().to_string(),
+        (None, _) => "?column?".to_string()
+    };
+    Ok(ColumnDefinition {
+        name: name,
+        // An expression that is always NULL has no type of its own
+        ctype: ctype.unwrap_or(ColumnType::Text)
+    })
+}
+
+// Resolves a GROUP BY or ORDER BY item. It can name a column alias or give the
+// position of a result column, anything else is an expression over the tables.
+fn resolve_output_reference(expr: &Expr, clause: &str, projections: &[ExprIr], aliases: &[Option<String>], table_refs: &[TableRef], schema: &Schema) -> SqlError<ExprIr> {
+    match *expr {
+        Expr::Column(ref name) if aliases.iter().any(|a| a.as_ref() == Some(name)) => {
+            let index = aliases.iter().position(|a| a.as_ref() == Some(name)).unwrap();
+            Ok(projections[index].clone())
+        },
+        Expr::LiteralValue(LiteralValue::Integer(position)) => {
+            if position < 1 || position as usize > projections.len() {
+                return Err(Error::Syntax(format!("{} position {} is not in select list", clause, position)));
+            }
+            Ok(projections[position as usize - 1].clone())
+        },
+        ref expr => {
+            let expr = try!(resolve_expr(expr, table_refs, schema));
+            try!(infer_type(&expr, table_refs, schema));
+            Ok(expr)
+        }
+    }
+}
+
+fn resolve_order_by(stmt: &SelectStmt, projections: &[ExprIr], aliases: &[Option<String>], table_refs: &[TableRef], schema: &Schema) -> SqlError<Vec<SortKeyIr>> {
+    let mut sort_keys = Vec::new();
+    for item in stmt.order_by.iter() {
+        let expr = try!(resolve_output_reference(&item.expr, "ORDER BY", projections, aliases, table_refs, schema));
+        sort_keys.push(SortKeyIr {
+            expr: expr,
+            descending: item.descending,
+            nulls_first: item.nulls_first.unwrap_or(item.descending)
+        });
+    }
+    Ok(sort_keys)
+}
+
+fn resolve_group_by(stmt: &SelectStmt, projections: &[ExprIr], aliases: &[Option<String>], table_refs: &[TableRef], schema: &Schema) -> SqlError<Vec<ExprIr>> {
+    let mut group_by = Vec::new();
+    for item in stmt.group_by.iter() {
+        let key = match *item {
+            // Columns of the tables take precedence over aliases
+            Expr::Column(ref name) if resolve_single_column(name, table_refs).is_ok() => {
+                try!(resolve_expr(item, table_refs, schema))
             },
-            &SelectProjectionColumn::Wildcard => {
-                if table_refs.len() != 1 {
-                    return Err("Select wildcard not supported for multiple tables".to_string());
+            ref item => try!(resolve_output_reference(item, "GROUP BY", projections, aliases, table_refs, schema))
+        };
+        try!(reject_aggregates(&key, "GROUP BY"));
+        try!(reject_windows(&key, "GROUP BY"));
+        if !group_by.contains(&key) {
+            group_by.push(key);
+        }
+    }
+    Ok(group_by)
+}
+
+// Rewrites an expression evaluated after aggregation so that it reads the
+// group keys and aggregate values of the aggregated rows. New aggregates are
+// added to the list of aggregates the aggregation computes. Columns of the
+// enclosing queries are constant within a group and are left alone.
+fn rewrite_grouped(expr: ExprIr, group_by: &[ExprIr], aggregates: &mut Vec<AggregateIr>, outer_len: usize, table_refs: &[TableRef]) -> SqlError<ExprIr> {
+    if let Some(index) = group_by.iter().position(|key| *key == expr) {
+        return Ok(ExprIr::Position(index));
+    }
+    match expr {
+        ExprIr::Aggregate(aggregate) => {
+            let aggregate = *aggregate;
+            let index = match aggregates.iter().position(|a| *a == aggregate) {
+                Some(index) => index,
+                None => {
+                    aggregates.push(aggregate);
+                    aggregates.len() - 1
                 }
-                // TODO: A wildcard column should not cause all the other projection columns to be ignored
-                return resolve_column_wildcard(&table_refs[0],0,schema);
+            };
+            Ok(ExprIr::Position(group_by.len() + index))
+        },
+        ExprIr::Column(column_ref) => {
+            if column_ref.table_ref_index < outer_len {
+                return Ok(ExprIr::Column(column_ref));
+            }
+            let column_def = column_definition(&column_ref, table_refs);
+            Err(Error::Grouping(format!("Column {}.{} must appear in the GROUP BY clause or be used in an aggregate function",
+                                        table_refs[column_ref.table_ref_index].name, column_def.name)))
+        },
+        expr => expr.map_children(|child| rewrite_grouped(child, group_by, aggregates, outer_len, table_refs))
+    }
+}
+
+// Replaces the window function calls of an expression by references to their
+// values, which follow the window_base values of the rows they are computed on
+fn extract_windows(expr: ExprIr, window_base: usize, windows: &mut Vec<WindowIr>) -> SqlError<ExprIr> {
+    match expr {
+        ExprIr::Window(window) => {
+            let window = *window;
+            let index = match windows.iter().position(|w| *w == window) {
+                Some(index) => index,
+                None => {
+                    windows.push(window);
+                    windows.len() - 1
+                }
+            };
+            Ok(ExprIr::Position(window_base + index))
+        },
+        expr => expr.map_children(|child| extract_windows(child, window_base, windows))
+    }
+}
+
+// Frame offsets are constants. ROWS offsets count rows, RANGE offsets are
+// added to or subtracted from the value of the single ORDER BY key.
+fn resolve_frame_offset(offset: &Expr, units: FrameUnits, order_by: &[SortKeyIr], table_refs: &[TableRef], schema: &Schema) -> SqlError<ExprIr> {
+    let offset = try!(resolve_expr(offset, &[], schema));
+    try!(reject_aggregates(&offset, "a window frame"));
+    try!(reject_windows(&offset, "a window frame"));
+    let ctype = try!(infer_type(&offset, &[], schema));
+    match units {
+        FrameUnits::Rows => {
+            try!(match ctype {
+                None | Some(ColumnType::Integer) => Ok(()),
+                Some(ctype) => Err(Error::Type(format!("ROWS offset must be an Integer, got {:?}", ctype)))
+            });
+        },
+        FrameUnits::Range => {
+            if order_by.len() != 1 {
+                return Err(Error::Windowing("RANGE with an offset requires exactly one ORDER BY key".to_string()));
+            }
+            let key_type = try!(infer_type(&order_by[0].expr, table_refs, schema));
+            if !is_numeric(key_type) || !is_numeric(ctype) {
+                return Err(Error::Type(format!("RANGE offset of type {:?} cannot be applied to ORDER BY key of type {:?}", ctype, key_type)));
+            }
+        }
+    }
+    Ok(offset)
+}
+
+fn resolve_frame_bound(bound: &FrameBound, units: FrameUnits, order_by: &[SortKeyIr], table_refs: &[TableRef], schema: &Schema) -> SqlError<FrameBoundIr> {
+    Ok(match *bound {
+        FrameBound::UnboundedPreceding => FrameBoundIr::UnboundedPreceding,
+        FrameBound::Preceding(ref offset) => FrameBoundIr::Preceding(try!(resolve_frame_offset(offset, units, order_by, table_refs, schema))),
+        FrameBound::CurrentRow => FrameBoundIr::CurrentRow,
+        FrameBound::Following(ref offset) => FrameBoundIr::Following(try!(resolve_frame_offset(offset, units, order_by, table_refs, schema))),
+        FrameBound::UnboundedFollowing => FrameBoundIr::UnboundedFollowing
+    })
+}
+
+fn resolve_frame(frame: &Option<WindowFrame>, order_by: &[SortKeyIr], table_refs: &[TableRef], schema: &Schema) -> SqlError<FrameIr> {
+    let frame = match *frame {
+        Some(ref frame) => frame,
+        None => {
+            return Ok(FrameIr {
+                units: FrameUnits::Range,
+                start: FrameBoundIr::UnboundedPreceding,
+                end: FrameBoundIr::CurrentRow
+            });
+        }
+    };
+    let start = try!(resolve_frame_bound(&frame.start, frame.units, order_by, table_refs, schema));
+    let end = try!(resolve_frame_bound(&frame.end, frame.units, order_by, table_refs, schema));
+    if start == FrameBoundIr::UnboundedFollowing {
+        return Err(Error::Windowing("Frame start cannot be UNBOUNDED FOLLOWING".to_string()));
+    }
+    if end == FrameBoundIr::UnboundedPreceding {
+        return Err(Error::Windowing("Frame end cannot be UNBOUNDED PRECEDING".to_string()));
+    }
+    if start.position() > end.position() {
+        return Err(Error::Windowing("Frame cannot end before the row it starts from".to_string()));
+    }
+    Ok(FrameIr {
+        units: frame.units,
+        start: start,
+        end: end
+    })
+}
+
+// Resolves a window function call. The expressions it evaluates are resolved
+// against the tables of the query like its projections.
+pub fn resolve_window(function: WindowFunction, args: &[Expr], window: &WindowSpec, table_refs: &[TableRef], schema: &Schema) -> SqlError<WindowIr> {
+    let (min_args, max_args) = match function {
+        WindowFunction::RowNumber | WindowFunction::Rank | WindowFunction::DenseRank => (0, 0),
+        WindowFunction::Lag | WindowFunction::Lead => (1, 3),
+        WindowFunction::FirstValue | WindowFunction::LastValue => (1, 1),
+        WindowFunction::Aggregate(_) => (0, 1)
+    };
+    if args.len() < min_args || args.len() > max_args {
+        return Err(Error::Syntax(format!("{} expects {} to {} arguments, got {}", function.name(), min_args, max_args, args.len())));
+    }
+
+    let resolve = |expr: &Expr| -> SqlError<ExprIr> {
+        let expr = try!(resolve_expr(expr, table_refs, schema));
+        if expr.contains_window() {
+            return Err(Error::Windowing("Window function calls cannot be nested".to_string()));
+        }
+        try!(infer_type(&expr, table_refs, schema));
+        Ok(expr)
+    };
+    let mut resolved_args = Vec::new();
+    for arg in args.iter() {
+        resolved_args.push(try!(resolve(arg)));
+    }
+    let mut partition_by = Vec::new();
+    for key in window.partition_by.iter() {
+        partition_by.push(try!(resolve(key)));
+    }
+    let mut order_by = Vec::new();
+    for item in window.order_by.iter() {
+        order_by.push(SortKeyIr {
+            expr: try!(resolve(&item.expr)),
+            descending: item.descending,
+            nulls_first: item.nulls_first.unwrap_or(item.descending)
+        });
+    }
+    let frame = try!(resolve_frame(&window.frame, &order_by, table_refs, schema));
+
+    Ok(WindowIr {
+        function: function,
+        args: resolved_args,
+        partition_by: partition_by,
+        order_by: order_by,
+        frame: frame
+    })
+}
+
+// Resolves the expression of a LIMIT or OFFSET clause. It is evaluated once
+// before reading any row, so it cannot reference columns.
+pub fn resolve_row_count(expr: &Option<Expr>, clause: &str, schema: &Schema) -> SqlError<Option<ExprIr>> {
+    match *expr {
+        Some(ref expr) => {
+            let expr = try!(resolve_expr(expr, &[], schema));
+            try!(reject_aggregates(&expr, clause));
+            try!(reject_windows(&expr, clause));
+            match try!(infer_type(&expr, &[], schema)) {
+                None | Some(ColumnType::Integer) => Ok(Some(expr)),
+                Some(ctype) => Err(Error::Type(format!("{} must be an Integer, got {:?}", clause, ctype)))
+            }
+        },
+        None => Ok(None)
+    }
+}
+
+// Table names only have to be unique within a query, a subquery can reuse
+// the names of the enclosing queries
+fn add_table_ref(table_ref: TableRef, table_refs: &mut Vec<TableRef>) -> SqlError<usize> {
+    if table_refs.iter().any(|t| t.scope == table_ref.scope && t.name == table_ref.name) {
+        return Err(Error::DuplicateAlias(table_ref.name.clone()));
+    }
+    table_refs.push(table_ref);
+    Ok(table_refs.len() - 1)
+}
+
+// Resolves an item of the FROM clause, adding its tables to table_refs after
+// the outer_len tables of the enclosing queries. The tables of the FROM
+// clause are numbered from the query's own first table.
+// Common tables of enclosing WITH clauses hide the stored tables of the same name
+fn resolve_named_table(table_name: &str, alias: &str, table_refs: &mut Vec<TableRef>, outer_len: usize, scope: usize, schema: &Schema) -> SqlError<FromIr> {
+    let common_table = schema.find_common_table(table_name).map(|(index, table)| (index, table.clone()));
+    match common_table {
+        Some((index, CommonTable::Query{column_names, query, recursive, ..})) => {
+            let (columns, source) = try!(resolve_common_table(table_name, &column_names, &query, recursive,
+                                                              &schema.common_tables_before(index)));
+            let table_ref = TableRef {
+                table_index: None,
+                name: alias.to_string(),
+                columns: columns,
+                scope: scope
+            };
+            let table_ref_index = try!(add_table_ref(table_ref, table_refs)) - outer_len;
+            Ok(match source {
+                CommonTableIr::Query(subquery) => FromIr::Derived(table_ref_index, Box::new(subquery)),
+                CommonTableIr::Recursive(recursive) => FromIr::Recursive(table_ref_index, Box::new(recursive))
+            })
+        },
+        Some((_, CommonTable::WorkingTable{columns, ..})) => {
+            // The rows of the working table are only bound in the FROM clauses
+            // of the recursive term, not in its subqueries
+            if scope > 0 {
+                return Err(Error::Syntax(format!("Recursive reference to {} cannot appear in a subquery", table_name)));
+            }
+            let table_ref = TableRef {
+                table_index: None,
+                name: alias.to_string(),
+                columns: columns,
+                scope: scope
+            };
+            Ok(FromIr::WorkingTable(try!(add_table_ref(table_ref, table_refs)) - outer_len, Rc::new(Vec::new())))
+        },
+        None => {
+            let mut table_ref = try!(resolve_table_reference(table_name, scope, schema));
+            table_ref.name = alias.to_string();
+            Ok(FromIr::Table(try!(add_table_ref(table_ref, table_refs)) - outer_len))
+        }
+    }
+}
+
+// Table functions are called with constants, so their columns are known
+// before any row is read
+fn resolve_table_function(call: &FunctionCall, alias: &str, table_refs: &mut Vec<TableRef>, outer_len: usize, scope: usize, schema: &Schema) -> SqlError<FromIr> {
+    let function = try!(schema.find_table_function_or_err(&call.function_name));
+    let mut args = Vec::new();
+    for arg in call.arguments.iter() {
+        match *arg {
+            FunctionArgument::LiteralValue(ref value) => args.push(value.clone()),
+            FunctionArgument::Identifier(ref name) => {
+                return Err(Error::Syntax(format!("Arguments of {} must be constants, got {}", call.function_name, name)));
             }
         }
     }
-    resolve_column_references(&column_names, &table_refs[..], schema)
+    let table_ref = TableRef {
+        table_index: None,
+        name: alias.to_string(),
+        columns: try!(function.columns(&args)),
+        scope: scope
+    };
+    Ok(FromIr::Function(try!(add_table_ref(table_ref, table_refs)) - outer_len, call.function_name.clone(), args))
+}
+
+fn resolve_from_item(item: &SelectFromTable, table_refs: &mut Vec<TableRef>, outer_len: usize, scope: usize, schema: &Schema) -> SqlError<FromIr> {
+    match item {
+        &SelectFromTable::NamedTable(ref table_name) => {
+            resolve_named_table(table_name, table_name, table_refs, outer_len, scope, schema)
+        },
+        &SelectFromTable::Aliased(ref inner, ref alias) => {
+            match **inner {
+                SelectFromTable::NamedTable(ref table_name) => {
+                    resolve_named_table(table_name, alias, table_refs, outer_len, scope, schema)
+                },
+                SelectFromTable::Subquery(ref query) => {
+                    // A derived table cannot see the other tables of the FROM clause
+                    let subquery = try!(resolve_subquery(query, &table_refs[..outer_len], schema));
+                    let table_ref = TableRef {
+                        table_index: None,
+                        name: alias.clone(),
+                        columns: subquery.query.column_defs().to_vec(),
+                        scope: scope
+                    };
+                    Ok(FromIr::Derived(try!(add_table_ref(table_ref, table_refs)) - outer_len, Box::new(subquery)))
+                },
+                SelectFromTable::Function(ref call) => {
+                    resolve_table_function(call, alias, table_refs, outer_len, scope, schema)
+                },
+                _ => {
+                    Err(Error::Syntax(format!("Cannot alias {:?} as {}", inner, alias)))
+                }
+            }
+        },
+        &SelectFromTable::Subquery(_) => {
+            Err(Error::Syntax("Subquery in FROM must have an alias".to_string()))
+        },
+        &SelectFromTable::Function(ref call) => {
+            resolve_table_function(call, &call.function_name, table_refs, outer_len, scope, schema)
+        },
+        &SelectFromTable::Join(ref join) => {
+            let join_start = table_refs.len();
+            let left = try!(resolve_from_item(&join.left, table_refs, outer_len, scope, schema));
+            let right = try!(resolve_from_item(&join.right, table_refs, outer_len, scope, schema));
+            // The join condition can only see the tables of the join and those
+            // of the enclosing queries, not the tables listed before the join
+            // in the FROM clause
+            let visible: Vec<TableRef> = table_refs[..outer_len].iter().chain(table_refs[join_start..].iter()).cloned().collect();
+            let mut on = Vec::new();
+            for expr in try!(resolve_conjuncts(&join.on, &visible[..], schema)).into_iter() {
+                on.push(try!(renumber_scope_columns(expr, outer_len, join_start - outer_len)));
+            }
+            Ok(FromIr::Join {
+                join_type: join.join_type,
+                left: Box::new(left),
+                right: Box::new(right),
+                on: on
+            })
+        }
+    }
+}
+
+// Renumbers the columns of an expression resolved against the tables in scope
+// of a join condition, moving those of the join's own tables past the tables
+// that were left out
+fn renumber_scope_columns(expr: ExprIr, outer_len: usize, skipped: usize) -> SqlError<ExprIr> {
+    match expr {
+        ExprIr::Column(column_ref) => {
+            let table_ref_index = if column_ref.table_ref_index < outer_len { column_ref.table_ref_index } else { column_ref.table_ref_index + skipped };
+            Ok(ExprIr::Column(ColumnRef {
+                column_index: column_ref.column_index,
+                table_ref_index: table_ref_index
+            }))
+        },
+        expr => expr.map_children(|child| renumber_scope_columns(child, outer_len, skipped))
+    }
+}
+
+// Replaces the columns of the enclosing queries with parameters, adding them
+// to params, and renumbers the columns of the query's own tables
+fn parameterize(expr: ExprIr, outer_len: usize, params: &mut Vec<ExprIr>) -> SqlError<ExprIr> {
+    match expr {
+        ExprIr::Column(column_ref) => {
+            if column_ref.table_ref_index >= outer_len {
+                return Ok(ExprIr::Column(ColumnRef {
+                    column_index: column_ref.column_index,
+                    table_ref_index: column_ref.table_ref_index - outer_len
+                }));
+            }
+            let param = ExprIr::Column(column_ref);
+            let index = match params.iter().position(|p| *p == param) {
+                Some(index) => index,
+                None => {
+                    params.push(param);
+                    params.len() - 1
+                }
+            };
+            Ok(ExprIr::Parameter(index))
+        },
+        expr => expr.map_children(|child| parameterize(child, outer_len, params))
+    }
 }
 
-pub fn ir_from_select_stmt(stmt: &SelectStmt, schema: &Schema) -> SqlError<SelectIr> {
-    // Get all named tables
-    let mut table_refs = Vec::new();
+// Resolves a select that may be nested in other queries. outer holds the
+// tables of the enclosing queries, the columns of which are referenced
+// through the parameters added to params.
+pub fn resolve_select(stmt: &SelectStmt, outer: &[TableRef], params: &mut Vec<ExprIr>, schema: &Schema) -> SqlError<SelectIr> {
+    let outer_len = outer.len();
+    let scope = outer.last().map(|t| t.scope + 1).unwrap_or(0);
+
+    // Resolve the tables, a comma separated list is a series of cross joins
+    let mut table_refs = outer.to_vec();
+    let mut from = None;
     for table in stmt.from.iter() {
-        match table {
-            &SelectFromTable::NamedTable(ref table_name) => {
-                table_refs.push(try!(resolve_table_reference(table_name,schema)));
-            },
-            &SelectFromTable::Function(_) => {
-                return Err("Select from function not implemented".to_string());
+        let item = try!(resolve_from_item(table, &mut table_refs, outer_len, scope, schema));
+        from = Some(match from {
+            None => item,
+            Some(left) => FromIr::Join {
+                join_type: JoinType::Cross,
+                left: Box::new(left),
+                right: Box::new(item),
+                on: Vec::new()
             }
+        });
+    }
+    let from = match from {
+        Some(from) => from,
+        None => {
+            return Err(Error::Syntax("Select without tables".to_string()));
         }
+    };
+
+    let (mut projections, aliases) = try!(resolve_projection_columns(stmt, &table_refs, outer_len, schema));
+    let filters = try!(resolve_conjuncts(&stmt.filter, &table_refs, schema));
+    let group_by = try!(resolve_group_by(stmt, &projections, &aliases, &table_refs, schema));
+    let mut order_by = try!(resolve_order_by(stmt, &projections, &aliases, &table_refs, schema));
+    let limit = try!(resolve_row_count(&stmt.limit, "LIMIT", schema));
+    let offset = try!(resolve_row_count(&stmt.offset, "OFFSET", schema));
+
+    let mut column_defs = Vec::new();
+    for (expr, alias) in projections.iter().zip(aliases.into_iter()) {
+        column_defs.push(try!(projection_definition(expr, alias, &table_refs, schema)));
     }
 
-    let column_refs = try!(resolve_projection_columns(stmt, &table_refs, schema));
+    // A query is grouped by its GROUP BY clause, or as a whole when it uses
+    // aggregates or HAVING without one
+    let grouped = !group_by.is_empty() || stmt.having.is_some() ||
+        projections.iter().any(|p| p.contains_aggregate()) ||
+        order_by.iter().any(|key| key.expr.contains_aggregate());
+    let aggregation = if grouped {
+        let mut aggregates = Vec::new();
+        projections = try!(projections.into_iter().map(|p| {
+            rewrite_grouped(p, &group_by, &mut aggregates, outer_len, &table_refs)
+        }).collect());
+        let mut having = Vec::new();
+        if let Some(ref expr) = stmt.having {
+            let expr = try!(resolve_predicate(expr, &table_refs, schema));
+            try!(reject_windows(&expr, "HAVING"));
+            split_conjuncts(try!(rewrite_grouped(expr, &group_by, &mut aggregates, outer_len, &table_refs)), &mut having);
+        }
+        for key in order_by.iter_mut() {
+            let expr = key.expr.clone();
+            key.expr = try!(rewrite_grouped(expr, &group_by, &mut aggregates, outer_len, &table_refs));
+        }
+        Some(AggregationIr {
+            group_by: group_by,
+            aggregates: aggregates,
+            having: having
+        })
+    }
+    else {
+        None
+    };
 
+    // Window values follow the columns of the tables, or the group keys and
+    // aggregate values of grouped queries
+    let window_base = match aggregation {
+        Some(ref aggregation) => aggregation.group_by.len() + aggregation.aggregates.len(),
+        None => table_refs[outer_len..].iter().fold(0, |width, table_ref| width + table_ref.columns.len())
+    };
+    let mut windows = Vec::new();
+    projections = try!(projections.into_iter().map(|p| extract_windows(p, window_base, &mut windows)).collect());
+    for key in order_by.iter_mut() {
+        let expr = key.expr.clone();
+        key.expr = try!(extract_windows(expr, window_base, &mut windows));
+    }
 
-    Ok(SelectIr {
-        columns: column_refs,
-        tables: table_refs
-    })
+    // Duplicates are removed from the result rows, which can then only be
+    // sorted on the values they hold
+    if stmt.distinct {
+        for key in order_by.iter_mut() {
+            match projections.iter().position(|p| *p == key.expr) {
+                Some(index) => {
+                    key.expr = ExprIr::Position(index);
+                },
+                None => {
+                    return Err(Error::Syntax("For SELECT DISTINCT, ORDER BY expressions must appear in select list".to_string()));
+                }
+            }
+        }
+    }
+
+    let ir = SelectIr {
+        distinct: stmt.distinct,
+        projections: projections,
+        column_defs: column_defs,
+        tables: table_refs.split_off(outer_len),
+        from: from,
+        filters: filters,
+        aggregation: aggregation,
+        windows: windows,
+        order_by: order_by,
+        limit: limit,
+        offset: offset
+    };
+    ir.map_exprs(&mut |expr: ExprIr| parameterize(expr, outer_len, params))
 }
@@ -0,0 +1,46 @@
+use definitions::*;
+use schema::*;
+use super::{TableRef,ColumnRef,ExprIr,resolve_table_reference,resolve_column_references,check_assignment,column_definition};
+use super::expr_ir::{resolve_expr,resolve_conjuncts};
+
+pub struct UpdateIr {
+    pub table: TableRef,
+    pub columns: Vec<ColumnRef>,
+    pub column_defs: Vec<ColumnDefinition>,
+    pub values: Vec<ExprIr>,
+    pub filters: Vec<ExprIr>
+}
+
+pub fn ir_from_update_stmt(stmt: &UpdateStmt, schema: &Schema) -> SqlError<UpdateIr> {
+    let table_ref = try!(resolve_table_reference(&stmt.table_name, 0, schema));
+    let table_refs = vec![table_ref.clone()];
+
+    // A column can only be assigned once
+    let column_names: Vec<String> = stmt.assignments.iter().map(|a| a.column_name.clone()).collect();
+    for (index, name) in column_names.iter().enumerate() {
+        if column_names[..index].contains(name) {
+            return Err(Error::DuplicateColumn(name.clone()));
+        }
+    }
+    let column_refs = try!(resolve_column_references(&column_names, &table_refs[..]));
+
+    let mut values = Vec::new();
+    let mut column_defs = Vec::new();
+    for (assignment, column_ref) in stmt.assignments.iter().zip(column_refs.iter()) {
+        let column_def = column_definition(column_ref, &table_refs[..]);
+        let value = try!(resolve_expr(&assignment.value, &table_refs[..], schema));
+        try!(check_assignment(&value, &column_def, &table_refs[..], schema));
+        values.push(value);
+        column_defs.push(column_def);
+    }
+
+    let filters = try!(resolve_conjuncts(&stmt.filter, &table_refs[..], schema));
+
+    Ok(UpdateIr {
+        table: table_ref,
+        columns: column_refs,
+        column_defs: column_defs,
+        values: values,
+        filters: filters
+    })
+}
@@ -0,0 +1,42 @@
+#![feature(plugin,drain)]
+#![plugin(peg_syntax_ext)]
+#![allow(dead_code)]
+
+extern crate bit_vec;
+extern crate libc;
+
+pub use definitions::*;
+pub use error::Error;
+pub use engine::SqlEngine;
+pub use cursor::Cursor;
+pub use plan::PlanOptions;
+pub use functions::{TableFunction,FunctionRows};
+pub use tables::{Table,RowId};
+
+mod tests;
+mod engine;
+mod tables;
+mod functions;
+mod definitions;
+mod error;
+mod plan;
+mod schema;
+mod storage;
+mod ir;
+mod cursor;
+
+peg_file! sql_parse("sql.rustpeg");
+
+// Parses a single SQL statement
+pub fn parse(sql: &str) -> SqlError<SqlStmt> {
+    sql_parse::sql_expression(sql).map_err(|e| {
+        let mut expected: Vec<String> = e.expected.iter().map(|s| s.to_string()).collect();
+        expected.sort();
+        Error::Parse {
+            line: e.line,
+            column: e.column,
+            offset: e.offset,
+            expected: expected
+        }
+    })
+}
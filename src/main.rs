@@ -1,49 +1,68 @@
-#![feature(plugin,drain)]
-#![plugin(peg_syntax_ext)]
-#![allow(dead_code)]
+extern crate rustql;
+extern crate libc;
 
-extern crate bit_vec;
+use std::io;
+use std::env;
+use std::process;
 
-use sql_parse::sql_expression;
-use std::io::BufRead;
-use engine::*;
+mod repl;
 
-mod tests;
-mod engine;
-mod tables;
-mod definitions;
-mod plan;
-mod schema;
-mod ir;
+use rustql::SqlEngine;
 
-peg_file! sql_parse("sql.rustpeg");
+use repl::output::OutputMode;
 
+const USAGE: &'static str = "Usage: rustql [-m|--mode table|csv|tsv|json|line] [FILE]";
+
+fn stdin_is_interactive() -> bool {
+    unsafe { libc::isatty(libc::STDIN_FILENO) != 0 }
+}
+
+fn parse_mode(name: &str) -> OutputMode {
+    match OutputMode::from_name(name) {
+        Some(mode) => mode,
+        None => {
+            println!("Unknown output mode {}. Available modes: {}", name, OutputMode::names());
+            process::exit(1);
+        }
+    }
+}
 
 fn main() {
-    let mut engine = SqlEngine::new();
-    let io = std::io::stdin();
-
-    loop {
-        let mut statement_bytes = Vec::new();
-        let _ = io.lock().read_until(b';', &mut statement_bytes);
-        // Get rid of semicolon
-        let _ = statement_bytes.pop();
-        let statement_string = String::from_utf8(statement_bytes);
-
-        let expr_result = sql_expression(&statement_string.unwrap().trim());
-        match expr_result {
-            Ok(stmt) => {
-                match engine.excecute_stmt(stmt) {
-                    Ok(_) => {},
-                    Err(e) => {
-                        println!("Error: {}",e);
+    let mut mode = OutputMode::Table;
+    let mut path = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "-m" | "--mode" => {
+                match args.next() {
+                    Some(name) => mode = parse_mode(&name),
+                    None => {
+                        println!("{}", USAGE);
+                        process::exit(1);
                     }
                 }
             },
-            Err(e) => {
-                println!("Error: {:?}",e);
+            _ if path.is_none() && !arg.starts_with("-") => {
+                path = Some(arg.clone());
+            },
+            _ => {
+                println!("{}", USAGE);
+                process::exit(1);
             }
         }
     }
 
+    let engine = match path {
+        Some(path) => match SqlEngine::open(&path) {
+            Ok(engine) => engine,
+            Err(e) => {
+                println!("Error: Cannot open {}: {}", path, e);
+                process::exit(1);
+            }
+        },
+        None => SqlEngine::new()
+    };
+    let mut repl = repl::Repl::new(engine, mode);
+    let stdin = io::stdin();
+    repl.run(stdin.lock(), stdin_is_interactive());
 }
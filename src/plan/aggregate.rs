@@ -0,0 +1,379 @@
+use definitions::*;
+use schema::*;
+use ir::expr_ir::AggregateIr;
+use std::cmp::Ordering;
+use std::collections::{HashMap,HashSet,VecDeque};
+use std::mem::replace;
+use super::{RowSource,HashKey,PlanOptions};
+use super::expr::{BoundExpr,bind_expr,compare,arithmetic};
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct BoundAggregate {
+    function: AggregateFunction,
+    distinct: bool,
+    arg: Option<BoundExpr>
+}
+
+pub fn bind_aggregates(aggregates: &[AggregateIr], table_offsets: &[usize], options: &PlanOptions) -> SqlError<Vec<BoundAggregate>> {
+    aggregates.iter().map(|aggregate| {
+        let arg = match aggregate.arg {
+            Some(ref arg) => Some(try!(bind_expr(arg, table_offsets, 0, options))),
+            None => None
+        };
+        Ok(BoundAggregate {
+            function: aggregate.function,
+            distinct: aggregate.distinct,
+            arg: arg
+        })
+    }).collect()
+}
+
+// The running state of an aggregate function over the rows of a group. NULL
+// arguments are ignored, so a group without any other value yields NULL, or 0
+// for COUNT.
+pub struct Accumulator {
+    count: i64,
+    value: LiteralValue,
+    // The arguments seen so far by a DISTINCT aggregate
+    seen: Option<HashSet<HashKey>>
+}
+
+impl Accumulator {
+    pub fn new(aggregate: &BoundAggregate) -> Accumulator {
+        Accumulator {
+            count: 0,
+            value: LiteralValue::Null,
+            seen: if aggregate.distinct { Some(HashSet::new()) } else { None }
+        }
+    }
+
+    pub fn add(&mut self, aggregate: &BoundAggregate, row: &[LiteralValue], schema: &Schema) -> SqlError<()> {
+        let value = match aggregate.arg {
+            Some(ref arg) => try!(arg.evaluate(row, schema)),
+            // COUNT(*) counts rows
+            None => {
+                self.count += 1;
+                return Ok(());
+            }
+        };
+        if value == LiteralValue::Null {
+            return Ok(());
+        }
+        if let Some(ref mut seen) = self.seen {
+            if !seen.insert(HashKey::from_value(&value)) {
+                return Ok(());
+            }
+        }
+        self.count += 1;
+        let replace_value = match aggregate.function {
+            AggregateFunction::Count => false,
+            AggregateFunction::Sum | AggregateFunction::Avg => {
+                if self.value != LiteralValue::Null {
+                    self.value = try!(arithmetic(BinaryOperator::Add, &self.value, &value));
+                    false
+                }
+                else {
+                    true
+                }
+            },
+            AggregateFunction::Min => self.value == LiteralValue::Null || try!(compare(&value, &self.value)) == Some(Ordering::Less),
+            AggregateFunction::Max => self.value == LiteralValue::Null || try!(compare(&value, &self.value)) == Some(Ordering::Greater)
+        };
+        if replace_value {
+            self.value = value;
+        }
+        Ok(())
+    }
+
+    // Takes the value of a row added before back out of a COUNT, SUM or AVG
+    // that is not DISTINCT
+    pub fn remove(&mut self, aggregate: &BoundAggregate, row: &[LiteralValue], schema: &Schema) -> SqlError<()> {
+        if aggregate.distinct {
+            return Err(Error::Internal("Values cannot be removed from a DISTINCT aggregate".to_string()));
+        }
+        let value = match aggregate.arg {
+            Some(ref arg) => try!(arg.evaluate(row, schema)),
+            None => {
+                self.count -= 1;
+                return Ok(());
+            }
+        };
+        if value == LiteralValue::Null {
+            return Ok(());
+        }
+        self.count -= 1;
+        match aggregate.function {
+            AggregateFunction::Count => {},
+            AggregateFunction::Sum | AggregateFunction::Avg => {
+                // Without any value left the sum is NULL again
+                self.value = if self.count == 0 { LiteralValue::Null } else { try!(arithmetic(BinaryOperator::Subtract, &self.value, &value)) };
+            },
+            AggregateFunction::Min | AggregateFunction::Max => {
+                return Err(Error::Internal("Values cannot be removed from a MIN or MAX aggregate".to_string()));
+            }
+        }
+        Ok(())
+    }
+
+    // The value of the aggregate over the rows added so far
+    pub fn finish(&self, aggregate: &BoundAggregate) -> LiteralValue {
+        match aggregate.function {
+            AggregateFunction::Count => LiteralValue::Integer(self.count),
+            AggregateFunction::Sum | AggregateFunction::Min | AggregateFunction::Max => self.value.clone(),
+            AggregateFunction::Avg => {
+                match self.value {
+                    LiteralValue::Integer(sum) => LiteralValue::Real(sum as f64 / self.count as f64),
+                    LiteralValue::Real(sum) => LiteralValue::Real(sum / self.count as f64),
+                    ref value => value.clone()
+                }
+            }
+        }
+    }
+}
+
+// The state of an aggregate function over a window frame moving forward
+// through a partition, rows entering it at its end and leaving it at its start
+// in the same order. COUNT, SUM and AVG take the values of leaving rows back
+// out. MIN and MAX keep the values no later value beats, the first of them
+// being the result, so a leaving row can only be the first one.
+pub struct FrameAccumulator {
+    accumulator: Accumulator,
+    // The values that may still become the MIN or MAX, each with the position
+    // of its row
+    extremes: VecDeque<(usize, LiteralValue)>,
+    // Set once a value would be taken back out of a REAL sum. Small values
+    // added next to large ones are lost, so the sum is taken again over the
+    // rows of each frame from then on.
+    resums: bool
+}
+
+impl FrameAccumulator {
+    pub fn new(aggregate: &BoundAggregate) -> FrameAccumulator {
+        FrameAccumulator {
+            accumulator: Accumulator::new(aggregate),
+            extremes: VecDeque::new(),
+            resums: false
+        }
+    }
+
+    fn keeps_extremes(aggregate: &BoundAggregate) -> bool {
+        aggregate.function == AggregateFunction::Min || aggregate.function == AggregateFunction::Max
+    }
+
+    // Adds the row entering the frame at the given position in the partition
+    pub fn add(&mut self, aggregate: &BoundAggregate, position: usize, row: &[LiteralValue], schema: &Schema) -> SqlError<()> {
+        if self.resums {
+            return Ok(());
+        }
+        if !FrameAccumulator::keeps_extremes(aggregate) {
+            return self.accumulator.add(aggregate, row, schema);
+        }
+        let value = match aggregate.arg {
+            Some(ref arg) => try!(arg.evaluate(row, schema)),
+            None => LiteralValue::Null
+        };
+        if value == LiteralValue::Null {
+            return Ok(());
+        }
+        // Values the new one beats can no longer be the result, equal ones
+        // come first and stay it
+        let beats = if aggregate.function == AggregateFunction::Min { Ordering::Less } else { Ordering::Greater };
+        loop {
+            let beaten = match self.extremes.back() {
+                Some(&(_, ref last)) => try!(compare(&value, last)) == Some(beats),
+                None => false
+            };
+            if !beaten {
+                break;
+            }
+            self.extremes.pop_back();
+        }
+        self.extremes.push_back((position, value));
+        Ok(())
+    }
+
+    // Removes the row leaving the frame, the first of those added
+    pub fn remove(&mut self, aggregate: &BoundAggregate, position: usize, row: &[LiteralValue], schema: &Schema) -> SqlError<()> {
+        if self.resums {
+            return Ok(());
+        }
+        if !FrameAccumulator::keeps_extremes(aggregate) {
+            if let LiteralValue::Real(_) = self.accumulator.value {
+                self.resums = true;
+                return Ok(());
+            }
+            return self.accumulator.remove(aggregate, row, schema);
+        }
+        if self.extremes.front().map(|&(first, _)| first == position).unwrap_or(false) {
+            self.extremes.pop_front();
+        }
+        Ok(())
+    }
+
+    // The value of the aggregate over the rows in the frame
+    pub fn finish(&self, aggregate: &BoundAggregate, frame: &[Vec<LiteralValue>], schema: &Schema) -> SqlError<LiteralValue> {
+        if self.resums {
+            let mut accumulator = Accumulator::new(aggregate);
+            for row in frame.iter() {
+                try!(accumulator.add(aggregate, row, schema));
+            }
+            return Ok(accumulator.finish(aggregate));
+        }
+        if !FrameAccumulator::keeps_extremes(aggregate) {
+            return Ok(self.accumulator.finish(aggregate));
+        }
+        Ok(match self.extremes.front() {
+            Some(&(_, ref value)) => value.clone(),
+            None => LiteralValue::Null
+        })
+    }
+}
+
+struct Group {
+    key_values: Vec<LiteralValue>,
+    accumulators: Vec<Accumulator>
+}
+
+impl Group {
+    fn new(key_values: Vec<LiteralValue>, aggregates: &[BoundAggregate]) -> Group {
+        Group {
+            key_values: key_values,
+            accumulators: aggregates.iter().map(Accumulator::new).collect()
+        }
+    }
+
+    fn add(&mut self, aggregates: &[BoundAggregate], row: &[LiteralValue], schema: &Schema) -> SqlError<()> {
+        for (accumulator, aggregate) in self.accumulators.iter_mut().zip(aggregates.iter()) {
+            try!(accumulator.add(aggregate, row, schema));
+        }
+        Ok(())
+    }
+
+    // The aggregated row: the group keys followed by the aggregate values
+    fn finish(self, aggregates: &[BoundAggregate]) -> Vec<LiteralValue> {
+        let mut row = self.key_values;
+        for (accumulator, aggregate) in self.accumulators.into_iter().zip(aggregates.iter()) {
+            row.push(accumulator.finish(aggregate));
+        }
+        row
+    }
+}
+
+// Evaluates the group keys of a row. NULL keys compare equal to each other so
+// all rows with NULL keys form a single group.
+fn evaluate_keys(keys: &[BoundExpr], row: &[LiteralValue], schema: &Schema) -> SqlError<(Vec<HashKey>, Vec<LiteralValue>)> {
+    let mut values = Vec::with_capacity(keys.len());
+    for key in keys.iter() {
+        values.push(try!(key.evaluate(row, schema)));
+    }
+    Ok((values.iter().map(HashKey::from_value).collect(), values))
+}
+
+// Groups its whole input in a hash table before producing one row per group,
+// in the order the groups were first seen.
+pub struct HashAggregate {
+    source: Box<RowSource>,
+    keys: Vec<BoundExpr>,
+    aggregates: Vec<BoundAggregate>,
+    prepared: bool,
+    // Aggregated rows in reverse order so the next one can be popped
+    rows: Vec<Vec<LiteralValue>>
+}
+
+impl HashAggregate {
+    pub fn new(source: Box<RowSource>, keys: Vec<BoundExpr>, aggregates: Vec<BoundAggregate>) -> HashAggregate {
+        HashAggregate {
+            source: source,
+            keys: keys,
+            aggregates: aggregates,
+            prepared: false,
+            rows: Vec::new()
+        }
+    }
+
+    fn prepare(&mut self, schema: &Schema) -> SqlError<()> {
+        let mut groups = Vec::new();
+        let mut group_indexes = HashMap::new();
+        while let Some(row) = try!(self.source.get_next_row(schema)) {
+            let (hash_keys, key_values) = try!(evaluate_keys(&self.keys, &row, schema));
+            let index = match group_indexes.get(&hash_keys) {
+                Some(&index) => index,
+                None => groups.len()
+            };
+            if index == groups.len() {
+                groups.push(Group::new(key_values, &self.aggregates));
+                group_indexes.insert(hash_keys, index);
+            }
+            try!(groups[index].add(&self.aggregates, &row, schema));
+        }
+        // Without group keys the whole input is a single group, even when empty
+        if self.keys.is_empty() && groups.is_empty() {
+            groups.push(Group::new(Vec::new(), &self.aggregates));
+        }
+
+        let aggregates = &self.aggregates;
+        self.rows = groups.into_iter().rev().map(|group| group.finish(aggregates)).collect();
+        self.prepared = true;
+        Ok(())
+    }
+}
+
+impl RowSource for HashAggregate {
+    fn get_next_row(&mut self, schema: &Schema) -> SqlError<Option<Vec<LiteralValue>>> {
+        if !self.prepared {
+            try!(self.prepare(schema));
+        }
+        Ok(self.rows.pop())
+    }
+}
+
+// Aggregates an input that is sorted on the group keys, so that the rows of a
+// group are consecutive. Only the current group is held in memory.
+pub struct StreamAggregate {
+    source: Box<RowSource>,
+    keys: Vec<BoundExpr>,
+    aggregates: Vec<BoundAggregate>,
+    current: Option<(Vec<HashKey>, Group)>,
+    done: bool
+}
+
+impl StreamAggregate {
+    pub fn new(source: Box<RowSource>, keys: Vec<BoundExpr>, aggregates: Vec<BoundAggregate>) -> StreamAggregate {
+        StreamAggregate {
+            source: source,
+            keys: keys,
+            aggregates: aggregates,
+            current: None,
+            done: false
+        }
+    }
+}
+
+impl RowSource for StreamAggregate {
+    fn get_next_row(&mut self, schema: &Schema) -> SqlError<Option<Vec<LiteralValue>>> {
+        if self.done {
+            return Ok(None);
+        }
+        while let Some(row) = try!(self.source.get_next_row(schema)) {
+            let (hash_keys, key_values) = try!(evaluate_keys(&self.keys, &row, schema));
+            if let Some((ref current_keys, ref mut group)) = self.current {
+                if *current_keys == hash_keys {
+                    try!(group.add(&self.aggregates, &row, schema));
+                    continue;
+                }
+            }
+            let mut group = Group::new(key_values, &self.aggregates);
+            try!(group.add(&self.aggregates, &row, schema));
+            if let Some((_, finished)) = replace(&mut self.current, Some((hash_keys, group))) {
+                return Ok(Some(finished.finish(&self.aggregates)));
+            }
+        }
+        self.done = true;
+        match self.current.take() {
+            Some((_, group)) => Ok(Some(group.finish(&self.aggregates))),
+            // Without group keys the whole input is a single group, even when empty
+            None if self.keys.is_empty() => Ok(Some(Group::new(Vec::new(), &self.aggregates).finish(&self.aggregates))),
+            None => Ok(None)
+        }
+    }
+}
@@ -0,0 +1,41 @@
+use definitions::*;
+use schema::*;
+use tables::RowId;
+use ir::delete_ir::*;
+use super::PlanOptions;
+use super::expr::{BoundExpr,bind_exprs,row_matches_filters};
+
+pub struct DeletePlan {
+    table_index: usize,
+    filters: Vec<BoundExpr>,
+    // Whether subqueries read tables while the rows are deleted
+    reads_tables: bool
+}
+impl DeletePlan {
+    pub fn run(&mut self, schema: &mut Schema) -> SqlError<usize> {
+        let filters = &self.filters;
+        // Subqueries see the tables as they were before the delete
+        let snapshot = if self.reads_tables { schema.snapshot() } else { Schema::new() };
+
+        schema.map_on_table_mut(self.table_index, |table| {
+            let mut count = 0usize;
+            let rowids: Vec<RowId> = try!(table.scan()).collect();
+            for rowid in rowids {
+                let row = try!(table.get_row(rowid));
+                if try!(row_matches_filters(filters, &row, &snapshot)) {
+                    try!(table.delete(rowid));
+                    count += 1;
+                }
+            }
+            Ok(count)
+        })
+    }
+}
+
+pub fn build_delete_plan(delete_ir: &DeleteIr, _schema: &Schema, options: &PlanOptions) -> SqlError<DeletePlan> {
+    Ok(DeletePlan {
+        table_index: try!(delete_ir.table.stored_table_index()),
+        filters: try!(bind_exprs(&delete_ir.filters, &[0], 0, options)),
+        reads_tables: delete_ir.filters.iter().any(|e| e.contains_subquery())
+    })
+}
@@ -0,0 +1,521 @@
+use definitions::*;
+use schema::*;
+use ir::*;
+use ir::query_ir::SubqueryIr;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::{HashMap,HashSet};
+use std::iter::repeat;
+use std::rc::Rc;
+use super::{PlanOptions,HashKey};
+use super::select_plan::build_query_plan;
+
+// An expression whose columns have been mapped to positions in the rows it is evaluated against
+#[derive(Debug,Clone,PartialEq)]
+pub enum BoundExpr {
+    Position(usize),
+    LiteralValue(LiteralValue),
+    Unary(UnaryOperator, Box<BoundExpr>),
+    Binary(Box<BoundExpr>, BinaryOperator, Box<BoundExpr>),
+    IsNull {
+        expr: Box<BoundExpr>,
+        negated: bool
+    },
+    Between {
+        expr: Box<BoundExpr>,
+        negated: bool,
+        low: Box<BoundExpr>,
+        high: Box<BoundExpr>
+    },
+    InList {
+        expr: Box<BoundExpr>,
+        negated: bool,
+        list: Vec<BoundExpr>
+    },
+    Like {
+        expr: Box<BoundExpr>,
+        negated: bool,
+        pattern: Box<BoundExpr>
+    },
+    Subquery(Box<BoundSubquery>),
+    InSubquery {
+        expr: Box<BoundExpr>,
+        negated: bool,
+        subquery: Box<BoundSubquery>
+    }
+}
+
+#[derive(Debug,Clone,Copy,PartialEq)]
+enum SubqueryKind {
+    Scalar,
+    Exists,
+    In
+}
+
+// What a subquery produced for a set of parameter values
+#[derive(Debug,Clone,PartialEq)]
+enum SubqueryResult {
+    Value(LiteralValue),
+    Values(SubqueryValues)
+}
+
+// The values returned by an IN subquery, kept so that matching a value gives
+// what comparing it with = to each of them would
+#[derive(Debug,Clone,PartialEq)]
+struct SubqueryValues {
+    // The values other than NULL and NaN
+    keys: HashSet<HashKey>,
+    // The first value of each kind, Bool, number or Text, a value of another
+    // kind cannot be compared with
+    samples: Vec<LiteralValue>,
+    // Whether any value was NULL or NaN, which equal nothing but make the
+    // comparison unknown
+    has_null: bool,
+    has_nan: bool
+}
+
+// A subquery that is planned and run when its result is needed. Results are
+// kept by parameter values, so an uncorrelated subquery only runs once and a
+// correlated one once per distinct set of outer values.
+#[derive(Debug,Clone,PartialEq)]
+pub struct BoundSubquery {
+    kind: SubqueryKind,
+    subquery: SubqueryIr,
+    params: Vec<BoundExpr>,
+    options: PlanOptions,
+    results: RefCell<HashMap<Vec<HashKey>, Rc<SubqueryResult>>>
+}
+
+impl BoundSubquery {
+    fn new(kind: SubqueryKind, subquery: &SubqueryIr, table_offsets: &[usize], base: usize, options: &PlanOptions) -> SqlError<BoundSubquery> {
+        Ok(BoundSubquery {
+            kind: kind,
+            subquery: subquery.clone(),
+            params: try!(bind_exprs(&subquery.params, table_offsets, base, options)),
+            options: options.clone(),
+            results: RefCell::new(HashMap::new())
+        })
+    }
+
+    fn result(&self, row: &[LiteralValue], schema: &Schema) -> SqlError<Rc<SubqueryResult>> {
+        let mut values = Vec::with_capacity(self.params.len());
+        for param in self.params.iter() {
+            values.push(try!(param.evaluate(row, schema)));
+        }
+        let key: Vec<HashKey> = values.iter().map(HashKey::from_value).collect();
+        if let Some(result) = self.results.borrow().get(&key) {
+            return Ok(result.clone());
+        }
+
+        let query = try!(self.subquery.bind_parameters(&values));
+        let mut plan = try!(build_query_plan(&query, schema, &self.options));
+        let result = match self.kind {
+            SubqueryKind::Scalar => {
+                let value = match try!(plan.get_next_row(schema)) {
+                    Some(mut row) => row.remove(0),
+                    None => LiteralValue::Null
+                };
+                if try!(plan.get_next_row(schema)).is_some() {
+                    return Err(Error::Cardinality("More than one row returned by a subquery used as an expression".to_string()));
+                }
+                SubqueryResult::Value(value)
+            },
+            // Only the first row is needed to know whether there are any
+            SubqueryKind::Exists => SubqueryResult::Value(LiteralValue::Bool(try!(plan.get_next_row(schema)).is_some())),
+            SubqueryKind::In => {
+                let mut values = SubqueryValues {
+                    keys: HashSet::new(),
+                    samples: Vec::new(),
+                    has_null: false,
+                    has_nan: false
+                };
+                while let Some(mut row) = try!(plan.get_next_row(schema)) {
+                    let value = row.swap_remove(0);
+                    match value {
+                        LiteralValue::Null => {
+                            values.has_null = true;
+                            continue;
+                        },
+                        LiteralValue::Real(r) if r.is_nan() => {
+                            values.has_nan = true;
+                        },
+                        _ => {
+                            values.keys.insert(HashKey::from_value(&value));
+                        }
+                    }
+                    if !values.samples.iter().any(|sample| same_kind(sample, &value)) {
+                        values.samples.push(value);
+                    }
+                }
+                SubqueryResult::Values(values)
+            }
+        };
+        let result = Rc::new(result);
+        self.results.borrow_mut().insert(key, result.clone());
+        Ok(result)
+    }
+}
+
+// Maps the columns referenced by an expression to positions in a row made of
+// the columns of each table ref in order, starting with the table ref at offset
+// base. table_offsets holds the position of the first column of each table ref.
+pub fn bind_expr(expr: &ExprIr, table_offsets: &[usize], base: usize, options: &PlanOptions) -> SqlError<BoundExpr> {
+    let bind = |expr: &ExprIr| bind_expr(expr, table_offsets, base, options).map(Box::new);
+    Ok(match *expr {
+        ExprIr::Column(ref column_ref) => {
+            BoundExpr::Position(table_offsets[column_ref.table_ref_index] + column_ref.column_index - base)
+        },
+        ExprIr::LiteralValue(ref value) => BoundExpr::LiteralValue(value.clone()),
+        ExprIr::Unary(operator, ref expr) => BoundExpr::Unary(operator, try!(bind(expr))),
+        ExprIr::Binary(ref left, operator, ref right) => BoundExpr::Binary(try!(bind(left)), operator, try!(bind(right))),
+        ExprIr::IsNull{ref expr, negated} => BoundExpr::IsNull { expr: try!(bind(expr)), negated: negated },
+        ExprIr::Between{ref expr, negated, ref low, ref high} => {
+            BoundExpr::Between { expr: try!(bind(expr)), negated: negated, low: try!(bind(low)), high: try!(bind(high)) }
+        },
+        ExprIr::InList{ref expr, negated, ref list} => {
+            BoundExpr::InList {
+                expr: try!(bind(expr)),
+                negated: negated,
+                list: try!(bind_exprs(list, table_offsets, base, options))
+            }
+        },
+        ExprIr::Like{ref expr, negated, ref pattern} => {
+            BoundExpr::Like { expr: try!(bind(expr)), negated: negated, pattern: try!(bind(pattern)) }
+        },
+        ExprIr::Position(position) => BoundExpr::Position(position),
+        ExprIr::Aggregate(_) => {
+            return Err(Error::Internal("Aggregate functions are computed by the aggregate operators".to_string()));
+        },
+        ExprIr::Window(_) => {
+            return Err(Error::Internal("Window functions are computed by the window operators".to_string()));
+        },
+        ExprIr::Parameter(_) => {
+            return Err(Error::Internal("Parameters are replaced by their values before a subquery is planned".to_string()));
+        },
+        ExprIr::Subquery(ref subquery) => {
+            BoundExpr::Subquery(Box::new(try!(BoundSubquery::new(SubqueryKind::Scalar, subquery, table_offsets, base, options))))
+        },
+        ExprIr::InSubquery{ref expr, negated, ref subquery} => {
+            BoundExpr::InSubquery {
+                expr: try!(bind(expr)),
+                negated: negated,
+                subquery: Box::new(try!(BoundSubquery::new(SubqueryKind::In, subquery, table_offsets, base, options)))
+            }
+        },
+        ExprIr::Exists(ref subquery) => {
+            BoundExpr::Subquery(Box::new(try!(BoundSubquery::new(SubqueryKind::Exists, subquery, table_offsets, base, options))))
+        }
+    })
+}
+
+pub fn bind_exprs(exprs: &[ExprIr], table_offsets: &[usize], base: usize, options: &PlanOptions) -> SqlError<Vec<BoundExpr>> {
+    exprs.iter().map(|expr| bind_expr(expr, table_offsets, base, options)).collect()
+}
+
+// Truth values follow SQL three-valued logic, with NULL standing for unknown
+fn truth_value(value: LiteralValue) -> SqlError<Option<bool>> {
+    match value {
+        LiteralValue::Null => Ok(None),
+        LiteralValue::Bool(b) => Ok(Some(b)),
+        value => Err(Error::Type(format!("Expected a boolean, got {:?}", value)))
+    }
+}
+
+fn from_truth_value(value: Option<bool>) -> LiteralValue {
+    match value {
+        Some(b) => LiteralValue::Bool(b),
+        None => LiteralValue::Null
+    }
+}
+
+fn negate_if(value: Option<bool>, negated: bool) -> Option<bool> {
+    value.map(|b| b != negated)
+}
+
+pub fn compare(left: &LiteralValue, right: &LiteralValue) -> SqlError<Option<Ordering>> {
+    if *left == LiteralValue::Null || *right == LiteralValue::Null {
+        return Ok(None);
+    }
+    let numbers = ColumnType::accomodate_literal(left).widen(ColumnType::accomodate_literal(right)).is_some();
+    match left.compare(right) {
+        Some(ordering) => Ok(Some(ordering)),
+        // NaN does not compare with any number, treat it as unknown
+        None if numbers => Ok(None),
+        None => Err(Error::Type(format!("Cannot compare {:?} with {:?}", left, right)))
+    }
+}
+
+// Whether two values other than NULL are both Bool, numbers or Text
+fn same_kind(left: &LiteralValue, right: &LiteralValue) -> bool {
+    match (left, right) {
+        (&LiteralValue::Bool(_), &LiteralValue::Bool(_)) | (&LiteralValue::Text(_), &LiteralValue::Text(_)) => true,
+        (&LiteralValue::Integer(_), &LiteralValue::Integer(_)) | (&LiteralValue::Integer(_), &LiteralValue::Real(_)) |
+        (&LiteralValue::Real(_), &LiteralValue::Integer(_)) | (&LiteralValue::Real(_), &LiteralValue::Real(_)) => true,
+        _ => false
+    }
+}
+
+fn comparison(operator: BinaryOperator, ordering: Ordering) -> bool {
+    match operator {
+        BinaryOperator::Equals => ordering == Ordering::Equal,
+        BinaryOperator::NotEquals => ordering != Ordering::Equal,
+        BinaryOperator::Less => ordering == Ordering::Less,
+        BinaryOperator::LessEquals => ordering != Ordering::Greater,
+        BinaryOperator::Greater => ordering == Ordering::Greater,
+        BinaryOperator::GreaterEquals => ordering != Ordering::Less,
+        _ => false
+    }
+}
+
+fn numeric_operand(operator: BinaryOperator, value: &LiteralValue) -> SqlError<f64> {
+    match *value {
+        LiteralValue::Integer(i) => Ok(i as f64),
+        LiteralValue::Real(r) => Ok(r),
+        _ => Err(Error::Type(format!("Operator {} expects numeric operands, got {:?}", operator.symbol(), value)))
+    }
+}
+
+// Integer arithmetic is checked for overflow, anything involving a real is done in reals
+pub fn arithmetic(operator: BinaryOperator, left: &LiteralValue, right: &LiteralValue) -> SqlError<LiteralValue> {
+    if *left == LiteralValue::Null || *right == LiteralValue::Null {
+        return Ok(LiteralValue::Null);
+    }
+    if let (&LiteralValue::Integer(a), &LiteralValue::Integer(b)) = (left, right) {
+        if b == 0 && (operator == BinaryOperator::Divide || operator == BinaryOperator::Modulo) {
+            return Err(Error::DivisionByZero);
+        }
+        let result = match operator {
+            BinaryOperator::Add => a.checked_add(b),
+            BinaryOperator::Subtract => a.checked_sub(b),
+            BinaryOperator::Multiply => a.checked_mul(b),
+            BinaryOperator::Divide => a.checked_div(b),
+            BinaryOperator::Modulo => a.checked_rem(b),
+            _ => None
+        };
+        return match result {
+            Some(result) => Ok(LiteralValue::Integer(result)),
+            None => Err(Error::NumericOverflow(format!("{} {} {}", a, operator.symbol(), b)))
+        };
+    }
+
+    let (a, b) = (try!(numeric_operand(operator, left)), try!(numeric_operand(operator, right)));
+    if b == 0.0 && (operator == BinaryOperator::Divide || operator == BinaryOperator::Modulo) {
+        return Err(Error::DivisionByZero);
+    }
+    let result = match operator {
+        BinaryOperator::Add => a + b,
+        BinaryOperator::Subtract => a - b,
+        BinaryOperator::Multiply => a * b,
+        BinaryOperator::Divide => a / b,
+        BinaryOperator::Modulo => a % b,
+        _ => {
+            return Err(Error::Internal(format!("{} is not an arithmetic operator", operator.symbol())));
+        }
+    };
+    if result.is_infinite() && a.is_finite() && b.is_finite() {
+        return Err(Error::NumericOverflow(format!("{} {} {}", a, operator.symbol(), b)));
+    }
+    Ok(LiteralValue::Real(result))
+}
+
+// Matches text against a LIKE pattern where % matches any sequence of
+// characters and _ matches a single character
+fn like_matches(text: &[char], pattern: &[char]) -> bool {
+    // matches[i] is true when the pattern read so far matches the first i characters
+    let mut matches: Vec<bool> = repeat(false).take(text.len() + 1).collect();
+    matches[0] = true;
+    for &p in pattern.iter() {
+        let mut next: Vec<bool> = repeat(false).take(text.len() + 1).collect();
+        match p {
+            '%' => {
+                let mut any = false;
+                for i in 0..text.len() + 1 {
+                    any = any || matches[i];
+                    next[i] = any;
+                }
+            },
+            _ => {
+                for i in 0..text.len() {
+                    next[i + 1] = matches[i] && (p == '_' || p == text[i]);
+                }
+            }
+        }
+        matches = next;
+    }
+    matches[text.len()]
+}
+
+impl BoundExpr {
+    // Subqueries read their tables from schema
+    pub fn evaluate(&self, row: &[LiteralValue], schema: &Schema) -> SqlError<LiteralValue> {
+        match *self {
+            BoundExpr::Position(position) => Ok(row[position].clone()),
+            BoundExpr::LiteralValue(ref value) => Ok(value.clone()),
+            BoundExpr::Unary(UnaryOperator::Not, ref expr) => {
+                let value = try!(truth_value(try!(expr.evaluate(row, schema))));
+                Ok(from_truth_value(value.map(|b| !b)))
+            },
+            BoundExpr::Unary(operator, ref expr) => {
+                match try!(expr.evaluate(row, schema)) {
+                    LiteralValue::Null => Ok(LiteralValue::Null),
+                    LiteralValue::Integer(i) if operator == UnaryOperator::Minus => {
+                        match i.checked_neg() {
+                            Some(i) => Ok(LiteralValue::Integer(i)),
+                            None => Err(Error::NumericOverflow(format!("-{}", i)))
+                        }
+                    },
+                    LiteralValue::Real(r) if operator == UnaryOperator::Minus => Ok(LiteralValue::Real(-r)),
+                    value @ LiteralValue::Integer(_) | value @ LiteralValue::Real(_) => Ok(value),
+                    value => Err(Error::Type(format!("Unary {:?} expects a numeric operand, got {:?}", operator, value)))
+                }
+            },
+            BoundExpr::Binary(ref left, BinaryOperator::And, ref right) => {
+                // FALSE AND anything is FALSE, even NULL
+                let left = try!(truth_value(try!(left.evaluate(row, schema))));
+                if left == Some(false) {
+                    return Ok(LiteralValue::Bool(false));
+                }
+                let right = try!(truth_value(try!(right.evaluate(row, schema))));
+                Ok(from_truth_value(match (left, right) {
+                    (_, Some(false)) => Some(false),
+                    (Some(true), Some(true)) => Some(true),
+                    _ => None
+                }))
+            },
+            BoundExpr::Binary(ref left, BinaryOperator::Or, ref right) => {
+                // TRUE OR anything is TRUE, even NULL
+                let left = try!(truth_value(try!(left.evaluate(row, schema))));
+                if left == Some(true) {
+                    return Ok(LiteralValue::Bool(true));
+                }
+                let right = try!(truth_value(try!(right.evaluate(row, schema))));
+                Ok(from_truth_value(match (left, right) {
+                    (_, Some(true)) => Some(true),
+                    (Some(false), Some(false)) => Some(false),
+                    _ => None
+                }))
+            },
+            BoundExpr::Binary(ref left, BinaryOperator::Concat, ref right) => {
+                let (left, right) = (try!(left.evaluate(row, schema)), try!(right.evaluate(row, schema)));
+                if left == LiteralValue::Null || right == LiteralValue::Null {
+                    return Ok(LiteralValue::Null);
+                }
+                Ok(LiteralValue::Text(try!(left.as_string()) + &try!(right.as_string())))
+            },
+            BoundExpr::Binary(ref left, operator, ref right) => {
+                let (left, right) = (try!(left.evaluate(row, schema)), try!(right.evaluate(row, schema)));
+                if operator.is_comparison() {
+                    let ordering = try!(compare(&left, &right));
+                    Ok(from_truth_value(ordering.map(|o| comparison(operator, o))))
+                }
+                else {
+                    arithmetic(operator, &left, &right)
+                }
+            },
+            BoundExpr::IsNull{ref expr, negated} => {
+                let is_null = try!(expr.evaluate(row, schema)) == LiteralValue::Null;
+                Ok(LiteralValue::Bool(is_null != negated))
+            },
+            BoundExpr::Between{ref expr, negated, ref low, ref high} => {
+                let value = try!(expr.evaluate(row, schema));
+                let above = try!(compare(&value, &try!(low.evaluate(row, schema)))).map(|o| o != Ordering::Less);
+                let below = try!(compare(&value, &try!(high.evaluate(row, schema)))).map(|o| o != Ordering::Greater);
+                let result = match (above, below) {
+                    (Some(false), _) | (_, Some(false)) => Some(false),
+                    (Some(true), Some(true)) => Some(true),
+                    _ => None
+                };
+                Ok(from_truth_value(negate_if(result, negated)))
+            },
+            BoundExpr::InList{ref expr, negated, ref list} => {
+                // Without a match, a NULL in the list makes the result unknown
+                let value = try!(expr.evaluate(row, schema));
+                let mut result = Some(false);
+                for item in list.iter() {
+                    match try!(compare(&value, &try!(item.evaluate(row, schema)))) {
+                        Some(Ordering::Equal) => {
+                            result = Some(true);
+                            break;
+                        },
+                        Some(_) => {},
+                        None => {
+                            result = None;
+                        }
+                    }
+                }
+                Ok(from_truth_value(negate_if(result, negated)))
+            },
+            BoundExpr::Like{ref expr, negated, ref pattern} => {
+                match (try!(expr.evaluate(row, schema)), try!(pattern.evaluate(row, schema))) {
+                    (LiteralValue::Text(text), LiteralValue::Text(pattern)) => {
+                        let text: Vec<char> = text.chars().collect();
+                        let pattern: Vec<char> = pattern.chars().collect();
+                        Ok(LiteralValue::Bool(like_matches(&text, &pattern) != negated))
+                    },
+                    (LiteralValue::Null, _) | (_, LiteralValue::Null) => Ok(LiteralValue::Null),
+                    (text, pattern) => Err(Error::Type(format!("LIKE expects Text operands, got {:?} and {:?}", text, pattern)))
+                }
+            },
+            BoundExpr::Subquery(ref subquery) => {
+                match *try!(subquery.result(row, schema)) {
+                    SubqueryResult::Value(ref value) => Ok(value.clone()),
+                    SubqueryResult::Values(..) => Err(Error::Internal("IN subquery used as a value".to_string()))
+                }
+            },
+            BoundExpr::InSubquery{ref expr, negated, ref subquery} => {
+                // Like an IN list, the value is compared with = to each value
+                // returned, a NULL among them makes the result unknown when
+                // there is no match
+                let value = try!(expr.evaluate(row, schema));
+                let result = match *try!(subquery.result(row, schema)) {
+                    SubqueryResult::Values(ref values) => {
+                        if values.samples.is_empty() && !values.has_null {
+                            Some(false)
+                        }
+                        else if value == LiteralValue::Null {
+                            None
+                        }
+                        else {
+                            // A value of another kind cannot be compared with,
+                            // NaN compares with no number
+                            let mut unknown = values.has_null || values.has_nan;
+                            for sample in values.samples.iter() {
+                                if try!(compare(&value, sample)).is_none() {
+                                    unknown = true;
+                                }
+                            }
+                            if values.keys.contains(&HashKey::from_value(&value)) {
+                                Some(true)
+                            }
+                            else if unknown {
+                                None
+                            }
+                            else {
+                                Some(false)
+                            }
+                        }
+                    },
+                    SubqueryResult::Value(_) => {
+                        return Err(Error::Internal("Scalar subquery used in IN".to_string()));
+                    }
+                };
+                Ok(from_truth_value(negate_if(result, negated)))
+            }
+        }
+    }
+}
+
+// Evaluates an expression that does not reference any column
+pub fn evaluate_constant(expr: &ExprIr, schema: &Schema, options: &PlanOptions) -> SqlError<LiteralValue> {
+    try!(bind_expr(expr, &[], 0, options)).evaluate(&[], schema)
+}
+
+// A row matches when every filter evaluates to TRUE, NULL counts as a mismatch
+pub fn row_matches_filters(filters: &[BoundExpr], row: &[LiteralValue], schema: &Schema) -> SqlError<bool> {
+    for filter in filters.iter() {
+        if try!(filter.evaluate(row, schema)) != LiteralValue::Bool(true) {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
@@ -1,7 +1,8 @@
 use definitions::*;
 use schema::*;
 use std::iter::repeat;
-use super::StaticRow;
+use super::{StaticRow,PlanOptions};
+use super::expr::evaluate_constant;
 use ir::*;
 
 pub struct InsertPlan {
@@ -15,7 +16,7 @@ impl InsertPlan {
             match self.source.next() {
                 Some(row) => {
                     let _ = try!(schema.map_on_table_mut(self.table_index, |table| {
-                        Ok(table.insert_row(row.to_owned()))
+                        table.insert(row.to_owned())
                         }));
                 },
                 None => {
@@ -28,19 +29,15 @@ impl InsertPlan {
     }
 }
 
-pub fn build_insert_plan(insert_ir: &insert_ir::InsertIr, schema: &Schema) -> SqlError<InsertPlan> {
+pub fn build_insert_plan(insert_ir: &insert_ir::InsertIr, schema: &Schema, options: &PlanOptions) -> SqlError<InsertPlan> {
 
     // First convert what might potentially be a partial row into a full one by
     // making all the absent columns NULLs
-    let table_col_len = try!(schema.map_on_table(insert_ir.table.table_index, |table| {
-        Ok(table.columns().len())
-    }));
+    let table_col_len = insert_ir.table.columns.len();
     let mut row: Vec<LiteralValue> = repeat(LiteralValue::Null).take(table_col_len).collect();
-    let mut cur_index = 0;
-
-    for val in insert_ir.values.iter() {
-        row[insert_ir.columns[cur_index].column_index] = val.clone();
-        cur_index += 1;
+    for ((column_ref, column_def), value) in insert_ir.columns.iter().zip(insert_ir.column_defs.iter()).zip(insert_ir.values.iter()) {
+        let value = try!(evaluate_constant(value, schema, options));
+        row[column_ref.column_index] = try!(coerce_value(&value, column_def));
     }
 
     let static_row = StaticRow {
@@ -48,7 +45,7 @@ pub fn build_insert_plan(insert_ir: &insert_ir::InsertIr, schema: &Schema) -> Sq
     };
 
     Ok(InsertPlan {
-        table_index: insert_ir.table.table_index,
+        table_index: try!(insert_ir.table.stored_table_index()),
         source: static_row
     })
 
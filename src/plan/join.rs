@@ -0,0 +1,619 @@
+use definitions::*;
+use schema::*;
+use std::iter::repeat;
+use std::mem::size_of;
+use std::cmp::Ordering;
+use std::collections::{HashMap,VecDeque};
+use super::{RowSource,BufferedSource,HashKey,PlanOptions,estimated_row_size};
+use super::expr::{BoundExpr,row_matches_filters};
+use super::sort::{Sort,BoundSortKey};
+
+fn keeps_unmatched_left(join_type: JoinType) -> bool {
+    join_type == JoinType::LeftOuter || join_type == JoinType::FullOuter
+}
+
+fn keeps_unmatched_right(join_type: JoinType) -> bool {
+    join_type == JoinType::RightOuter || join_type == JoinType::FullOuter
+}
+
+fn null_padded(left_nulls: usize, left: &[LiteralValue], right: &[LiteralValue], right_nulls: usize) -> Vec<LiteralValue> {
+    let mut row: Vec<LiteralValue> = repeat(LiteralValue::Null).take(left_nulls).collect();
+    row.extend(left.iter().cloned());
+    row.extend(right.iter().cloned());
+    row.extend(repeat(LiteralValue::Null).take(right_nulls));
+    row
+}
+
+// The hash key made of the given columns of a row. NULL and NaN are not
+// equal to anything, not even themselves, so rows with such a key column never
+// match anything and have no key.
+//
+// Every join operator decides whether keys are equal the way the = operator
+// does: through LiteralValue::compare for the nested loop join, through the
+// HashKey of the values for the hash join and through sort_cmp, which follows
+// compare, for the sort-merge join.
+fn join_key(row: &[LiteralValue], key_columns: &[usize]) -> Option<Vec<HashKey>> {
+    let mut key = Vec::with_capacity(key_columns.len());
+    for &column in key_columns.iter() {
+        match row[column] {
+            LiteralValue::Null => {
+                return None;
+            },
+            LiteralValue::Real(r) if r.is_nan() => {
+                return None;
+            },
+            ref value => {
+                key.push(HashKey::from_value(value));
+            }
+        }
+    }
+    Some(key)
+}
+
+fn has_join_key(row: &[LiteralValue], key_columns: &[usize]) -> bool {
+    join_key(row, key_columns).is_some()
+}
+
+// Orders rows that both have a join key on their key columns
+fn compare_keys(left: &[LiteralValue], left_keys: &[usize], right: &[LiteralValue], right_keys: &[usize]) -> Ordering {
+    for (&l, &r) in left_keys.iter().zip(right_keys.iter()) {
+        match left[l].sort_cmp(&right[r]) {
+            Ordering::Equal => {},
+            ordering => return ordering
+        }
+    }
+    Ordering::Equal
+}
+
+fn read_all(source: &mut Box<RowSource>, schema: &Schema) -> SqlError<Vec<Vec<LiteralValue>>> {
+    let mut rows = Vec::new();
+    while let Some(row) = try!(source.get_next_row(schema)) {
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+// Joins every row of the left input with every row of the right input that
+// satisfies the join filters. The right input is read into memory the first
+// time a row is requested.
+struct NestedLoopJoin {
+    join_type: JoinType,
+    left: Box<RowSource>,
+    right: Box<RowSource>,
+    left_width: usize,
+    right_width: usize,
+    filters: Vec<BoundExpr>,
+    right_rows: Option<Vec<Vec<LiteralValue>>>,
+    right_matched: Vec<bool>,
+    current_left: Option<Vec<LiteralValue>>,
+    current_left_matched: bool,
+    right_pos: usize,
+    left_done: bool
+}
+impl NestedLoopJoin {
+    fn new(join_type: JoinType, left: Box<RowSource>, right: Box<RowSource>, left_width: usize, right_width: usize, filters: Vec<BoundExpr>) -> NestedLoopJoin {
+        NestedLoopJoin {
+            join_type: join_type,
+            left: left,
+            right: right,
+            left_width: left_width,
+            right_width: right_width,
+            filters: filters,
+            right_rows: None,
+            right_matched: Vec::new(),
+            current_left: None,
+            current_left_matched: false,
+            right_pos: 0,
+            left_done: false
+        }
+    }
+}
+impl RowSource for NestedLoopJoin {
+    fn get_next_row(&mut self, schema: &Schema) -> SqlError<Option<Vec<LiteralValue>>> {
+        if self.right_rows.is_none() {
+            let rows = try!(read_all(&mut self.right, schema));
+            self.right_matched = repeat(false).take(rows.len()).collect();
+            self.right_rows = Some(rows);
+        }
+
+        loop {
+            if self.left_done {
+                // Right rows that never found a match are padded with NULLs
+                if keeps_unmatched_right(self.join_type) {
+                    let right_rows = self.right_rows.as_ref().unwrap();
+                    while self.right_pos < right_rows.len() {
+                        let index = self.right_pos;
+                        self.right_pos += 1;
+                        if !self.right_matched[index] {
+                            return Ok(Some(null_padded(self.left_width, &[], &right_rows[index], 0)));
+                        }
+                    }
+                }
+                return Ok(None);
+            }
+
+            if self.current_left.is_none() {
+                match try!(self.left.get_next_row(schema)) {
+                    Some(row) => {
+                        self.current_left = Some(row);
+                        self.current_left_matched = false;
+                        self.right_pos = 0;
+                    },
+                    None => {
+                        self.left_done = true;
+                        self.right_pos = 0;
+                        continue;
+                    }
+                }
+            }
+
+            {
+                let left_row = self.current_left.as_ref().unwrap();
+                let right_rows = self.right_rows.as_ref().unwrap();
+                while self.right_pos < right_rows.len() {
+                    let index = self.right_pos;
+                    self.right_pos += 1;
+
+                    let mut row = left_row.clone();
+                    row.extend(right_rows[index].iter().cloned());
+                    if try!(row_matches_filters(&self.filters, &row, schema)) {
+                        self.current_left_matched = true;
+                        self.right_matched[index] = true;
+                        return Ok(Some(row));
+                    }
+                }
+            }
+
+            // Every right row has been tried against the current left row
+            let left_row = self.current_left.take().unwrap();
+            if !self.current_left_matched && keeps_unmatched_left(self.join_type) {
+                return Ok(Some(null_padded(0, &left_row, &[], self.right_width)));
+            }
+        }
+    }
+}
+
+// Equi-join that loads one input (the build side) into a hash table and looks
+// up each row of the other input (the probe side) in it. If the build side
+// turns out to be larger than the memory budget the join is handed over to a
+// sort-merge join, which spills to temporary files instead.
+struct HashJoin {
+    join_type: JoinType,
+    build_is_left: bool,
+    build: Option<Box<RowSource>>,
+    probe: Option<Box<RowSource>>,
+    build_keys: Vec<usize>,
+    probe_keys: Vec<usize>,
+    left_width: usize,
+    right_width: usize,
+    residual: Vec<BoundExpr>,
+    options: PlanOptions,
+    built: bool,
+    fallback: Option<Box<RowSource>>,
+    build_rows: Vec<Vec<LiteralValue>>,
+    table: HashMap<Vec<HashKey>, Vec<usize>>,
+    build_matched: Vec<bool>,
+    pending: VecDeque<Vec<LiteralValue>>,
+    probe_done: bool,
+    unmatched_pos: usize
+}
+impl HashJoin {
+    fn new(join_type: JoinType, left: Box<RowSource>, right: Box<RowSource>, left_width: usize, right_width: usize,
+           left_keys: Vec<usize>, right_keys: Vec<usize>, residual: Vec<BoundExpr>, build_is_left: bool, options: &PlanOptions) -> HashJoin {
+        let (build, probe, build_keys, probe_keys) = if build_is_left {
+            (left, right, left_keys, right_keys)
+        }
+        else {
+            (right, left, right_keys, left_keys)
+        };
+
+        HashJoin {
+            join_type: join_type,
+            build_is_left: build_is_left,
+            build: Some(build),
+            probe: Some(probe),
+            build_keys: build_keys,
+            probe_keys: probe_keys,
+            left_width: left_width,
+            right_width: right_width,
+            residual: residual,
+            options: options.clone(),
+            built: false,
+            fallback: None,
+            build_rows: Vec::new(),
+            table: HashMap::new(),
+            build_matched: Vec::new(),
+            pending: VecDeque::new(),
+            probe_done: false,
+            unmatched_pos: 0
+        }
+    }
+
+    fn keeps_unmatched_build(&self) -> bool {
+        if self.build_is_left { keeps_unmatched_left(self.join_type) } else { keeps_unmatched_right(self.join_type) }
+    }
+
+    fn keeps_unmatched_probe(&self) -> bool {
+        if self.build_is_left { keeps_unmatched_right(self.join_type) } else { keeps_unmatched_left(self.join_type) }
+    }
+
+    fn combine(&self, probe_row: &[LiteralValue], build_row: &[LiteralValue]) -> Vec<LiteralValue> {
+        if self.build_is_left {
+            null_padded(0, build_row, probe_row, 0)
+        }
+        else {
+            null_padded(0, probe_row, build_row, 0)
+        }
+    }
+
+    fn build_table(&mut self, schema: &Schema) -> SqlError<()> {
+        self.built = true;
+        let mut build = self.build.take().unwrap();
+        let mut rows = Vec::new();
+        let mut bytes = 0usize;
+        let mut over_budget = false;
+        loop {
+            match try!(build.get_next_row(schema)) {
+                Some(row) => {
+                    bytes += estimated_row_size(&row);
+                    rows.push(row);
+                    if bytes > self.options.join_memory_budget {
+                        over_budget = true;
+                        break;
+                    }
+                },
+                None => {
+                    break;
+                }
+            }
+        }
+
+        if over_budget {
+            let build: Box<RowSource> = Box::new(BufferedSource::new(rows, build));
+            let probe = self.probe.take().unwrap();
+            let join = if self.build_is_left {
+                SortMergeJoin::new(self.join_type, build, probe, self.left_width, self.right_width,
+                                   self.build_keys.clone(), self.probe_keys.clone(), self.residual.clone(), &self.options)
+            }
+            else {
+                SortMergeJoin::new(self.join_type, probe, build, self.left_width, self.right_width,
+                                   self.probe_keys.clone(), self.build_keys.clone(), self.residual.clone(), &self.options)
+            };
+            self.fallback = Some(Box::new(join));
+            return Ok(());
+        }
+
+        for (index, row) in rows.iter().enumerate() {
+            if let Some(key) = join_key(row, &self.build_keys) {
+                self.table.entry(key).or_insert(Vec::new()).push(index);
+            }
+        }
+        self.build_matched = repeat(false).take(rows.len()).collect();
+        self.build_rows = rows;
+        Ok(())
+    }
+
+    fn probe_row(&mut self, probe_row: Vec<LiteralValue>, schema: &Schema) -> SqlError<()> {
+        let mut matched = false;
+        if let Some(key) = join_key(&probe_row, &self.probe_keys) {
+            if let Some(indexes) = self.table.get(&key) {
+                for &index in indexes.iter() {
+                    let row = self.combine(&probe_row, &self.build_rows[index]);
+                    if try!(row_matches_filters(&self.residual, &row, schema)) {
+                        matched = true;
+                        self.build_matched[index] = true;
+                        self.pending.push_back(row);
+                    }
+                }
+            }
+        }
+
+        if !matched && self.keeps_unmatched_probe() {
+            let row = if self.build_is_left {
+                null_padded(self.left_width, &[], &probe_row, 0)
+            }
+            else {
+                null_padded(0, &probe_row, &[], self.right_width)
+            };
+            self.pending.push_back(row);
+        }
+        Ok(())
+    }
+
+    fn next_unmatched_build(&mut self) -> Option<Vec<LiteralValue>> {
+        if !self.keeps_unmatched_build() {
+            return None;
+        }
+        while self.unmatched_pos < self.build_rows.len() {
+            let index = self.unmatched_pos;
+            self.unmatched_pos += 1;
+            if !self.build_matched[index] {
+                let build_row = &self.build_rows[index];
+                return Some(if self.build_is_left {
+                    null_padded(0, build_row, &[], self.right_width)
+                }
+                else {
+                    null_padded(self.left_width, &[], build_row, 0)
+                });
+            }
+        }
+        None
+    }
+}
+impl RowSource for HashJoin {
+    fn get_next_row(&mut self, schema: &Schema) -> SqlError<Option<Vec<LiteralValue>>> {
+        if !self.built {
+            try!(self.build_table(schema));
+        }
+        if let Some(ref mut fallback) = self.fallback {
+            return fallback.get_next_row(schema);
+        }
+
+        loop {
+            if let Some(row) = self.pending.pop_front() {
+                return Ok(Some(row));
+            }
+            if self.probe_done {
+                return Ok(self.next_unmatched_build());
+            }
+
+            let next_probe = try!(self.probe.as_mut().unwrap().get_next_row(schema));
+            match next_probe {
+                Some(row) => {
+                    try!(self.probe_row(row, schema));
+                },
+                None => {
+                    self.probe_done = true;
+                }
+            }
+        }
+    }
+}
+
+// Equi-join that sorts both inputs on the join keys and walks them in step,
+// joining the groups of rows with equal keys. The inputs are sorted by Sort,
+// which spills runs to temporary files past its memory budget, so only the
+// rows of the current key group are held in memory.
+struct SortMergeJoin {
+    join_type: JoinType,
+    left: Box<RowSource>,
+    right: Box<RowSource>,
+    left_width: usize,
+    right_width: usize,
+    left_keys: Vec<usize>,
+    right_keys: Vec<usize>,
+    residual: Vec<BoundExpr>,
+    started: bool,
+    // The next row of each sorted input
+    left_head: Option<Vec<LiteralValue>>,
+    right_head: Option<Vec<LiteralValue>>,
+    pending: VecDeque<Vec<LiteralValue>>
+}
+impl SortMergeJoin {
+    fn new(join_type: JoinType, left: Box<RowSource>, right: Box<RowSource>, left_width: usize, right_width: usize,
+           left_keys: Vec<usize>, right_keys: Vec<usize>, residual: Vec<BoundExpr>, options: &PlanOptions) -> SortMergeJoin {
+        let left_sort = left_keys.iter().map(|&column| BoundSortKey::column(column)).collect();
+        let right_sort = right_keys.iter().map(|&column| BoundSortKey::column(column)).collect();
+        SortMergeJoin {
+            join_type: join_type,
+            left: Box::new(Sort::new(left, left_sort, options)),
+            right: Box::new(Sort::new(right, right_sort, options)),
+            left_width: left_width,
+            right_width: right_width,
+            left_keys: left_keys,
+            right_keys: right_keys,
+            residual: residual,
+            started: false,
+            left_head: None,
+            right_head: None,
+            pending: VecDeque::new()
+        }
+    }
+
+    // Takes the rows following the head of a sorted input that share its key.
+    // Returns them with the head, along with the next row of the input.
+    fn take_group(source: &mut Box<RowSource>, head: Vec<LiteralValue>, keys: &[usize], schema: &Schema)
+                  -> SqlError<(Vec<Vec<LiteralValue>>, Option<Vec<LiteralValue>>)> {
+        let mut group = vec![head];
+        loop {
+            match try!(source.get_next_row(schema)) {
+                Some(row) => {
+                    if has_join_key(&row, keys) && compare_keys(&row, keys, &group[0], keys) == Ordering::Equal {
+                        group.push(row);
+                    }
+                    else {
+                        return Ok((group, Some(row)));
+                    }
+                },
+                None => {
+                    return Ok((group, None));
+                }
+            }
+        }
+    }
+
+    // Produces the output for the next key (or unmatched row) of the inputs.
+    // Returns false once both inputs are exhausted.
+    fn merge_step(&mut self, schema: &Schema) -> SqlError<bool> {
+        if !self.started {
+            self.started = true;
+            self.left_head = try!(self.left.get_next_row(schema));
+            self.right_head = try!(self.right.get_next_row(schema));
+        }
+
+        // Rows with a NULL or NaN key can never match, and the rows around
+        // them are still in key order
+        if self.left_head.as_ref().map(|row| !has_join_key(row, &self.left_keys)).unwrap_or(false) {
+            let row = self.left_head.take().unwrap();
+            if keeps_unmatched_left(self.join_type) {
+                self.pending.push_back(null_padded(0, &row, &[], self.right_width));
+            }
+            self.left_head = try!(self.left.get_next_row(schema));
+            return Ok(true);
+        }
+        if self.right_head.as_ref().map(|row| !has_join_key(row, &self.right_keys)).unwrap_or(false) {
+            let row = self.right_head.take().unwrap();
+            if keeps_unmatched_right(self.join_type) {
+                self.pending.push_back(null_padded(self.left_width, &[], &row, 0));
+            }
+            self.right_head = try!(self.right.get_next_row(schema));
+            return Ok(true);
+        }
+
+        let ordering = match (&self.left_head, &self.right_head) {
+            (&None, &None) => {
+                return Ok(false);
+            },
+            (&None, &Some(_)) => Ordering::Greater,
+            (&Some(_), &None) => Ordering::Less,
+            (&Some(ref left), &Some(ref right)) => compare_keys(left, &self.left_keys, right, &self.right_keys)
+        };
+
+        match ordering {
+            Ordering::Less => {
+                let row = self.left_head.take().unwrap();
+                if keeps_unmatched_left(self.join_type) {
+                    self.pending.push_back(null_padded(0, &row, &[], self.right_width));
+                }
+                self.left_head = try!(self.left.get_next_row(schema));
+            },
+            Ordering::Greater => {
+                let row = self.right_head.take().unwrap();
+                if keeps_unmatched_right(self.join_type) {
+                    self.pending.push_back(null_padded(self.left_width, &[], &row, 0));
+                }
+                self.right_head = try!(self.right.get_next_row(schema));
+            },
+            Ordering::Equal => {
+                let left_head = self.left_head.take().unwrap();
+                let (left_rows, left_next) = try!(SortMergeJoin::take_group(&mut self.left, left_head, &self.left_keys, schema));
+                let right_head = self.right_head.take().unwrap();
+                let (right_rows, right_next) = try!(SortMergeJoin::take_group(&mut self.right, right_head, &self.right_keys, schema));
+                self.left_head = left_next;
+                self.right_head = right_next;
+
+                let mut right_matched: Vec<bool> = repeat(false).take(right_rows.len()).collect();
+                for left_row in left_rows.iter() {
+                    let mut left_matched = false;
+                    for (index, right_row) in right_rows.iter().enumerate() {
+                        let row = null_padded(0, left_row, right_row, 0);
+                        if try!(row_matches_filters(&self.residual, &row, schema)) {
+                            left_matched = true;
+                            right_matched[index] = true;
+                            self.pending.push_back(row);
+                        }
+                    }
+                    if !left_matched && keeps_unmatched_left(self.join_type) {
+                        self.pending.push_back(null_padded(0, left_row, &[], self.right_width));
+                    }
+                }
+                if keeps_unmatched_right(self.join_type) {
+                    for (right_row, matched) in right_rows.iter().zip(right_matched.iter()) {
+                        if !*matched {
+                            self.pending.push_back(null_padded(self.left_width, &[], right_row, 0));
+                        }
+                    }
+                }
+            }
+        }
+        Ok(true)
+    }
+}
+impl RowSource for SortMergeJoin {
+    fn get_next_row(&mut self, schema: &Schema) -> SqlError<Option<Vec<LiteralValue>>> {
+        loop {
+            if let Some(row) = self.pending.pop_front() {
+                return Ok(Some(row));
+            }
+            if !try!(self.merge_step(schema)) {
+                return Ok(None);
+            }
+        }
+    }
+}
+
+// Splits join filters into equality conditions between a left and a right
+// column, usable as join keys, and the remaining residual filters
+fn split_equi_join_keys(filters: Vec<BoundExpr>, left_width: usize) -> (Vec<usize>, Vec<usize>, Vec<BoundExpr>) {
+    let mut left_keys = Vec::new();
+    let mut right_keys = Vec::new();
+    let mut residual = Vec::new();
+    for filter in filters.into_iter() {
+        let key = match filter {
+            BoundExpr::Binary(ref left, BinaryOperator::Equals, ref right) => {
+                match (&**left, &**right) {
+                    (&BoundExpr::Position(left), &BoundExpr::Position(right)) => {
+                        if left < left_width && right >= left_width {
+                            Some((left, right - left_width))
+                        }
+                        else if right < left_width && left >= left_width {
+                            Some((right, left - left_width))
+                        }
+                        else {
+                            None
+                        }
+                    },
+                    _ => None
+                }
+            },
+            _ => None
+        };
+        match key {
+            Some((left, right)) => {
+                left_keys.push(left);
+                right_keys.push(right);
+            },
+            None => {
+                residual.push(filter);
+            }
+        }
+    }
+    (left_keys, right_keys, residual)
+}
+
+// How the rows produced by a join are ordered
+pub enum JoinOrder {
+    // In the order of the rows of the left input
+    Left,
+    // Sorted on these columns of the joined rows, ascending with NULLs last
+    Columns(Vec<usize>),
+    Unknown
+}
+
+// Picks the join algorithm. Equi-joins use a hash join built on the smaller
+// input unless even that is expected to exceed the memory budget, in which
+// case a sort-merge join is used. Anything else uses a nested loop.
+pub fn build_join(join_type: JoinType, left: Box<RowSource>, right: Box<RowSource>, left_width: usize, right_width: usize,
+                  filters: Vec<BoundExpr>, left_rows_estimate: usize, right_rows_estimate: usize, options: &PlanOptions) -> (Box<RowSource>, JoinOrder) {
+    let (left_keys, right_keys, residual) = split_equi_join_keys(filters.clone(), left_width);
+    if left_keys.is_empty() {
+        // Unmatched right rows come last, after every left row
+        let order = if keeps_unmatched_right(join_type) { JoinOrder::Unknown } else { JoinOrder::Left };
+        return (Box::new(NestedLoopJoin::new(join_type, left, right, left_width, right_width, filters)), order);
+    }
+
+    let build_is_left = left_rows_estimate < right_rows_estimate;
+    let (build_rows, build_width) = if build_is_left {
+        (left_rows_estimate, left_width)
+    }
+    else {
+        (right_rows_estimate, right_width)
+    };
+    let build_bytes = build_rows.saturating_mul(size_of::<Vec<LiteralValue>>() + build_width * size_of::<LiteralValue>());
+
+    if build_bytes > options.join_memory_budget {
+        // Rows come in the order of the keys of the side whose rows are all
+        // kept, the NULL padded rows of the other side break its order
+        let order = match join_type {
+            JoinType::FullOuter => JoinOrder::Unknown,
+            JoinType::RightOuter => JoinOrder::Columns(right_keys.iter().map(|&key| left_width + key).collect()),
+            _ => JoinOrder::Columns(left_keys.clone())
+        };
+        (Box::new(SortMergeJoin::new(join_type, left, right, left_width, right_width, left_keys, right_keys, residual, options)), order)
+    }
+    else {
+        // The hash join may hand over to a sort-merge join, whose order
+        // differs, once it reads its build side
+        (Box::new(HashJoin::new(join_type, left, right, left_width, right_width, left_keys, right_keys, residual, build_is_left, options)), JoinOrder::Unknown)
+    }
+}
@@ -1,8 +1,115 @@
 use definitions::*;
-use std::mem::replace;
+use schema::*;
+use std::mem::{replace,size_of};
+use std::f64;
+use std::env;
+use std::path::PathBuf;
 
 pub mod insert_plan;
 pub mod select_plan;
+pub mod update_plan;
+pub mod delete_plan;
+mod join;
+mod aggregate;
+mod set_operation;
+mod recursive;
+mod window;
+mod expr;
+mod sort;
+mod spill;
+
+// Tuning knobs used when building query plans
+#[derive(Debug,Clone,PartialEq)]
+pub struct PlanOptions {
+    // Approximate number of bytes a hash join may use for its hash table before
+    // falling back to a sort-merge join
+    pub join_memory_budget: usize,
+    // Approximate number of bytes a sort may hold in memory before spilling
+    // sorted runs to temporary files
+    pub sort_memory_budget: usize,
+    // Where temporary files are created
+    pub temp_directory: PathBuf,
+    // How many times the recursive term of a recursive query may run
+    pub max_recursion_depth: usize
+}
+
+impl PlanOptions {
+    pub fn new() -> PlanOptions {
+        PlanOptions {
+            join_memory_budget: 64 * 1024 * 1024,
+            sort_memory_budget: 64 * 1024 * 1024,
+            temp_directory: env::temp_dir(),
+            max_recursion_depth: 1000
+        }
+    }
+}
+
+trait RowSource {
+    fn get_next_row(&mut self, schema: &Schema) -> SqlError<Option<Vec<LiteralValue>>>;
+}
+
+// Replays rows that were already read from a source before continuing with the source itself
+struct BufferedSource {
+    rows: Vec<Vec<LiteralValue>>,
+    source: Box<RowSource>
+}
+impl BufferedSource {
+    fn new(mut rows: Vec<Vec<LiteralValue>>, source: Box<RowSource>) -> BufferedSource {
+        rows.reverse();
+        BufferedSource {
+            rows: rows,
+            source: source
+        }
+    }
+}
+impl RowSource for BufferedSource {
+    fn get_next_row(&mut self, schema: &Schema) -> SqlError<Option<Vec<LiteralValue>>> {
+        match self.rows.pop() {
+            Some(row) => Ok(Some(row)),
+            None => self.source.get_next_row(schema)
+        }
+    }
+}
+
+// A value that can be used as a hash table key. Two values other than NULL
+// and NaN have equal keys exactly when LiteralValue::compare finds them equal,
+// so integral reals share the key of the integer they are equal to. Every NaN
+// has the same key.
+#[derive(Debug,Clone,PartialEq,Eq,Hash)]
+enum HashKey {
+    Null,
+    Text(String),
+    Bool(bool),
+    Integer(i64),
+    Real(u64)
+}
+
+impl HashKey {
+    fn from_value(value: &LiteralValue) -> HashKey {
+        match *value {
+            LiteralValue::Null => HashKey::Null,
+            LiteralValue::Text(ref s) => HashKey::Text(s.clone()),
+            LiteralValue::Bool(b) => HashKey::Bool(b),
+            LiteralValue::Integer(i) => HashKey::Integer(i),
+            LiteralValue::Real(r) if r.is_nan() => HashKey::Real(f64::NAN.to_bits()),
+            // The integral reals in the range of i64, including -0.0
+            LiteralValue::Real(r) if r.floor() == r && r >= -9223372036854775808.0 && r < 9223372036854775808.0 => {
+                HashKey::Integer(r as i64)
+            },
+            LiteralValue::Real(r) => HashKey::Real(r.to_bits())
+        }
+    }
+}
+
+// Rough number of bytes used by a row in memory
+fn estimated_row_size(row: &[LiteralValue]) -> usize {
+    row.iter().fold(size_of::<Vec<LiteralValue>>(), |size, value| {
+        size + size_of::<LiteralValue>() + match *value {
+            LiteralValue::Text(ref s) => s.len(),
+            _ => 0
+        }
+    })
+}
 
 
 struct StaticRow {
@@ -0,0 +1,123 @@
+use definitions::*;
+use schema::*;
+use ir::select_ir::FromIr;
+use ir::query_ir::RecursiveIr;
+use std::collections::HashSet;
+use std::mem::replace;
+use std::rc::Rc;
+use super::{RowSource,HashKey,PlanOptions};
+use super::select_plan::{ResultSet,build_query_plan};
+use super::set_operation::widen_row;
+
+// Reads the rows of the previous iteration of a recursive query
+pub struct WorkingTableScan {
+    rows: Rc<Vec<Vec<LiteralValue>>>,
+    position: usize
+}
+
+impl WorkingTableScan {
+    pub fn new(rows: Rc<Vec<Vec<LiteralValue>>>) -> WorkingTableScan {
+        WorkingTableScan {
+            rows: rows,
+            position: 0
+        }
+    }
+}
+
+impl RowSource for WorkingTableScan {
+    fn get_next_row(&mut self, _schema: &Schema) -> SqlError<Option<Vec<LiteralValue>>> {
+        match self.rows.get(self.position) {
+            Some(row) => {
+                self.position += 1;
+                Ok(Some(row.clone()))
+            },
+            None => Ok(None)
+        }
+    }
+}
+
+// Computes a recursive query as a fixpoint. The rows of the anchor are
+// returned first and kept as the working table, then the recursive term is
+// planned again for each iteration on the rows returned by the previous one,
+// until an iteration returns no rows. Rows are returned as they are produced.
+pub struct RecursiveUnion {
+    ir: RecursiveIr,
+    options: PlanOptions,
+    // None until the anchor is planned
+    source: Option<ResultSet>,
+    // The rows returned by the current iteration
+    next_rows: Vec<Vec<LiteralValue>>,
+    // Every row returned so far, unless duplicates are kept
+    seen: HashSet<Vec<HashKey>>,
+    iterations: usize
+}
+
+impl RecursiveUnion {
+    pub fn new(ir: RecursiveIr, options: PlanOptions) -> RecursiveUnion {
+        RecursiveUnion {
+            ir: ir,
+            options: options,
+            source: None,
+            next_rows: Vec::new(),
+            seen: HashSet::new(),
+            iterations: 0
+        }
+    }
+
+    // Plans the next iteration of the recursive term on the rows of the
+    // current one. Returns None once the current iteration produced no rows.
+    fn next_iteration(&mut self, schema: &Schema) -> SqlError<Option<ResultSet>> {
+        if self.next_rows.is_empty() {
+            return Ok(None);
+        }
+        if self.iterations == self.options.max_recursion_depth {
+            return Err(Error::RecursionLimit(format!("Recursive query did not finish after {} iterations",
+                                                    self.options.max_recursion_depth)));
+        }
+        self.iterations += 1;
+
+        let rows = Rc::new(replace(&mut self.next_rows, Vec::new()));
+        let mut term = self.ir.term.clone();
+        term.for_each_from_item(&mut |item: &mut FromIr| {
+            if let FromIr::WorkingTable(_, ref mut working_rows) = *item {
+                *working_rows = rows.clone();
+            }
+        });
+        Ok(Some(try!(build_query_plan(&term, schema, &self.options))))
+    }
+}
+
+impl RowSource for RecursiveUnion {
+    fn get_next_row(&mut self, schema: &Schema) -> SqlError<Option<Vec<LiteralValue>>> {
+        loop {
+            let row = match self.source {
+                Some(ref mut source) => try!(source.get_next_row(schema)),
+                None => None
+            };
+            match row {
+                Some(row) => {
+                    let row = widen_row(row, &self.ir.column_defs);
+                    if !self.ir.all && !self.seen.insert(row.iter().map(HashKey::from_value).collect()) {
+                        continue;
+                    }
+                    self.next_rows.push(row.clone());
+                    return Ok(Some(row));
+                },
+                None => {
+                    let source = if self.source.is_none() {
+                        try!(build_query_plan(&self.ir.anchor, schema, &self.options))
+                    }
+                    else {
+                        match try!(self.next_iteration(schema)) {
+                            Some(source) => source,
+                            None => {
+                                return Ok(None);
+                            }
+                        }
+                    };
+                    self.source = Some(source);
+                }
+            }
+        }
+    }
+}
@@ -1,66 +1,555 @@
 use definitions::*;
 use schema::*;
+use ir::*;
 use ir::select_ir::*;
+use ir::query_ir::{QueryIr,SubqueryIr};
 use tables::*;
+use functions::FunctionRows;
+use super::{RowSource,PlanOptions};
+use super::expr::{BoundExpr,bind_exprs,evaluate_constant,row_matches_filters};
+use super::join::{build_join,JoinOrder};
+use std::mem::size_of;
+use super::sort::{Sort,TopN,BoundSortKey,SortedColumn,bind_sort_keys};
+use super::aggregate::{HashAggregate,StreamAggregate,bind_aggregates};
+use super::set_operation::{Distinct,SetOperation};
+use super::recursive::{RecursiveUnion,WorkingTableScan};
+use super::window::build_window;
 
-struct FullTableScan {
+// Reads the rows of a stored table, all of them or those found by an index
+struct TableScan {
     table_index: usize,
-    column_ids: Vec<usize>,
-    table_iter: RowIdIterator
+    table_iter: Box<Iterator<Item=RowId>>
 }
-impl FullTableScan {
-    fn get_next_row(&mut self, schema: &Schema) -> SqlError<Vec<LiteralValue>> {
+impl TableScan {
+    fn new(table_index: usize, schema: &Schema) -> SqlError<TableScan> {
+        let iterator = try!(schema.map_on_table(table_index,|table| table.scan()));
+
+        Ok(TableScan {
+            table_index: table_index,
+            table_iter: iterator
+        })
+    }
+
+    fn with_rowids(table_index: usize, rowids: Vec<RowId>) -> TableScan {
+        TableScan {
+            table_index: table_index,
+            table_iter: Box::new(rowids.into_iter())
+        }
+    }
+}
+impl RowSource for TableScan {
+    fn get_next_row(&mut self, schema: &Schema) -> SqlError<Option<Vec<LiteralValue>>> {
         let row_id = match self.table_iter.next() {
                 Some(id) => id,
                 None => {
-                    return Err(format!("No more rows."));
+                    return Ok(None);
                 }
             };
         schema.map_on_table(self.table_index, |table| {
-            let row = try!(table.get_row(row_id));
-            let mut filtered_row = Vec::new();
-            for (index,column_val) in row.iter().enumerate() {
-                match self.column_ids.iter().position(|&col_id| col_id == index) {
-                    Some(_) => {
-                        filtered_row.push(column_val.clone());
-                    },
-                    None => {}
+            Ok(Some(try!(table.get_row(row_id))))
+        })
+    }
+}
+
+// Reads the rows returned by a table function
+struct FunctionScan {
+    rows: FunctionRows
+}
+impl RowSource for FunctionScan {
+    fn get_next_row(&mut self, _schema: &Schema) -> SqlError<Option<Vec<LiteralValue>>> {
+        match self.rows.next() {
+            Some(row) => Ok(Some(try!(row))),
+            None => Ok(None)
+        }
+    }
+}
+
+struct Filter {
+    source: Box<RowSource>,
+    filters: Vec<BoundExpr>
+}
+impl Filter {
+    fn new(source: Box<RowSource>, filters: Vec<BoundExpr>) -> Filter {
+        Filter {
+            source: source,
+            filters: filters
+        }
+    }
+}
+impl RowSource for Filter {
+    fn get_next_row(&mut self, schema: &Schema) -> SqlError<Option<Vec<LiteralValue>>> {
+        loop {
+            match try!(self.source.get_next_row(schema)) {
+                Some(row) => {
+                    if try!(row_matches_filters(&self.filters, &row, schema)) {
+                        return Ok(Some(row));
+                    }
+                },
+                None => {
+                    return Ok(None);
                 }
             }
-            Ok(filtered_row)
-        })
+        }
     }
+}
 
-    fn new(table_index: usize, column_ids: Vec<usize>, schema: &Schema) -> SqlError<FullTableScan> {
-        let iterator = try!(schema.map_on_table(table_index,|table| Ok(table.rowid_iter())));
+// Skips the first offset rows of its input and returns at most limit of the
+// rows after them. The input is not read any further once the limit is reached.
+struct Limit {
+    source: Box<RowSource>,
+    offset: usize,
+    limit: Option<usize>,
+    returned: usize
+}
+impl Limit {
+    fn new(source: Box<RowSource>, offset: usize, limit: Option<usize>) -> Limit {
+        Limit {
+            source: source,
+            offset: offset,
+            limit: limit,
+            returned: 0
+        }
+    }
+}
+impl RowSource for Limit {
+    fn get_next_row(&mut self, schema: &Schema) -> SqlError<Option<Vec<LiteralValue>>> {
+        if self.limit.map(|limit| self.returned >= limit).unwrap_or(false) {
+            return Ok(None);
+        }
+        while self.offset > 0 {
+            if try!(self.source.get_next_row(schema)).is_none() {
+                return Ok(None);
+            }
+            self.offset -= 1;
+        }
+        let row = try!(self.source.get_next_row(schema));
+        if row.is_some() {
+            self.returned += 1;
+        }
+        Ok(row)
+    }
+}
 
-        Ok(FullTableScan {
-            table_index: table_index,
-            table_iter: iterator,
-            column_ids: column_ids
-        })
+// Evaluates the projections of a query on each row
+struct Project {
+    source: Box<RowSource>,
+    projections: Vec<BoundExpr>
+}
+impl RowSource for Project {
+    fn get_next_row(&mut self, schema: &Schema) -> SqlError<Option<Vec<LiteralValue>>> {
+        match try!(self.source.get_next_row(schema)) {
+            Some(row) => {
+                let mut values = Vec::new();
+                for projection in self.projections.iter() {
+                    values.push(try!(projection.evaluate(&row, schema)));
+                }
+                Ok(Some(values))
+            },
+            None => Ok(None)
+        }
     }
 }
 
 pub struct ResultSet {
-    table_scan: FullTableScan
+    source: Box<RowSource>
 }
 impl ResultSet {
-    pub fn get_next_row(&mut self, schema: &mut Schema) -> SqlError<Vec<LiteralValue>> {
-        self.table_scan.get_next_row(schema)
+    pub fn get_next_row(&mut self, schema: &Schema) -> SqlError<Option<Vec<LiteralValue>>> {
+        self.source.get_next_row(schema)
     }
 }
 
+// Returns the ORDER BY keys that sort the input of an aggregation on its group
+// keys when the first keys of ORDER BY are exactly the group keys. Sorting the
+// input then lets it be aggregated as a stream and leaves the output sorted.
+fn group_sort_keys(aggregation: &AggregationIr, order_by: &[SortKeyIr]) -> Option<Vec<SortKeyIr>> {
+    let group_count = aggregation.group_by.len();
+    if group_count == 0 || order_by.len() < group_count {
+        return None;
+    }
+    let mut keys: Vec<SortKeyIr> = Vec::new();
+    for key in order_by[..group_count].iter() {
+        match key.expr {
+            ExprIr::Position(index) if index < group_count && !keys.iter().any(|k| k.expr == aggregation.group_by[index]) => {
+                keys.push(SortKeyIr {
+                    expr: aggregation.group_by[index].clone(),
+                    descending: key.descending,
+                    nulls_first: key.nulls_first
+                });
+            },
+            _ => {
+                return None;
+            }
+        }
+    }
+    Some(keys)
+}
 
-pub fn build_select_plan(ir: &SelectIr, schema: &Schema) -> SqlError<ResultSet> {
-    let mut table_column_ids = Vec::new();
-    for column_ref in ir.columns.iter() {
-        if column_ref.table_ref_index == 0 {
-            table_column_ids.push(column_ref.column_index);
+// Returns the columns of the rows of a derived table its query sorts them on,
+// by their position in the full row. Only the leading ORDER BY keys that are
+// result columns count.
+fn derived_order(query: &QueryIr, offset: usize) -> Vec<SortedColumn> {
+    let mut order = Vec::new();
+    let (order_by, projections) = match *query {
+        QueryIr::Select(ref ir) => (&ir.order_by, if ir.distinct { None } else { Some(&ir.projections) }),
+        // The sort keys of a set operation refer to its result columns
+        QueryIr::SetOperation(ref ir) => (&ir.order_by, None)
+    };
+    for key in order_by.iter() {
+        let column = match projections {
+            Some(projections) => projections.iter().position(|projection| *projection == key.expr),
+            None => match key.expr {
+                ExprIr::Position(index) => Some(index),
+                _ => None
+            }
+        };
+        match column {
+            Some(column) => order.push(SortedColumn {
+                position: offset + column,
+                descending: key.descending,
+                nulls_first: key.nulls_first
+            }),
+            None => break
         }
     }
-    Ok(ResultSet {
-        table_scan: try!(FullTableScan::new(ir.tables[0].table_index, table_column_ids, schema))
+    order
+}
+
+// Returns the positions of the columns a WHERE conjunct compares to a non NULL
+// constant. The rows left by the filters hold a single value in them, so they
+// don't matter to the grouping or the order of the rows.
+fn constant_columns(filters: &[ExprIr], table_offsets: &[usize]) -> Vec<usize> {
+    let mut columns = Vec::new();
+    for filter in filters.iter() {
+        if let ExprIr::Binary(ref left, BinaryOperator::Equals, ref right) = *filter {
+            match (&**left, &**right) {
+                (&ExprIr::Column(ref column), &ExprIr::LiteralValue(ref value)) |
+                (&ExprIr::LiteralValue(ref value), &ExprIr::Column(ref column)) if *value != LiteralValue::Null => {
+                    columns.push(table_offsets[column.table_ref_index] + column.column_index);
+                },
+                _ => {}
+            }
         }
-    )
+    }
+    columns
+}
+
+// Checks rows in the given order have equal group keys next to each other,
+// which holds when they are sorted on the group key columns before any other
+fn is_grouped(keys: &[BoundExpr], input_order: &[SortedColumn]) -> bool {
+    let mut columns: Vec<usize> = Vec::new();
+    for key in keys.iter() {
+        match *key {
+            BoundExpr::Position(position) => if !columns.contains(&position) { columns.push(position); },
+            _ => {
+                return false;
+            }
+        }
+    }
+    input_order.len() >= columns.len() && input_order[..columns.len()].iter().all(|column| columns.contains(&column.position))
+}
+
+// Checks rows in the given order are sorted on the given keys
+fn is_sorted_on(keys: &[BoundSortKey], input_order: &[SortedColumn], constants: &[usize]) -> bool {
+    let mut columns = Vec::new();
+    for key in keys.iter() {
+        match key.sorted_column() {
+            Some(column) => if !constants.contains(&column.position) { columns.push(column); },
+            None => {
+                return false;
+            }
+        }
+    }
+    input_order.len() >= columns.len() && input_order[..columns.len()] == columns[..]
+}
+
+// Evaluates a LIMIT or OFFSET clause. NULL means no limit.
+fn evaluate_row_count(expr: &Option<ExprIr>, clause: &str, schema: &Schema, options: &PlanOptions) -> SqlError<Option<usize>> {
+    let expr = match *expr {
+        Some(ref expr) => expr,
+        None => {
+            return Ok(None);
+        }
+    };
+    match try!(evaluate_constant(expr, schema, options)) {
+        LiteralValue::Null => Ok(None),
+        LiteralValue::Integer(count) if count >= 0 => Ok(Some(count as usize)),
+        LiteralValue::Integer(count) => Err(Error::InvalidRowCount(format!("{} must not be negative, got {}", clause, count))),
+        value => Err(Error::Type(format!("{} must be an Integer, got {:?}", clause, value)))
+    }
+}
+
+// The range of table refs covered by a FROM clause subtree
+fn table_range(from: &FromIr) -> (usize, usize) {
+    match from {
+        &FromIr::Table(table_ref_index) | &FromIr::Derived(table_ref_index, _) |
+        &FromIr::Recursive(table_ref_index, _) | &FromIr::WorkingTable(table_ref_index, _) |
+        &FromIr::Function(table_ref_index, _, _) => (table_ref_index, table_ref_index + 1),
+        &FromIr::Join{ref left, ref right, ..} => (table_range(left).0, table_range(right).1)
+    }
+}
+
+// Moves WHERE filters comparing columns of different tables into the lowest
+// inner or cross join covering those tables so they can be used as join
+// conditions. Outer joins are left alone as that would change their result.
+fn push_down_join_filters(from: FromIr, filters: &mut Vec<ExprIr>) -> FromIr {
+    match from {
+        FromIr::Join{join_type, left, right, mut on} => {
+            if join_type != JoinType::Cross && join_type != JoinType::Inner {
+                return FromIr::Join{join_type: join_type, left: left, right: right, on: on};
+            }
+
+            let left = push_down_join_filters(*left, filters);
+            let right = push_down_join_filters(*right, filters);
+            let (start, end) = (table_range(&left).0, table_range(&right).1);
+
+            let (join_filters, remaining): (Vec<ExprIr>, Vec<ExprIr>) = filters.drain(..).partition(|filter| {
+                let table_refs = filter.table_refs();
+                table_refs.len() > 1 && table_refs.iter().all(|&t| t >= start && t < end)
+            });
+            *filters = remaining;
+            on.extend(join_filters);
+
+            FromIr::Join {
+                join_type: if on.is_empty() { join_type } else { JoinType::Inner },
+                left: Box::new(left),
+                right: Box::new(right),
+                on: on
+            }
+        },
+        table => table
+    }
+}
+
+// Finds a WHERE conjunct comparing a column of a table to a constant of the
+// column's type, whose rows the table may find through an index. Only rows
+// holding the value can satisfy the conjunct, even on the outer side of a join.
+fn lookup_key(filters: &[ExprIr], table_ref_index: usize, table_ref: &TableRef) -> Option<(usize, LiteralValue)> {
+    for filter in filters.iter() {
+        if let ExprIr::Binary(ref left, BinaryOperator::Equals, ref right) = *filter {
+            let (column, value) = match (&**left, &**right) {
+                (&ExprIr::Column(ref column), &ExprIr::LiteralValue(ref value)) |
+                (&ExprIr::LiteralValue(ref value), &ExprIr::Column(ref column)) => (column, value),
+                _ => continue
+            };
+            if column.table_ref_index == table_ref_index && *value != LiteralValue::Null &&
+                ColumnType::accomodate_literal(value) == table_ref.columns[column.column_index].ctype {
+                return Some((column.column_index, value.clone()));
+            }
+        }
+    }
+    None
+}
+
+// Builds the operators producing the rows of a FROM clause subtree. Returns
+// the source along with the position of its first column in the full row, the
+// number of columns it produces, an estimate of the number of rows and the
+// columns the rows are known to be sorted on.
+fn build_from_source(from: &FromIr, ir: &SelectIr, filters: &[ExprIr], table_offsets: &[usize], table_widths: &[usize], schema: &Schema, options: &PlanOptions)
+                     -> SqlError<(Box<RowSource>, usize, usize, usize, Vec<SortedColumn>)> {
+    match from {
+        &FromIr::Table(table_ref_index) => {
+            let table_index = try!(ir.tables[table_ref_index].stored_table_index());
+            // The filter is still applied to the rows found through the index
+            let rowids = match lookup_key(filters, table_ref_index, &ir.tables[table_ref_index]) {
+                Some((column, value)) => try!(schema.map_on_table(table_index, |table| table.lookup(column, &value))),
+                None => None
+            };
+            let rows = match rowids {
+                Some(ref rowids) => rowids.len(),
+                None => try!(schema.map_on_table(table_index, |table| Ok(table.len())))
+            };
+            let scan: Box<RowSource> = match rowids {
+                Some(rowids) => Box::new(TableScan::with_rowids(table_index, rowids)),
+                None => Box::new(try!(TableScan::new(table_index, schema)))
+            };
+            Ok((scan, table_offsets[table_ref_index], table_widths[table_ref_index], rows, Vec::new()))
+        },
+        &FromIr::Derived(table_ref_index, ref subquery) => {
+            let source = try!(build_derived_source(subquery, schema, options));
+            let order = derived_order(&subquery.query, table_offsets[table_ref_index]);
+            Ok((source, table_offsets[table_ref_index], table_widths[table_ref_index], DERIVED_ROWS_ESTIMATE, order))
+        },
+        &FromIr::Recursive(table_ref_index, ref recursive) => {
+            let source: Box<RowSource> = Box::new(RecursiveUnion::new((**recursive).clone(), options.clone()));
+            Ok((source, table_offsets[table_ref_index], table_widths[table_ref_index], DERIVED_ROWS_ESTIMATE, Vec::new()))
+        },
+        &FromIr::WorkingTable(table_ref_index, ref rows) => {
+            let scan: Box<RowSource> = Box::new(WorkingTableScan::new(rows.clone()));
+            Ok((scan, table_offsets[table_ref_index], table_widths[table_ref_index], rows.len(), Vec::new()))
+        },
+        &FromIr::Function(table_ref_index, ref name, ref args) => {
+            let function = try!(schema.find_table_function_or_err(name));
+            let scan: Box<RowSource> = Box::new(FunctionScan { rows: try!(function.rows(args)) });
+            Ok((scan, table_offsets[table_ref_index], table_widths[table_ref_index], DERIVED_ROWS_ESTIMATE, Vec::new()))
+        },
+        &FromIr::Join{join_type, ref left, ref right, ref on} => {
+            let (left_source, left_base, left_width, left_rows, left_order) = try!(build_from_source(left, ir, filters, table_offsets, table_widths, schema, options));
+            let (right_source, _, right_width, right_rows, _) = try!(build_from_source(right, ir, filters, table_offsets, table_widths, schema, options));
+            let filters = try!(bind_exprs(on, table_offsets, left_base, options));
+            let rows = if on.is_empty() { left_rows.saturating_mul(right_rows) } else { ::std::cmp::max(left_rows, right_rows) };
+            let (join, join_order) = build_join(join_type, left_source, right_source, left_width, right_width, filters, left_rows, right_rows, options);
+            let order = match join_order {
+                JoinOrder::Left => left_order,
+                JoinOrder::Columns(columns) => columns.into_iter().map(|column| SortedColumn::ascending(left_base + column)).collect(),
+                JoinOrder::Unknown => Vec::new()
+            };
+            Ok((join, left_base, left_width + right_width, rows, order))
+        }
+    }
+}
+
+// The number of rows of a derived table or a table function is only known
+// once they are read
+const DERIVED_ROWS_ESTIMATE: usize = 1000;
+
+// Builds the operators producing the rows of a derived table. It can only
+// reference columns of the enclosing queries, whose values are already known.
+fn build_derived_source(subquery: &SubqueryIr, schema: &Schema, options: &PlanOptions) -> SqlError<Box<RowSource>> {
+    let mut values = Vec::new();
+    for param in subquery.params.iter() {
+        values.push(try!(evaluate_constant(param, schema, options)));
+    }
+    build_query_source(&try!(subquery.bind_parameters(&values)), schema, options)
+}
+
+// Sorts the rows of a query on its ORDER BY keys and applies its LIMIT and
+// OFFSET clauses. row_width is the number of values in the rows to sort.
+fn sort_and_limit(mut source: Box<RowSource>, keys: Vec<BoundSortKey>, row_width: usize, limit: &Option<ExprIr>, offset: &Option<ExprIr>,
+                  schema: &Schema, options: &PlanOptions) -> SqlError<Box<RowSource>> {
+    let limit = try!(evaluate_row_count(limit, "LIMIT", schema, options));
+    let offset = try!(evaluate_row_count(offset, "OFFSET", schema, options)).unwrap_or(0);
+    if !keys.is_empty() {
+        // Only the rows up to the limit need sorting, keep them in a heap if they fit in memory
+        let row_bytes = size_of::<Vec<LiteralValue>>() + (keys.len() + row_width) * size_of::<LiteralValue>();
+        let top_n = limit.map(|limit| limit.saturating_add(offset)).and_then(|count| {
+            if count.saturating_mul(row_bytes) <= options.sort_memory_budget { Some(count) } else { None }
+        });
+        match top_n {
+            Some(count) => {
+                source = Box::new(TopN::new(source, keys, count));
+            },
+            None => {
+                source = Box::new(Sort::new(source, keys, options));
+            }
+        }
+    }
+    if limit.is_some() || offset > 0 {
+        source = Box::new(Limit::new(source, offset, limit));
+    }
+    Ok(source)
+}
+
+// Builds the operators producing the result rows of a select
+fn build_select_source(ir: &SelectIr, schema: &Schema, options: &PlanOptions) -> SqlError<Box<RowSource>> {
+    // Rows are built from the columns of every table in order
+    let mut table_offsets = Vec::new();
+    let mut table_widths = Vec::new();
+    let mut row_width = 0;
+    for table_ref in ir.tables.iter() {
+        let width = table_ref.columns.len();
+        table_offsets.push(row_width);
+        table_widths.push(width);
+        row_width += width;
+    }
+
+    let mut filters = ir.filters.clone();
+    let from = push_down_join_filters(ir.from.clone(), &mut filters);
+
+    let (mut source, _, _, _, mut input_order) = try!(build_from_source(&from, ir, &filters, &table_offsets, &table_widths, schema, options));
+    let constants = constant_columns(&filters, &table_offsets);
+    input_order.retain(|column| !constants.contains(&column.position));
+    if !filters.is_empty() {
+        source = Box::new(Filter::new(source, try!(bind_exprs(&filters, &table_offsets, 0, options))));
+    }
+
+    let mut sorted = ir.order_by.is_empty();
+    if let Some(ref aggregation) = ir.aggregation {
+        let keys = try!(bind_exprs(&aggregation.group_by, &table_offsets, 0, options));
+        let aggregates = try!(bind_aggregates(&aggregation.aggregates, &table_offsets, options));
+        // The sort keys of a DISTINCT select refer to the result rows instead
+        let stream_keys = if ir.distinct { None } else { group_sort_keys(aggregation, &ir.order_by) };
+        let sort_keys = match stream_keys {
+            Some(ref stream_keys) => Some(try!(bind_sort_keys(stream_keys, &table_offsets, options))),
+            None => None
+        };
+        let group_keys: Vec<BoundExpr> = keys.iter().filter(|key| match **key {
+            BoundExpr::Position(position) => !constants.contains(&position),
+            _ => true
+        }).cloned().collect();
+        if is_grouped(&group_keys, &input_order) {
+            // The input already has the rows of each group next to each other,
+            // in the order of the output if it is sorted on the sort keys
+            if let Some(ref sort_keys) = sort_keys {
+                if is_sorted_on(sort_keys, &input_order, &constants) {
+                    sorted = sort_keys.len() == ir.order_by.len();
+                }
+            }
+            source = Box::new(StreamAggregate::new(source, keys, aggregates));
+        }
+        else {
+            match sort_keys {
+                Some(sort_keys) => {
+                    sorted = sort_keys.len() == ir.order_by.len();
+                    let input = Box::new(Sort::new(source, sort_keys, options));
+                    source = Box::new(StreamAggregate::new(input, keys, aggregates));
+                },
+                None => {
+                    source = Box::new(HashAggregate::new(source, keys, aggregates));
+                }
+            }
+        }
+        if !aggregation.having.is_empty() {
+            source = Box::new(Filter::new(source, try!(bind_exprs(&aggregation.having, &[], 0, options))));
+        }
+        row_width = aggregation.group_by.len() + aggregation.aggregates.len();
+    }
+
+    // Window values are appended to the rows in order, each window sorting
+    // them on its own keys
+    for window in ir.windows.iter() {
+        source = try!(build_window(source, window, &table_offsets, schema, options));
+    }
+    if !ir.windows.is_empty() {
+        sorted = ir.order_by.is_empty();
+        row_width += ir.windows.len();
+    }
+
+    let projections = try!(bind_exprs(&ir.projections, &table_offsets, 0, options));
+    let keys = if sorted { Vec::new() } else { try!(bind_sort_keys(&ir.order_by, &table_offsets, options)) };
+    if ir.distinct {
+        // Duplicates are removed before sorting, on the projected rows
+        let width = projections.len();
+        source = Box::new(Distinct::new(Box::new(Project { source: source, projections: projections })));
+        sort_and_limit(source, keys, width, &ir.limit, &ir.offset, schema, options)
+    }
+    else {
+        source = try!(sort_and_limit(source, keys, row_width, &ir.limit, &ir.offset, schema, options));
+        Ok(Box::new(Project { source: source, projections: projections }))
+    }
+}
+
+fn build_query_source(ir: &QueryIr, schema: &Schema, options: &PlanOptions) -> SqlError<Box<RowSource>> {
+    match *ir {
+        QueryIr::Select(ref ir) => build_select_source(ir, schema, options),
+        QueryIr::SetOperation(ref ir) => {
+            let left = try!(build_query_source(&ir.left, schema, options));
+            let right = try!(build_query_source(&ir.right, schema, options));
+            let source = Box::new(SetOperation::new(ir.operator, ir.all, left, right, ir.column_defs.clone()));
+            let keys = try!(bind_sort_keys(&ir.order_by, &[], options));
+            sort_and_limit(source, keys, ir.column_defs.len(), &ir.limit, &ir.offset, schema, options)
+        }
+    }
+}
+
+pub fn build_select_plan(ir: &SelectIr, schema: &Schema, options: &PlanOptions) -> SqlError<ResultSet> {
+    Ok(ResultSet {
+        source: try!(build_select_source(ir, schema, options))
+    })
+}
+
+pub fn build_query_plan(ir: &QueryIr, schema: &Schema, options: &PlanOptions) -> SqlError<ResultSet> {
+    Ok(ResultSet {
+        source: try!(build_query_source(ir, schema, options))
+    })
 }
@@ -0,0 +1,151 @@
+use definitions::*;
+use schema::*;
+use std::collections::{HashMap,HashSet};
+use super::{RowSource,HashKey};
+
+fn row_key(row: &[LiteralValue]) -> Vec<HashKey> {
+    row.iter().map(HashKey::from_value).collect()
+}
+
+// Integers become reals in the columns where another input produces reals
+pub fn widen_row(mut row: Vec<LiteralValue>, column_defs: &[ColumnDefinition]) -> Vec<LiteralValue> {
+    for (value, column_def) in row.iter_mut().zip(column_defs.iter()) {
+        if let LiteralValue::Integer(i) = *value {
+            if column_def.ctype == ColumnType::Real {
+                *value = LiteralValue::Real(i as f64);
+            }
+        }
+    }
+    row
+}
+
+// Removes duplicate rows from its input, keeping the first occurrence of each.
+// Rows are returned as soon as they are read.
+pub struct Distinct {
+    source: Box<RowSource>,
+    seen: HashSet<Vec<HashKey>>
+}
+
+impl Distinct {
+    pub fn new(source: Box<RowSource>) -> Distinct {
+        Distinct {
+            source: source,
+            seen: HashSet::new()
+        }
+    }
+}
+
+impl RowSource for Distinct {
+    fn get_next_row(&mut self, schema: &Schema) -> SqlError<Option<Vec<LiteralValue>>> {
+        while let Some(row) = try!(self.source.get_next_row(schema)) {
+            if self.seen.insert(row_key(&row)) {
+                return Ok(Some(row));
+            }
+        }
+        Ok(None)
+    }
+}
+
+// Combines the rows of two inputs. UNION streams the left rows followed by the
+// right rows. INTERSECT and EXCEPT first count the right rows in a hash table
+// and then stream the left rows that are kept. Rows holding NULLs in the same
+// columns are considered equal.
+pub struct SetOperation {
+    operator: SetOperator,
+    all: bool,
+    left: Box<RowSource>,
+    right: Box<RowSource>,
+    column_defs: Vec<ColumnDefinition>,
+    prepared: bool,
+    left_done: bool,
+    // How many times each right row is left to be matched
+    counts: HashMap<Vec<HashKey>, usize>,
+    // The rows returned so far when duplicates are removed
+    seen: HashSet<Vec<HashKey>>
+}
+
+impl SetOperation {
+    pub fn new(operator: SetOperator, all: bool, left: Box<RowSource>, right: Box<RowSource>, column_defs: Vec<ColumnDefinition>) -> SetOperation {
+        SetOperation {
+            operator: operator,
+            all: all,
+            left: left,
+            right: right,
+            column_defs: column_defs,
+            prepared: false,
+            left_done: false,
+            counts: HashMap::new(),
+            seen: HashSet::new()
+        }
+    }
+
+    fn prepare(&mut self, schema: &Schema) -> SqlError<()> {
+        if self.operator != SetOperator::Union {
+            while let Some(row) = try!(self.right.get_next_row(schema)) {
+                *self.counts.entry(row_key(&row)).or_insert(0) += 1;
+            }
+        }
+        self.prepared = true;
+        Ok(())
+    }
+}
+
+impl RowSource for SetOperation {
+    fn get_next_row(&mut self, schema: &Schema) -> SqlError<Option<Vec<LiteralValue>>> {
+        if !self.prepared {
+            try!(self.prepare(schema));
+        }
+        loop {
+            // Only UNION reads the right input past preparing
+            let row = if self.left_done {
+                try!(self.right.get_next_row(schema))
+            }
+            else {
+                try!(self.left.get_next_row(schema))
+            };
+            let row = match row {
+                Some(row) => widen_row(row, &self.column_defs),
+                None if !self.left_done && self.operator == SetOperator::Union => {
+                    self.left_done = true;
+                    continue;
+                },
+                None => {
+                    return Ok(None);
+                }
+            };
+
+            let key = row_key(&row);
+            let keep = match self.operator {
+                SetOperator::Union => self.all || self.seen.insert(key),
+                SetOperator::Intersect => {
+                    match self.counts.get_mut(&key) {
+                        Some(count) => {
+                            let matched = *count > 0;
+                            if matched {
+                                *count = if self.all { *count - 1 } else { 0 };
+                            }
+                            matched
+                        },
+                        None => false
+                    }
+                },
+                SetOperator::Except if self.all => {
+                    match self.counts.get_mut(&key) {
+                        Some(count) => {
+                            let matched = *count > 0;
+                            if matched {
+                                *count -= 1;
+                            }
+                            !matched
+                        },
+                        None => true
+                    }
+                },
+                SetOperator::Except => !self.counts.contains_key(&key) && self.seen.insert(key)
+            };
+            if keep {
+                return Ok(Some(row));
+            }
+        }
+    }
+}
@@ -0,0 +1,358 @@
+use definitions::*;
+use schema::*;
+use ir::select_ir::SortKeyIr;
+use std::cmp::Ordering;
+use std::mem::replace;
+use std::path::PathBuf;
+use super::{RowSource,PlanOptions,estimated_row_size};
+use super::expr::{BoundExpr,bind_expr};
+use super::spill::SpillFile;
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct BoundSortKey {
+    expr: BoundExpr,
+    descending: bool,
+    nulls_first: bool
+}
+
+// A column rows are known to be sorted on, by its position in the rows
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub struct SortedColumn {
+    pub position: usize,
+    pub descending: bool,
+    pub nulls_first: bool
+}
+
+impl SortedColumn {
+    // The order of BoundSortKey::column
+    pub fn ascending(position: usize) -> SortedColumn {
+        SortedColumn {
+            position: position,
+            descending: false,
+            nulls_first: false
+        }
+    }
+}
+
+impl BoundSortKey {
+    // An ascending key on a column of the input rows
+    pub fn column(position: usize) -> BoundSortKey {
+        BoundSortKey {
+            expr: BoundExpr::Position(position),
+            descending: false,
+            nulls_first: false
+        }
+    }
+
+    // The column the key sorts on, None for a key computed from the row
+    pub fn sorted_column(&self) -> Option<SortedColumn> {
+        match self.expr {
+            BoundExpr::Position(position) => Some(SortedColumn {
+                position: position,
+                descending: self.descending,
+                nulls_first: self.nulls_first
+            }),
+            _ => None
+        }
+    }
+}
+
+pub fn bind_sort_keys(keys: &[SortKeyIr], table_offsets: &[usize], options: &PlanOptions) -> SqlError<Vec<BoundSortKey>> {
+    keys.iter().map(|key| {
+        Ok(BoundSortKey {
+            expr: try!(bind_expr(&key.expr, table_offsets, 0, options)),
+            descending: key.descending,
+            nulls_first: key.nulls_first
+        })
+    }).collect()
+}
+
+// Orders two values of a sort key. NULLs are placed according to the key
+// regardless of its direction.
+fn compare_sort_values(left: &LiteralValue, right: &LiteralValue, key: &BoundSortKey) -> Ordering {
+    let null_ordering = if key.nulls_first { Ordering::Less } else { Ordering::Greater };
+    match (*left == LiteralValue::Null, *right == LiteralValue::Null) {
+        (true, true) => Ordering::Equal,
+        (true, false) => null_ordering,
+        (false, true) => null_ordering.reverse(),
+        (false, false) => {
+            let ordering = left.sort_cmp(right);
+            if key.descending { ordering.reverse() } else { ordering }
+        }
+    }
+}
+
+// Compares two entries by the key values they start with
+fn compare_entries(left: &[LiteralValue], right: &[LiteralValue], keys: &[BoundSortKey]) -> Ordering {
+    for (index, key) in keys.iter().enumerate() {
+        match compare_sort_values(&left[index], &right[index], key) {
+            Ordering::Equal => {},
+            ordering => {
+                return ordering;
+            }
+        }
+    }
+    Ordering::Equal
+}
+
+// Runs hold at least this many rows, however small the memory budget
+const MIN_RUN_ROWS: usize = 1024;
+// The number of runs merged at once, bounding the open temporary files
+const MERGE_FAN_IN: usize = 16;
+
+// Merges sorted runs, the earliest run wins on ties
+struct Merge {
+    runs: Vec<SpillFile>,
+    // The next entry of each run
+    heads: Vec<Option<Vec<LiteralValue>>>
+}
+
+impl Merge {
+    fn new(mut runs: Vec<SpillFile>) -> SqlError<Merge> {
+        let mut heads = Vec::with_capacity(runs.len());
+        for run in runs.iter_mut() {
+            heads.push(try!(run.next_row()));
+        }
+        Ok(Merge {
+            runs: runs,
+            heads: heads
+        })
+    }
+
+    fn next(&mut self, keys: &[BoundSortKey]) -> SqlError<Option<Vec<LiteralValue>>> {
+        let mut smallest: Option<usize> = None;
+        for index in 0..self.heads.len() {
+            if let Some(ref head) = self.heads[index] {
+                smallest = match smallest {
+                    Some(current) if compare_entries(head, self.heads[current].as_ref().unwrap(), keys) != Ordering::Less => Some(current),
+                    _ => Some(index)
+                };
+            }
+        }
+        match smallest {
+            Some(index) => {
+                let next = try!(self.runs[index].next_row());
+                Ok(replace(&mut self.heads[index], next))
+            },
+            None => Ok(None)
+        }
+    }
+}
+
+// Sorts its input on a list of keys. Rows are held in memory until they exceed
+// the memory budget, at which point they are sorted and written to a temporary
+// file as a run. Runs are merged in passes of MERGE_FAN_IN runs until few
+// enough are left to be merged while producing the output. Rows with equal
+// keys keep their input order.
+pub struct Sort {
+    source: Box<RowSource>,
+    keys: Vec<BoundSortKey>,
+    memory_budget: usize,
+    temp_directory: PathBuf,
+    prepared: bool,
+    // Entries are the key values followed by the row. In memory entries are
+    // kept in reverse order so the next one can be popped.
+    entries: Vec<Vec<LiteralValue>>,
+    runs: Vec<SpillFile>,
+    merge: Option<Merge>
+}
+
+impl Sort {
+    pub fn new(source: Box<RowSource>, keys: Vec<BoundSortKey>, options: &PlanOptions) -> Sort {
+        Sort {
+            source: source,
+            keys: keys,
+            memory_budget: options.sort_memory_budget,
+            temp_directory: options.temp_directory.clone(),
+            prepared: false,
+            entries: Vec::new(),
+            runs: Vec::new(),
+            merge: None
+        }
+    }
+
+    fn sort_entries(&mut self) {
+        let keys = &self.keys;
+        self.entries.sort_by(|a, b| compare_entries(a, b, keys));
+    }
+
+    fn spill_entries(&mut self) -> SqlError<()> {
+        self.sort_entries();
+        let run = try!(SpillFile::write(&self.temp_directory, &self.entries));
+        self.runs.push(run);
+        self.entries.clear();
+        Ok(())
+    }
+
+    // Merges consecutive groups of runs into single runs, keeping their
+    // order so ties are still won by the earliest rows
+    fn merge_pass(&mut self) -> SqlError<()> {
+        let mut runs = Vec::new();
+        while !self.runs.is_empty() {
+            let count = if self.runs.len() < MERGE_FAN_IN { self.runs.len() } else { MERGE_FAN_IN };
+            let rest = self.runs.split_off(count);
+            let group = replace(&mut self.runs, rest);
+            if group.len() == 1 {
+                runs.extend(group.into_iter());
+                continue;
+            }
+            let mut merge = try!(Merge::new(group));
+            let mut writer = try!(SpillFile::create(&self.temp_directory));
+            while let Some(entry) = try!(merge.next(&self.keys)) {
+                try!(writer.write_row(&entry));
+            }
+            runs.push(try!(writer.finish()));
+        }
+        self.runs = runs;
+        Ok(())
+    }
+
+    fn prepare(&mut self, schema: &Schema) -> SqlError<()> {
+        let mut bytes = 0;
+        while let Some(row) = try!(self.source.get_next_row(schema)) {
+            let mut entry = Vec::with_capacity(self.keys.len() + row.len());
+            for key in self.keys.iter() {
+                entry.push(try!(key.expr.evaluate(&row, schema)));
+            }
+            entry.extend(row.into_iter());
+            bytes += estimated_row_size(&entry);
+            self.entries.push(entry);
+
+            if bytes > self.memory_budget && self.entries.len() >= MIN_RUN_ROWS {
+                try!(self.spill_entries());
+                bytes = 0;
+            }
+        }
+
+        if self.runs.is_empty() {
+            self.sort_entries();
+            self.entries.reverse();
+        }
+        else {
+            if !self.entries.is_empty() {
+                try!(self.spill_entries());
+            }
+            while self.runs.len() > MERGE_FAN_IN {
+                try!(self.merge_pass());
+            }
+            let runs = replace(&mut self.runs, Vec::new());
+            self.merge = Some(try!(Merge::new(runs)));
+        }
+        self.prepared = true;
+        Ok(())
+    }
+}
+
+impl RowSource for Sort {
+    fn get_next_row(&mut self, schema: &Schema) -> SqlError<Option<Vec<LiteralValue>>> {
+        if !self.prepared {
+            try!(self.prepare(schema));
+        }
+        let entry = match self.merge {
+            Some(ref mut merge) => try!(merge.next(&self.keys)),
+            None => self.entries.pop()
+        };
+        Ok(entry.map(|mut entry| entry.split_off(self.keys.len())))
+    }
+}
+
+// Keeps the first count rows of its input in sort order using a bounded
+// max-heap, so only count rows are ever held in memory. Entries carry their
+// input position so that rows with equal keys keep their input order.
+pub struct TopN {
+    source: Box<RowSource>,
+    keys: Vec<BoundSortKey>,
+    count: usize,
+    prepared: bool,
+    // Entries are the input position and key values followed by the row
+    heap: Vec<(usize, Vec<LiteralValue>)>
+}
+
+impl TopN {
+    pub fn new(source: Box<RowSource>, keys: Vec<BoundSortKey>, count: usize) -> TopN {
+        TopN {
+            source: source,
+            keys: keys,
+            count: count,
+            prepared: false,
+            heap: Vec::new()
+        }
+    }
+
+    fn compare(keys: &[BoundSortKey], left: &(usize, Vec<LiteralValue>), right: &(usize, Vec<LiteralValue>)) -> Ordering {
+        match compare_entries(&left.1, &right.1, keys) {
+            Ordering::Equal => left.0.cmp(&right.0),
+            ordering => ordering
+        }
+    }
+
+    fn sift_up(&mut self, mut index: usize) {
+        while index > 0 {
+            let parent = (index - 1) / 2;
+            if TopN::compare(&self.keys, &self.heap[index], &self.heap[parent]) != Ordering::Greater {
+                break;
+            }
+            self.heap.swap(index, parent);
+            index = parent;
+        }
+    }
+
+    fn sift_down(&mut self, mut index: usize) {
+        loop {
+            let mut largest = index;
+            for child in (index * 2 + 1)..(index * 2 + 3) {
+                if child < self.heap.len() && TopN::compare(&self.keys, &self.heap[child], &self.heap[largest]) == Ordering::Greater {
+                    largest = child;
+                }
+            }
+            if largest == index {
+                break;
+            }
+            self.heap.swap(index, largest);
+            index = largest;
+        }
+    }
+
+    fn prepare(&mut self, schema: &Schema) -> SqlError<()> {
+        let mut position = 0;
+        while self.count > 0 {
+            let row = match try!(self.source.get_next_row(schema)) {
+                Some(row) => row,
+                None => break
+            };
+            let mut entry = Vec::with_capacity(self.keys.len() + row.len());
+            for key in self.keys.iter() {
+                entry.push(try!(key.expr.evaluate(&row, schema)));
+            }
+            entry.extend(row.into_iter());
+            let entry = (position, entry);
+            position += 1;
+
+            if self.heap.len() < self.count {
+                self.heap.push(entry);
+                let last = self.heap.len() - 1;
+                self.sift_up(last);
+            }
+            else if TopN::compare(&self.keys, &entry, &self.heap[0]) == Ordering::Less {
+                // The new row beats the worst row kept so far
+                self.heap[0] = entry;
+                self.sift_down(0);
+            }
+        }
+
+        // Sort in reverse so the next row can be popped
+        let keys = &self.keys;
+        self.heap.sort_by(|a, b| TopN::compare(keys, b, a));
+        self.prepared = true;
+        Ok(())
+    }
+}
+
+impl RowSource for TopN {
+    fn get_next_row(&mut self, schema: &Schema) -> SqlError<Option<Vec<LiteralValue>>> {
+        if !self.prepared {
+            try!(self.prepare(schema));
+        }
+        Ok(self.heap.pop().map(|(_, mut entry)| entry.split_off(self.keys.len())))
+    }
+}
@@ -0,0 +1,209 @@
+use definitions::*;
+use std::fs::{File,OpenOptions,remove_file};
+use std::io;
+use std::io::{Read,Write,BufReader,BufWriter};
+use std::path::{Path,PathBuf};
+use std::sync::atomic::{AtomicUsize,Ordering,ATOMIC_USIZE_INIT};
+use libc;
+
+// Used to give every temporary file of the process a distinct name
+static NEXT_SPILL_FILE: AtomicUsize = ATOMIC_USIZE_INIT;
+
+fn io_error(err: io::Error) -> Error {
+    Error::Io(format!("{}", err))
+}
+
+fn write_u64<W: Write>(writer: &mut W, value: u64) -> io::Result<()> {
+    let mut bytes = [0u8; 8];
+    for i in 0..8 {
+        bytes[i] = (value >> (i * 8)) as u8;
+    }
+    writer.write_all(&bytes)
+}
+
+// Fills buf from the reader. Returns false if the reader was already at its end.
+fn read_bytes<R: Read>(reader: &mut R, buf: &mut [u8]) -> SqlError<bool> {
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) if read == 0 => {
+                return Ok(false);
+            },
+            Ok(0) => {
+                return Err(Error::Io("Unexpected end of file".to_string()));
+            },
+            Ok(n) => {
+                read += n;
+            },
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {},
+            Err(err) => {
+                return Err(io_error(err));
+            }
+        }
+    }
+    Ok(true)
+}
+
+fn u64_from_bytes(bytes: &[u8]) -> u64 {
+    bytes.iter().rev().fold(0u64, |value, &byte| (value << 8) | byte as u64)
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> SqlError<u64> {
+    let mut bytes = [0u8; 8];
+    if !try!(read_bytes(reader, &mut bytes)) {
+        return Err(Error::Io("Unexpected end of file".to_string()));
+    }
+    Ok(u64_from_bytes(&bytes))
+}
+
+// Values are written as a tag byte followed by their little endian representation
+fn write_value<W: Write>(writer: &mut W, value: &LiteralValue) -> io::Result<()> {
+    match *value {
+        LiteralValue::Null => writer.write_all(&[0]),
+        LiteralValue::Text(ref s) => {
+            try!(writer.write_all(&[1]));
+            try!(write_u64(writer, s.len() as u64));
+            writer.write_all(s.as_bytes())
+        },
+        LiteralValue::Bool(b) => writer.write_all(&[2, b as u8]),
+        LiteralValue::Integer(i) => {
+            try!(writer.write_all(&[3]));
+            write_u64(writer, i as u64)
+        },
+        LiteralValue::Real(r) => {
+            try!(writer.write_all(&[4]));
+            write_u64(writer, r.to_bits())
+        }
+    }
+}
+
+fn read_value<R: Read>(reader: &mut R) -> SqlError<LiteralValue> {
+    let mut tag = [0u8; 1];
+    if !try!(read_bytes(reader, &mut tag)) {
+        return Err(Error::Io("Unexpected end of file".to_string()));
+    }
+    match tag[0] {
+        0 => Ok(LiteralValue::Null),
+        1 => {
+            let len = try!(read_u64(reader)) as usize;
+            let mut bytes: Vec<u8> = (0..len).map(|_| 0u8).collect();
+            if len > 0 && !try!(read_bytes(reader, &mut bytes)) {
+                return Err(Error::Io("Unexpected end of file".to_string()));
+            }
+            String::from_utf8(bytes)
+                .map(LiteralValue::Text)
+                .map_err(|_| Error::Io("Invalid text value".to_string()))
+        },
+        2 => {
+            let mut byte = [0u8; 1];
+            if !try!(read_bytes(reader, &mut byte)) {
+                return Err(Error::Io("Unexpected end of file".to_string()));
+            }
+            Ok(LiteralValue::Bool(byte[0] != 0))
+        },
+        3 => Ok(LiteralValue::Integer(try!(read_u64(reader)) as i64)),
+        4 => Ok(LiteralValue::Real(f64::from_bits(try!(read_u64(reader))))),
+        tag => Err(Error::Io(format!("Invalid value tag {}", tag)))
+    }
+}
+
+// Rows are written as their number of values followed by the values
+pub fn write_row<W: Write>(writer: &mut W, row: &[LiteralValue]) -> io::Result<()> {
+    try!(write_u64(writer, row.len() as u64));
+    for value in row.iter() {
+        try!(write_value(writer, value));
+    }
+    Ok(())
+}
+
+// Reads the next row, or None at the end of the input
+pub fn read_row<R: Read>(reader: &mut R) -> SqlError<Option<Vec<LiteralValue>>> {
+    let mut bytes = [0u8; 8];
+    if !try!(read_bytes(reader, &mut bytes)) {
+        return Ok(None);
+    }
+    let len = u64_from_bytes(&bytes) as usize;
+    let mut row = Vec::with_capacity(len);
+    for _ in 0..len {
+        row.push(try!(read_value(reader)));
+    }
+    Ok(Some(row))
+}
+
+// A temporary file of rows that is read back in the order the rows were
+// written. The file is removed when dropped.
+pub struct SpillFile {
+    path: PathBuf,
+    reader: Option<BufReader<File>>
+}
+
+// Writes the rows of a spill file, which can be read once finished
+pub struct SpillWriter {
+    spill: SpillFile,
+    writer: BufWriter<File>
+}
+
+impl SpillWriter {
+    pub fn write_row(&mut self, row: &[LiteralValue]) -> SqlError<()> {
+        write_row(&mut self.writer, row).map_err(io_error)
+    }
+
+    pub fn finish(mut self) -> SqlError<SpillFile> {
+        try!(self.writer.flush().map_err(io_error));
+        let mut spill = self.spill;
+        spill.reader = Some(BufReader::new(try!(File::open(&spill.path).map_err(io_error))));
+        Ok(spill)
+    }
+}
+
+impl SpillFile {
+    pub fn create(directory: &Path) -> SqlError<SpillWriter> {
+        let pid = unsafe { libc::getpid() };
+        loop {
+            let id = NEXT_SPILL_FILE.fetch_add(1, Ordering::SeqCst);
+            let path = directory.join(format!("rustql-{}-{}.spill", pid, id));
+            // A file of the same name may be left by an earlier process with
+            // the same pid. It is neither truncated nor removed, the next name
+            // is tried instead.
+            let file = match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(file) => file,
+                Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => {
+                    return Err(io_error(e));
+                }
+            };
+            // The spill file owns the file from now on, so it is removed even
+            // if writing fails
+            let spill = SpillFile {
+                path: path,
+                reader: None
+            };
+            return Ok(SpillWriter {
+                spill: spill,
+                writer: BufWriter::new(file)
+            });
+        }
+    }
+
+    pub fn write(directory: &Path, rows: &[Vec<LiteralValue>]) -> SqlError<SpillFile> {
+        let mut writer = try!(SpillFile::create(directory));
+        for row in rows.iter() {
+            try!(writer.write_row(row));
+        }
+        writer.finish()
+    }
+
+    pub fn next_row(&mut self) -> SqlError<Option<Vec<LiteralValue>>> {
+        match self.reader {
+            Some(ref mut reader) => read_row(reader),
+            None => Ok(None)
+        }
+    }
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        self.reader = None;
+        let _ = remove_file(&self.path);
+    }
+}
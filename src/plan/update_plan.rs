@@ -0,0 +1,58 @@
+use definitions::*;
+use schema::*;
+use tables::RowId;
+use ir::coerce_value;
+use ir::update_ir::*;
+use super::PlanOptions;
+use super::expr::{BoundExpr,bind_exprs,row_matches_filters};
+
+pub struct UpdatePlan {
+    table_index: usize,
+    column_ids: Vec<usize>,
+    column_defs: Vec<ColumnDefinition>,
+    values: Vec<BoundExpr>,
+    filters: Vec<BoundExpr>,
+    // Whether subqueries read tables while the rows are updated
+    reads_tables: bool
+}
+impl UpdatePlan {
+    pub fn run(&mut self, schema: &mut Schema) -> SqlError<usize> {
+        let column_ids = &self.column_ids;
+        let column_defs = &self.column_defs;
+        let values = &self.values;
+        let filters = &self.filters;
+        // Subqueries see the tables as they were before the update
+        let snapshot = if self.reads_tables { schema.snapshot() } else { Schema::new() };
+
+        schema.map_on_table_mut(self.table_index, |table| {
+            let mut count = 0usize;
+            let rowids: Vec<RowId> = try!(table.scan()).collect();
+            for rowid in rowids {
+                let old_row = try!(table.get_row(rowid));
+                if !try!(row_matches_filters(filters, &old_row, &snapshot)) {
+                    continue;
+                }
+
+                // Values referencing columns always see the row as it was before the update
+                let mut new_row = old_row.clone();
+                for ((&column_id, column_def), value) in column_ids.iter().zip(column_defs.iter()).zip(values.iter()) {
+                    new_row[column_id] = try!(coerce_value(&try!(value.evaluate(&old_row, &snapshot)), column_def));
+                }
+                try!(table.update(rowid, new_row));
+                count += 1;
+            }
+            Ok(count)
+        })
+    }
+}
+
+pub fn build_update_plan(update_ir: &UpdateIr, _schema: &Schema, options: &PlanOptions) -> SqlError<UpdatePlan> {
+    Ok(UpdatePlan {
+        table_index: try!(update_ir.table.stored_table_index()),
+        column_ids: update_ir.columns.iter().map(|c| c.column_index).collect(),
+        column_defs: update_ir.column_defs.clone(),
+        values: try!(bind_exprs(&update_ir.values, &[0], 0, options)),
+        filters: try!(bind_exprs(&update_ir.filters, &[0], 0, options)),
+        reads_tables: update_ir.values.iter().chain(update_ir.filters.iter()).any(|e| e.contains_subquery())
+    })
+}
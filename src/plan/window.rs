@@ -0,0 +1,375 @@
+use definitions::*;
+use schema::*;
+use ir::expr_ir::{ExprIr,AggregateIr};
+use ir::select_ir::{WindowIr,FrameBoundIr,SortKeyIr};
+use std::cmp::{max,Ordering};
+use super::{RowSource,HashKey,PlanOptions};
+use super::expr::{BoundExpr,bind_exprs,evaluate_constant,compare,arithmetic};
+use super::sort::{Sort,bind_sort_keys};
+use super::aggregate::{FrameAccumulator,BoundAggregate,bind_aggregates};
+
+// A frame bound whose offset has been evaluated
+#[derive(Debug,Clone,PartialEq)]
+enum Bound {
+    UnboundedPreceding,
+    Preceding(LiteralValue),
+    CurrentRow,
+    Following(LiteralValue),
+    UnboundedFollowing
+}
+
+fn evaluate_offset(offset: &ExprIr, schema: &Schema, options: &PlanOptions) -> SqlError<LiteralValue> {
+    match try!(evaluate_constant(offset, schema, options)) {
+        LiteralValue::Integer(i) if i >= 0 => Ok(LiteralValue::Integer(i)),
+        LiteralValue::Real(r) if r >= 0.0 => Ok(LiteralValue::Real(r)),
+        value => Err(Error::Windowing(format!("Frame offset must not be NULL or negative, got {:?}", value)))
+    }
+}
+
+fn bind_bound(bound: &FrameBoundIr, schema: &Schema, options: &PlanOptions) -> SqlError<Bound> {
+    Ok(match *bound {
+        FrameBoundIr::UnboundedPreceding => Bound::UnboundedPreceding,
+        FrameBoundIr::Preceding(ref offset) => Bound::Preceding(try!(evaluate_offset(offset, schema, options))),
+        FrameBoundIr::CurrentRow => Bound::CurrentRow,
+        FrameBoundIr::Following(ref offset) => Bound::Following(try!(evaluate_offset(offset, schema, options))),
+        FrameBoundIr::UnboundedFollowing => Bound::UnboundedFollowing
+    })
+}
+
+fn evaluate_all(exprs: &[BoundExpr], row: &[LiteralValue], schema: &Schema) -> SqlError<Vec<LiteralValue>> {
+    let mut values = Vec::with_capacity(exprs.len());
+    for expr in exprs.iter() {
+        values.push(try!(expr.evaluate(row, schema)));
+    }
+    Ok(values)
+}
+
+// The first and one past the last position of the peers of each row, the rows
+// sorting equal to it
+fn peer_ranges(order_values: &[Vec<LiteralValue>]) -> Vec<(usize, usize)> {
+    let keys: Vec<Vec<HashKey>> = order_values.iter().map(|values| values.iter().map(HashKey::from_value).collect()).collect();
+    let mut ranges = Vec::with_capacity(keys.len());
+    let mut start = 0;
+    while start < keys.len() {
+        let mut end = start + 1;
+        while end < keys.len() && keys[end] == keys[start] {
+            end += 1;
+        }
+        for _ in start..end {
+            ranges.push((start, end));
+        }
+        start = end;
+    }
+    ranges
+}
+
+// Whether a key value is on the given side of a RANGE bound, in the direction
+// of the sort. NULL keys are on neither side.
+fn reaches(value: &LiteralValue, bound: &LiteralValue, descending: bool, after: bool) -> SqlError<bool> {
+    Ok(match try!(compare(value, bound)) {
+        Some(ordering) => {
+            let ordering = if descending { ordering.reverse() } else { ordering };
+            if after { ordering != Ordering::Less } else { ordering != Ordering::Greater }
+        },
+        None => false
+    })
+}
+
+struct BoundWindow {
+    function: WindowFunction,
+    args: Vec<BoundExpr>,
+    partition_by: Vec<BoundExpr>,
+    order_by: Vec<BoundExpr>,
+    // The direction of the single ORDER BY key of a RANGE frame with offsets
+    descending: bool,
+    aggregate: Option<BoundAggregate>,
+    units: FrameUnits,
+    start: Bound,
+    end: Bound
+}
+
+impl BoundWindow {
+    fn partition_key(&self, row: &[LiteralValue], schema: &Schema) -> SqlError<Vec<HashKey>> {
+        Ok(try!(evaluate_all(&self.partition_by, row, schema)).iter().map(HashKey::from_value).collect())
+    }
+
+    // The value of the window function for each row of a sorted partition
+    fn evaluate_partition(&self, rows: &[Vec<LiteralValue>], schema: &Schema) -> SqlError<Vec<LiteralValue>> {
+        let mut order_values = Vec::with_capacity(rows.len());
+        for row in rows.iter() {
+            order_values.push(try!(evaluate_all(&self.order_by, row, schema)));
+        }
+        let peers = peer_ranges(&order_values);
+
+        let mut values = Vec::with_capacity(rows.len());
+        match self.function {
+            WindowFunction::RowNumber => {
+                for i in 0..rows.len() {
+                    values.push(LiteralValue::Integer(i as i64 + 1));
+                }
+            },
+            WindowFunction::Rank => {
+                for &(start, _) in peers.iter() {
+                    values.push(LiteralValue::Integer(start as i64 + 1));
+                }
+            },
+            WindowFunction::DenseRank => {
+                let mut rank = 0;
+                for (i, &(start, _)) in peers.iter().enumerate() {
+                    if start == i {
+                        rank += 1;
+                    }
+                    values.push(LiteralValue::Integer(rank));
+                }
+            },
+            WindowFunction::Lag | WindowFunction::Lead => {
+                for i in 0..rows.len() {
+                    values.push(try!(self.shifted_value(rows, i, schema)));
+                }
+            },
+            WindowFunction::FirstValue | WindowFunction::LastValue => {
+                for (start, end) in try!(self.frames(&order_values, &peers)).into_iter() {
+                    let position = if self.function == WindowFunction::FirstValue { start } else { end.wrapping_sub(1) };
+                    values.push(if start < end { try!(self.args[0].evaluate(&rows[position], schema)) } else { LiteralValue::Null });
+                }
+            },
+            WindowFunction::Aggregate(_) => {
+                let aggregate = match self.aggregate {
+                    Some(ref aggregate) => aggregate,
+                    None => {
+                        return Err(Error::Internal("Window aggregate was not bound".to_string()));
+                    }
+                };
+                // Frames only move forward, so their aggregate is kept up to
+                // date with the rows leaving and entering them. Leaving rows
+                // go first, so the running value never covers more rows than
+                // the frame, and rows the frame skipped never enter it.
+                let mut accumulator = FrameAccumulator::new(aggregate);
+                let (mut added, mut removed) = (0, 0);
+                for (start, end) in try!(self.frames(&order_values, &peers)).into_iter() {
+                    while removed < start {
+                        if removed < added {
+                            try!(accumulator.remove(aggregate, removed, &rows[removed], schema));
+                        }
+                        removed += 1;
+                    }
+                    added = max(added, removed);
+                    while added < end {
+                        try!(accumulator.add(aggregate, added, &rows[added], schema));
+                        added += 1;
+                    }
+                    values.push(try!(accumulator.finish(aggregate, &rows[start..end], schema)));
+                }
+            }
+        }
+        Ok(values)
+    }
+
+    // The value of LAG or LEAD, read from the row offset rows before or after
+    // the current one
+    fn shifted_value(&self, rows: &[Vec<LiteralValue>], i: usize, schema: &Schema) -> SqlError<LiteralValue> {
+        let row = &rows[i];
+        let offset = match self.args.get(1) {
+            Some(offset) => {
+                match try!(offset.evaluate(row, schema)) {
+                    LiteralValue::Null => {
+                        return Ok(LiteralValue::Null);
+                    },
+                    LiteralValue::Integer(offset) => offset,
+                    value => {
+                        return Err(Error::Type(format!("{} offset must be an Integer, got {:?}", self.function.name(), value)));
+                    }
+                }
+            },
+            None => 1
+        };
+        let target = if self.function == WindowFunction::Lag { i as i64 - offset } else { i as i64 + offset };
+        if target >= 0 && target < rows.len() as i64 {
+            self.args[0].evaluate(&rows[target as usize], schema)
+        }
+        else {
+            match self.args.get(2) {
+                Some(default) => default.evaluate(row, schema),
+                None => Ok(LiteralValue::Null)
+            }
+        }
+    }
+
+    // The first and one past the last position of the frame of each row. Both
+    // never move back from one row to the next.
+    fn frames(&self, order_values: &[Vec<LiteralValue>], peers: &[(usize, usize)]) -> SqlError<Vec<(usize, usize)>> {
+        // The rows with a key sit together, the NULL keys sorting before or
+        // after them all
+        let is_null = |values: &Vec<LiteralValue>| values.first().map(|value| *value == LiteralValue::Null).unwrap_or(true);
+        let first = order_values.iter().position(|values| !is_null(values)).unwrap_or(order_values.len());
+        let keyed_end = first + order_values[first..].iter().take_while(|values| !is_null(*values)).count();
+
+        let (mut start_cursor, mut end_cursor) = (first, first);
+        let mut frames = Vec::with_capacity(order_values.len());
+        for i in 0..order_values.len() {
+            let start = try!(self.bound_position(&self.start, true, i, order_values, peers, keyed_end, &mut start_cursor));
+            let end = try!(self.bound_position(&self.end, false, i, order_values, peers, keyed_end, &mut end_cursor));
+            frames.push((start, max(start, end)));
+        }
+        Ok(frames)
+    }
+
+    // Where a frame starting or ending at the given bound starts, or ends
+    // exclusively, for row i. The bound of a RANGE frame with an offset is
+    // searched for among the keyed rows from the cursor, where the search
+    // stopped for the previous row.
+    fn bound_position(&self, bound: &Bound, start: bool, i: usize, order_values: &[Vec<LiteralValue>], peers: &[(usize, usize)],
+                      keyed_end: usize, cursor: &mut usize) -> SqlError<usize> {
+        let len = order_values.len();
+        let (offset, preceding) = match *bound {
+            Bound::UnboundedPreceding => {
+                return Ok(0);
+            },
+            Bound::UnboundedFollowing => {
+                return Ok(len);
+            },
+            Bound::CurrentRow => {
+                return Ok(match self.units {
+                    FrameUnits::Rows => if start { i } else { i + 1 },
+                    FrameUnits::Range => if start { peers[i].0 } else { peers[i].1 }
+                });
+            },
+            Bound::Preceding(ref offset) => (offset, true),
+            Bound::Following(ref offset) => (offset, false)
+        };
+
+        match self.units {
+            FrameUnits::Rows => {
+                let offset = try!(offset.as_integer());
+                let position = if preceding { i as i64 - offset } else { i as i64 + offset };
+                let position = if start { position } else { position + 1 };
+                Ok(::std::cmp::min(max(position, 0), len as i64) as usize)
+            },
+            FrameUnits::Range => {
+                let value = &order_values[i][0];
+                // Rows with a NULL key only have their peers in range
+                if *value == LiteralValue::Null {
+                    return Ok(if start { peers[i].0 } else { peers[i].1 });
+                }
+                let operator = if preceding != self.descending { BinaryOperator::Subtract } else { BinaryOperator::Add };
+                let bound = try!(arithmetic(operator, value, offset));
+                // The frame starts at the first keyed row past the bound and
+                // ends after the last one before it
+                while *cursor < keyed_end && try!(reaches(&order_values[*cursor][0], &bound, self.descending, start)) != start {
+                    *cursor += 1;
+                }
+                Ok(*cursor)
+            }
+        }
+    }
+}
+
+// Computes a window function over its input, appending its value to each row.
+// The input is sorted on the partition keys followed by the ORDER BY keys of
+// the window, then read one partition at a time, which is held in memory.
+pub struct Window {
+    source: Box<RowSource>,
+    window: BoundWindow,
+    // The first row of the next partition along with its partition key, read
+    // while looking for the end of the current one
+    pending: Option<(Vec<HashKey>, Vec<LiteralValue>)>,
+    // The rows of the current partition in reverse order
+    rows: Vec<Vec<LiteralValue>>
+}
+
+impl Window {
+    fn read_partition(&mut self, schema: &Schema) -> SqlError<Vec<Vec<LiteralValue>>> {
+        let mut rows = Vec::new();
+        let key = match self.pending.take() {
+            Some((key, row)) => {
+                rows.push(row);
+                key
+            },
+            None => {
+                match try!(self.source.get_next_row(schema)) {
+                    Some(row) => {
+                        let key = try!(self.window.partition_key(&row, schema));
+                        rows.push(row);
+                        key
+                    },
+                    None => {
+                        return Ok(rows);
+                    }
+                }
+            }
+        };
+        while let Some(row) = try!(self.source.get_next_row(schema)) {
+            let row_key = try!(self.window.partition_key(&row, schema));
+            if row_key != key {
+                self.pending = Some((row_key, row));
+                break;
+            }
+            rows.push(row);
+        }
+        Ok(rows)
+    }
+}
+
+impl RowSource for Window {
+    fn get_next_row(&mut self, schema: &Schema) -> SqlError<Option<Vec<LiteralValue>>> {
+        if self.rows.is_empty() {
+            let partition = try!(self.read_partition(schema));
+            let values = try!(self.window.evaluate_partition(&partition, schema));
+            self.rows = partition.into_iter().zip(values.into_iter()).rev().map(|(mut row, value)| {
+                row.push(value);
+                row
+            }).collect();
+        }
+        Ok(self.rows.pop())
+    }
+}
+
+// Builds the operators computing a window function on rows whose columns are
+// found through table_offsets
+pub fn build_window(source: Box<RowSource>, window: &WindowIr, table_offsets: &[usize], schema: &Schema, options: &PlanOptions) -> SqlError<Box<RowSource>> {
+    let mut sort_keys: Vec<SortKeyIr> = window.partition_by.iter().map(|key| {
+        SortKeyIr {
+            expr: key.clone(),
+            descending: false,
+            nulls_first: false
+        }
+    }).collect();
+    sort_keys.extend(window.order_by.iter().cloned());
+    let input: Box<RowSource> = if sort_keys.is_empty() {
+        source
+    }
+    else {
+        Box::new(Sort::new(source, try!(bind_sort_keys(&sort_keys, table_offsets, options)), options))
+    };
+
+    let aggregate = match window.function {
+        WindowFunction::Aggregate(function) => {
+            let aggregate = AggregateIr {
+                function: function,
+                distinct: false,
+                arg: window.args.first().cloned()
+            };
+            Some(try!(bind_aggregates(&[aggregate], table_offsets, options)).remove(0))
+        },
+        _ => None
+    };
+    let order_by: Vec<ExprIr> = window.order_by.iter().map(|key| key.expr.clone()).collect();
+    let bound = BoundWindow {
+        function: window.function,
+        args: try!(bind_exprs(&window.args, table_offsets, 0, options)),
+        partition_by: try!(bind_exprs(&window.partition_by, table_offsets, 0, options)),
+        order_by: try!(bind_exprs(&order_by, table_offsets, 0, options)),
+        descending: window.order_by.first().map(|key| key.descending).unwrap_or(false),
+        aggregate: aggregate,
+        units: window.frame.units,
+        start: try!(bind_bound(&window.frame.start, schema, options)),
+        end: try!(bind_bound(&window.frame.end, schema, options))
+    };
+
+    Ok(Box::new(Window {
+        source: input,
+        window: bound,
+        pending: None,
+        rows: Vec::new()
+    }))
+}
@@ -0,0 +1,278 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::fs::{self, File};
+use std::path::PathBuf;
+use rustql::{self, SqlEngine, SqlStmt, SqlResult, Query, SqlError, ColumnType};
+
+mod splitter;
+pub mod output;
+
+use self::splitter::StatementSplitter;
+use self::output::{OutputMode, Formatter};
+
+const PROMPT: &'static str = "rustql> ";
+const CONTINUATION_PROMPT: &'static str = "   ...> ";
+
+const HELP: &'static str = ".help             Show this message
+.mode MODE        Set the output mode to one of table, csv, tsv, json or line
+.quit             Exit
+.read FILE        Execute the statements in FILE
+.schema [TABLE]   Show the CREATE statement of TABLE, or of all tables
+.tables           List the tables";
+
+enum MetaCommandResult {
+    Continue,
+    Quit
+}
+
+pub struct Repl {
+    engine: SqlEngine,
+    mode: OutputMode,
+    // The files being executed by .read commands, which cannot read them again
+    reading: Vec<PathBuf>
+}
+
+impl Repl {
+    pub fn new(engine: SqlEngine, mode: OutputMode) -> Repl {
+        Repl {
+            engine: engine,
+            mode: mode,
+            reading: Vec::new()
+        }
+    }
+
+    // Executes everything read from input. Returns true if a .quit command was given.
+    pub fn run<R: BufRead>(&mut self, mut input: R, interactive: bool) -> bool {
+        let mut splitter = StatementSplitter::new();
+
+        loop {
+            if interactive {
+                print!("{}", if splitter.is_empty() { PROMPT } else { CONTINUATION_PROMPT });
+                let _ = io::stdout().flush();
+            }
+
+            let mut line = String::new();
+            match input.read_line(&mut line) {
+                Ok(0) => {
+                    if interactive {
+                        println!("");
+                    }
+                    break;
+                },
+                Ok(_) => {},
+                Err(e) => {
+                    println!("Error: {}", e);
+                    break;
+                }
+            }
+
+            if splitter.is_empty() && line.trim().starts_with(".") {
+                match self.meta_command(line.trim()) {
+                    MetaCommandResult::Quit => return true,
+                    MetaCommandResult::Continue => continue
+                }
+            }
+
+            for statement in splitter.push(&line) {
+                self.execute(&statement);
+            }
+        }
+
+        // The last statement does not need to be terminated
+        if let Some(statement) = splitter.finish() {
+            self.execute(&statement);
+        }
+        false
+    }
+
+    fn execute(&mut self, statement: &str) {
+        let result = rustql::parse(statement).and_then(|stmt| {
+            match stmt {
+                SqlStmt::Select(select) => {
+                    self.print_query(Query::Select(select))
+                },
+                SqlStmt::SetOperation(operation) => {
+                    self.print_query(Query::SetOperation(operation))
+                },
+                SqlStmt::With(with) => {
+                    self.print_query(Query::With(with))
+                },
+                stmt => {
+                    let result = try!(self.engine.excecute_stmt(stmt));
+                    self.print_result(result);
+                    Ok(())
+                }
+            }
+        });
+        if let Err(e) = result {
+            println!("Error [{}]: {}",e.code(),e);
+        }
+    }
+
+    fn print_query(&mut self, query: Query) -> SqlError<()> {
+        let cursor = try!(self.engine.query(query));
+        let stdout = io::stdout();
+        let mut out = stdout.lock();
+        let mut formatter = Formatter::new(self.mode, cursor.column_names());
+
+        let _ = formatter.begin(&mut out);
+        for row in cursor {
+            match row {
+                Ok(row) => {
+                    let _ = formatter.row(&mut out, &row);
+                },
+                Err(e) => {
+                    // Terminates the rows printed so far before the error is reported
+                    let _ = formatter.finish(&mut out);
+                    return Err(e);
+                }
+            }
+        }
+        let _ = formatter.finish(&mut out);
+        Ok(())
+    }
+
+    fn print_result(&self, result: SqlResult) {
+        match result {
+            SqlResult::Message(message) => {
+                println!("{}", message);
+            },
+            SqlResult::RowsUpdated(count) => {
+                println!("{} row{} affected", count, if count == 1 { "" } else { "s" });
+            },
+            SqlResult::Rows(rows) => {
+                let columns = match rows.first() {
+                    Some(row) => (1..row.len() + 1).map(|i| format!("column{}", i)).collect(),
+                    None => Vec::new()
+                };
+                let stdout = io::stdout();
+                let mut out = stdout.lock();
+                let mut formatter = Formatter::new(self.mode, columns);
+                let _ = formatter.begin(&mut out);
+                for row in rows.iter() {
+                    let _ = formatter.row(&mut out, row);
+                }
+                let _ = formatter.finish(&mut out);
+            },
+            SqlResult::None => {}
+        }
+    }
+
+    fn meta_command(&mut self, line: &str) -> MetaCommandResult {
+        let args: Vec<&str> = line.split_whitespace().collect();
+        match (args[0], args.len()) {
+            (".quit", 1) | (".exit", 1) => {
+                return MetaCommandResult::Quit;
+            },
+            (".help", 1) => {
+                println!("{}", HELP);
+            },
+            (".mode", 1) => {
+                println!("Current output mode: {:?}. Available modes: {}", self.mode, OutputMode::names());
+            },
+            (".mode", 2) => {
+                match OutputMode::from_name(args[1]) {
+                    Some(mode) => {
+                        self.mode = mode;
+                    },
+                    None => {
+                        println!("Error: Unknown output mode {}. Available modes: {}", args[1], OutputMode::names());
+                    }
+                }
+            },
+            (".tables", 1) => {
+                for name in self.engine.table_names() {
+                    println!("{}", name);
+                }
+            },
+            (".schema", 1) => {
+                for name in self.engine.table_names() {
+                    self.print_schema(&name);
+                }
+            },
+            (".schema", 2) => {
+                self.print_schema(&args[1].to_lowercase());
+            },
+            (".read", 2) => {
+                let file = match File::open(args[1]) {
+                    Ok(file) => file,
+                    Err(e) => {
+                        println!("Error: Cannot open {}: {}", args[1], e);
+                        return MetaCommandResult::Continue;
+                    }
+                };
+                // A file reading itself, directly or through other files,
+                // would never finish
+                let path = fs::canonicalize(args[1]).unwrap_or(PathBuf::from(args[1]));
+                if self.reading.contains(&path) {
+                    println!("Error: {} is already being read", args[1]);
+                    return MetaCommandResult::Continue;
+                }
+                self.reading.push(path);
+                let quit = self.run(BufReader::new(file), false);
+                self.reading.pop();
+                if quit {
+                    return MetaCommandResult::Quit;
+                }
+            },
+            _ => {
+                println!("Error: Unknown command or invalid arguments: {}. Enter .help for help", line);
+            }
+        }
+        MetaCommandResult::Continue
+    }
+
+    fn print_schema(&self, table_name: &str) {
+        match self.engine.table_columns(table_name) {
+            Ok(columns) => {
+                let column_defs: Vec<String> = columns.iter()
+                    .map(|c| format!("{} {}", c.name, type_name(c.ctype)))
+                    .collect();
+                println!("CREATE TABLE {}({});", table_name, column_defs.join(", "));
+            },
+            Err(e) => {
+                println!("Error [{}]: {}",e.code(),e);
+            }
+        }
+    }
+}
+
+fn type_name(ctype: ColumnType) -> &'static str {
+    match ctype {
+        ColumnType::Text => "TEXT",
+        ColumnType::Bool => "BOOL",
+        ColumnType::Integer => "INTEGER",
+        ColumnType::Real => "REAL"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::{Cursor, Write};
+    use rustql::LiteralValue;
+
+    #[test]
+    fn read_files() {
+        let (first, second) = (env::temp_dir().join("rustql_read_test_1.sql"), env::temp_dir().join("rustql_read_test_2.sql"));
+        // Each file reads itself and the other one
+        for &(path, other) in [(&first, &second), (&second, &first)].iter() {
+            let table = if path == &first { "a" } else { "b" };
+            let mut file = File::create(path).unwrap();
+            write!(file, ".read {}\n.read {}\nCREATE TABLE {}(x INTEGER);\nINSERT INTO {}(x) VALUES (1);\n",
+                   path.display(), other.display(), table, table).unwrap();
+        }
+
+        let mut repl = Repl::new(SqlEngine::new(), OutputMode::Csv);
+        let command = format!(".read {}\n", first.display());
+        assert!(!repl.run(Cursor::new(command.into_bytes()), false));
+        assert!(repl.reading.is_empty());
+        assert_eq!(repl.engine.table_names(), vec!["b".to_string(), "a".to_string()]);
+        let result = repl.engine.excecute_stmt(rustql::parse("SELECT x FROM b").unwrap()).unwrap();
+        assert_eq!(result, SqlResult::Rows(vec![vec![LiteralValue::Integer(1)]]));
+
+        fs::remove_file(&first).unwrap();
+        fs::remove_file(&second).unwrap();
+    }
+}
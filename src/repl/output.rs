@@ -0,0 +1,230 @@
+use std::io::{self, Write};
+use std::iter::repeat;
+use rustql::LiteralValue;
+
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub enum OutputMode {
+    // Aligned table with borders and a header
+    Table,
+    Csv,
+    Tsv,
+    // One JSON object per row
+    Json,
+    // One "column = value" line per column
+    Line
+}
+
+impl OutputMode {
+    pub fn from_name(name: &str) -> Option<OutputMode> {
+        match &name.to_lowercase()[..] {
+            "table" | "box" => Some(OutputMode::Table),
+            "csv" => Some(OutputMode::Csv),
+            "tsv" | "tabs" => Some(OutputMode::Tsv),
+            "json" => Some(OutputMode::Json),
+            "line" => Some(OutputMode::Line),
+            _ => None
+        }
+    }
+
+    pub fn names() -> &'static str {
+        "table, csv, tsv, json, line"
+    }
+}
+
+// Writes rows in the chosen mode. Rows are written as they arrive except in
+// table mode, which has to see every row before the column widths are known.
+pub struct Formatter {
+    mode: OutputMode,
+    columns: Vec<String>,
+    buffered: Vec<Vec<String>>,
+    row_count: usize
+}
+
+impl Formatter {
+    pub fn new(mode: OutputMode, columns: Vec<String>) -> Formatter {
+        Formatter {
+            mode: mode,
+            columns: columns,
+            buffered: Vec::new(),
+            row_count: 0
+        }
+    }
+
+    pub fn begin<W: Write>(&mut self, out: &mut W) -> io::Result<()> {
+        match self.mode {
+            OutputMode::Csv => {
+                let header: Vec<String> = self.columns.iter().map(|c| csv_escape(c)).collect();
+                writeln!(out, "{}", header.join(","))
+            },
+            OutputMode::Tsv => {
+                let header: Vec<String> = self.columns.iter().map(|c| tsv_escape(c)).collect();
+                writeln!(out, "{}", header.join("\t"))
+            },
+            _ => Ok(())
+        }
+    }
+
+    pub fn row<W: Write>(&mut self, out: &mut W, row: &[LiteralValue]) -> io::Result<()> {
+        self.row_count += 1;
+        match self.mode {
+            OutputMode::Table => {
+                self.buffered.push(row.iter().map(|v| display_value(v, "NULL")).collect());
+                Ok(())
+            },
+            OutputMode::Csv => {
+                let fields: Vec<String> = row.iter().map(|v| csv_escape(&display_value(v, ""))).collect();
+                writeln!(out, "{}", fields.join(","))
+            },
+            OutputMode::Tsv => {
+                let fields: Vec<String> = row.iter().map(|v| tsv_escape(&display_value(v, ""))).collect();
+                writeln!(out, "{}", fields.join("\t"))
+            },
+            OutputMode::Json => {
+                let fields: Vec<String> = self.columns.iter().zip(row.iter())
+                    .map(|(c, v)| format!("{}:{}", json_string(c), json_value(v)))
+                    .collect();
+                writeln!(out, "{{{}}}", fields.join(","))
+            },
+            OutputMode::Line => {
+                let width = self.columns.iter().map(|c| c.chars().count()).max().unwrap_or(0);
+                if self.row_count > 1 {
+                    try!(writeln!(out, ""));
+                }
+                for (column, value) in self.columns.iter().zip(row.iter()) {
+                    try!(writeln!(out, "{} = {}", pad_left(column, width), display_value(value, "NULL")));
+                }
+                Ok(())
+            }
+        }
+    }
+
+    pub fn finish<W: Write>(&mut self, out: &mut W) -> io::Result<()> {
+        if self.mode != OutputMode::Table {
+            return Ok(());
+        }
+
+        let mut widths: Vec<usize> = self.columns.iter().map(|c| c.chars().count()).collect();
+        for row in self.buffered.iter() {
+            for (width, value) in widths.iter_mut().zip(row.iter()) {
+                *width = ::std::cmp::max(*width, value.chars().count());
+            }
+        }
+
+        let separator: Vec<String> = widths.iter().map(|&w| repeat("-").take(w + 2).collect()).collect();
+        let separator = format!("+{}+", separator.join("+"));
+
+        try!(writeln!(out, "{}", separator));
+        try!(write_table_row(out, &self.columns, &widths));
+        try!(writeln!(out, "{}", separator));
+        for row in self.buffered.iter() {
+            try!(write_table_row(out, row, &widths));
+        }
+        if !self.buffered.is_empty() {
+            try!(writeln!(out, "{}", separator));
+        }
+        self.buffered.clear();
+        writeln!(out, "({} row{})", self.row_count, if self.row_count == 1 { "" } else { "s" })
+    }
+}
+
+fn write_table_row<W: Write>(out: &mut W, values: &[String], widths: &[usize]) -> io::Result<()> {
+    let cells: Vec<String> = values.iter().zip(widths.iter())
+        .map(|(v, &w)| format!(" {} ", pad_right(v, w)))
+        .collect();
+    writeln!(out, "|{}|", cells.join("|"))
+}
+
+fn pad_right(value: &str, width: usize) -> String {
+    let padding: String = repeat(" ").take(width - value.chars().count()).collect();
+    format!("{}{}", value, padding)
+}
+
+fn pad_left(value: &str, width: usize) -> String {
+    let padding: String = repeat(" ").take(width - value.chars().count()).collect();
+    format!("{}{}", padding, value)
+}
+
+fn display_value(value: &LiteralValue, null: &str) -> String {
+    match *value {
+        LiteralValue::Null => null.to_string(),
+        _ => value.as_string().unwrap_or(String::new())
+    }
+}
+
+fn csv_escape(value: &str) -> String {
+    if value.contains(',') || value.contains('"') || value.contains('\n') || value.contains('\r') {
+        format!("\"{}\"", value.replace("\"", "\"\""))
+    }
+    else {
+        value.to_string()
+    }
+}
+
+fn tsv_escape(value: &str) -> String {
+    value.replace("\\", "\\\\").replace("\t", "\\t").replace("\n", "\\n").replace("\r", "\\r")
+}
+
+fn json_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c)
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+fn json_value(value: &LiteralValue) -> String {
+    match *value {
+        LiteralValue::Null => "null".to_string(),
+        LiteralValue::Text(ref s) => json_string(s),
+        LiteralValue::Bool(b) => format!("{}", b),
+        LiteralValue::Integer(i) => format!("{}", i),
+        LiteralValue::Real(r) => {
+            // JSON has no representation for NaN or infinity
+            if r.is_finite() { format!("{}", r) } else { "null".to_string() }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustql::LiteralValue;
+
+    fn render(mode: OutputMode) -> String {
+        let mut out = Vec::new();
+        let mut formatter = Formatter::new(mode, vec!["id".to_string(), "name".to_string()]);
+        formatter.begin(&mut out).unwrap();
+        formatter.row(&mut out, &[LiteralValue::Integer(1), LiteralValue::Text("a,\"b\"".to_string())]).unwrap();
+        formatter.row(&mut out, &[LiteralValue::Integer(22), LiteralValue::Null]).unwrap();
+        formatter.finish(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn output_modes() {
+        assert_eq!(render(OutputMode::Csv), "id,name\n1,\"a,\"\"b\"\"\"\n22,\n");
+        assert_eq!(render(OutputMode::Tsv), "id\tname\n1\ta,\"b\"\n22\t\n");
+        assert_eq!(render(OutputMode::Json), "{\"id\":1,\"name\":\"a,\\\"b\\\"\"}\n{\"id\":22,\"name\":null}\n");
+        assert_eq!(render(OutputMode::Line), "  id = 1\nname = a,\"b\"\n\n  id = 22\nname = NULL\n");
+        assert_eq!(render(OutputMode::Table),
+            "+----+-------+\n\
+             | id | name  |\n\
+             +----+-------+\n\
+             | 1  | a,\"b\" |\n\
+             | 22 | NULL  |\n\
+             +----+-------+\n\
+             (2 rows)\n");
+        assert_eq!(OutputMode::from_name("CSV"), Some(OutputMode::Csv));
+        assert_eq!(OutputMode::from_name("xml"), None);
+    }
+}
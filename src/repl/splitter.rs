@@ -0,0 +1,76 @@
+// Splits input into statements on semicolons that are not inside a string literal
+pub struct StatementSplitter {
+    buffer: String,
+    in_quote: bool
+}
+
+impl StatementSplitter {
+    pub fn new() -> StatementSplitter {
+        StatementSplitter {
+            buffer: String::new(),
+            in_quote: false
+        }
+    }
+
+    // Feeds more input and returns every statement that is now complete
+    pub fn push(&mut self, input: &str) -> Vec<String> {
+        let mut statements = Vec::new();
+        for c in input.chars() {
+            match c {
+                '\'' => {
+                    self.in_quote = !self.in_quote;
+                    self.buffer.push(c);
+                },
+                ';' if !self.in_quote => {
+                    let statement = self.buffer.trim().to_string();
+                    if !statement.is_empty() {
+                        statements.push(statement);
+                    }
+                    self.buffer.clear();
+                },
+                _ => {
+                    self.buffer.push(c);
+                }
+            }
+        }
+        statements
+    }
+
+    // True if no partial statement is waiting for more input
+    pub fn is_empty(&self) -> bool {
+        self.buffer.trim().is_empty()
+    }
+
+    // Returns whatever is left once the input is exhausted
+    pub fn finish(&mut self) -> Option<String> {
+        let statement = self.buffer.trim().to_string();
+        self.buffer.clear();
+        self.in_quote = false;
+        if statement.is_empty() {
+            None
+        }
+        else {
+            Some(statement)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_statements() {
+        let mut splitter = StatementSplitter::new();
+        assert_eq!(splitter.push("SELECT * FROM a; SELECT"), vec!["SELECT * FROM a".to_string()]);
+        assert!(!splitter.is_empty());
+        assert_eq!(splitter.push(" * FROM b;\n"), vec!["SELECT * FROM b".to_string()]);
+        assert!(splitter.is_empty());
+
+        assert_eq!(splitter.push("INSERT INTO a(c) VALUES ('x;y');;"), vec!["INSERT INTO a(c) VALUES ('x;y')".to_string()]);
+        assert_eq!(splitter.push("SELECT 'a;"), Vec::<String>::new());
+        assert_eq!(splitter.push("b' FROM c"), Vec::<String>::new());
+        assert_eq!(splitter.finish(), Some("SELECT 'a;b' FROM c".to_string()));
+        assert_eq!(splitter.finish(), None);
+    }
+}
@@ -1,20 +1,83 @@
 use definitions::*;
 use tables::*;
+use functions::{TableFunction,TableFunctions};
+use std::rc::Rc;
 
+// A table named by the WITH clause of the query being resolved
 #[derive(Clone,PartialEq,Debug)]
+pub enum CommonTable {
+    // Its rows are produced by the query wherever the table is read
+    Query {
+        name: String,
+        column_names: Vec<String>,
+        query: Query,
+        recursive: bool
+    },
+    // The rows produced by the previous iteration of a recursive query, read
+    // by its recursive term
+    WorkingTable {
+        name: String,
+        columns: Vec<ColumnDefinition>
+    }
+}
+
+impl CommonTable {
+    pub fn name(&self) -> &str {
+        match *self {
+            CommonTable::Query{ref name, ..} => &name[..],
+            CommonTable::WorkingTable{ref name, ..} => &name[..]
+        }
+    }
+}
+
+#[derive(Clone,Debug)]
 pub struct Schema {
-    tables: Vec<MemoryTable>,
+    // Tables are shared with snapshots taken by open cursors and copied on write
+    tables: Vec<Rc<Box<Table>>>,
     // If a name is None then the table was deleted
-    names: Vec<Option<String>>
+    names: Vec<Option<String>>,
+    // Hide the stored tables of the same name, the latest defined first
+    common_tables: Vec<CommonTable>,
+    functions: TableFunctions
 }
 impl Schema {
     pub fn new() -> Schema {
         Schema {
             tables: Vec::new(),
-            names: Vec::new()
+            names: Vec::new(),
+            common_tables: Vec::new(),
+            functions: TableFunctions::new()
         }
     }
 
+    pub fn find_table_function_or_err(&self, name: &str) -> SqlError<Rc<TableFunction>> {
+        self.functions.find_or_err(name)
+    }
+
+    pub fn register_table_function(&mut self, name: &str, function: Rc<TableFunction>) {
+        self.functions.register(name, function);
+    }
+
+    // Returns the position of the common table along with it
+    pub fn find_common_table(&self, name: &str) -> Option<(usize, &CommonTable)> {
+        self.common_tables.iter().enumerate().rev().find(|&(_, table)| table.name() == name)
+    }
+
+    // A copy of the schema in which the common table can also be read
+    pub fn with_common_table(&self, table: CommonTable) -> Schema {
+        let mut schema = self.clone();
+        schema.common_tables.push(table);
+        schema
+    }
+
+    // A copy of the schema that only has the common tables defined before the
+    // given position, those a common table's own query can read
+    pub fn common_tables_before(&self, index: usize) -> Schema {
+        let mut schema = self.clone();
+        schema.common_tables.truncate(index);
+        schema
+    }
+
     pub fn find_table(&self, name: &str) -> Option<usize> {
         self.names.iter().position(|x| {
             match x {
@@ -26,42 +89,61 @@ impl Schema {
         })
     }
 
+    pub fn table_names(&self) -> Vec<String> {
+        self.names.iter().filter_map(|x| x.clone()).collect()
+    }
+
     pub fn find_table_or_err(&self, name: &str) -> SqlError<usize> {
-        self.find_table(name).ok_or(format!("Table {} doesn't exist", name))
+        self.find_table(name).ok_or(Error::UnknownTable(name.to_string()))
     }
 
     pub fn map_on_table_mut<F,H>(&mut self, index: usize, mut closure: F) -> SqlError<H>
-        where F: FnMut(&mut MemoryTable) -> SqlError<H> {
+        where F: FnMut(&mut Table) -> SqlError<H> {
             match self.tables.get_mut(index) {
                 Some(table) => {
-                    closure(table)
+                    if Rc::get_mut(table).is_none() {
+                        let copy = table.box_clone();
+                        *table = Rc::new(copy);
+                    }
+                    match Rc::get_mut(table) {
+                        Some(table) => closure(&mut **table),
+                        None => Err(Error::Internal(format!("Table index {} is still shared in map_on_table_mut", index)))
+                    }
                 },
                 None => {
-                    Err(format!("Internal Error: Table index {} doesn't exist in map_on_table_mut", index))
+                    Err(Error::Internal(format!("Table index {} doesn't exist in map_on_table_mut", index)))
                 }
             }
     }
 
     pub fn map_on_table<F,H>(&self, index: usize, mut closure: F) -> SqlError<H>
-        where F: FnMut(&MemoryTable) -> SqlError<H> {
+        where F: FnMut(&Table) -> SqlError<H> {
             match self.tables.get(index) {
                 Some(table) => {
-                    closure(table)
+                    closure(&***table)
                 },
                 None => {
-                    Err(format!("Internal Error: Table index {} doesn't exist in map_on_table", index))
+                    Err(Error::Internal(format!("Table index {} doesn't exist in map_on_table", index)))
                 }
             }
     }
 
 
-    pub fn create_table(&mut self, table_name: &String) -> SqlError<usize> {
+    // Returns a read-only view of the schema as it is right now. Tables are only
+    // copied if they are later modified through the original schema.
+    pub fn snapshot(&self) -> Schema {
+        self.clone()
+    }
+
+    // Adds a table, stored in memory by tables created with SQL or by any
+    // other backend registered by the application
+    pub fn create_table(&mut self, table_name: &String, table: Box<Table>) -> SqlError<usize> {
         if self.find_table(table_name).is_some() {
-            return Err(format!("Table {} already exists",table_name));
+            return Err(Error::DuplicateTable(table_name.clone()));
         }
 
         self.names.push(Some(table_name.clone()));
-        self.tables.push(MemoryTable::new());
+        self.tables.push(Rc::new(table));
         Ok(self.names.len()-1)
 
     }
@@ -73,7 +155,7 @@ impl Schema {
                 Ok(())
             },
             None => {
-                Err(format!("Internal Error: Table index {} doesn't exist in drop_table", index))
+                Err(Error::Internal(format!("Table index {} doesn't exist in drop_table", index)))
             }
         }
     }
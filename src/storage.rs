@@ -0,0 +1,683 @@
+// Database files hold the tables created with SQL. Each table is stored in
+// pages of its own, so a statement changing a table mostly changes pages of
+// that table, and saving writes only the pages whose content changed.
+//
+// The whole content is still encoded on every save and compared with the
+// content last written, which is kept in memory: a save costs time and memory
+// in proportion to the size of the database, and disk writes in proportion to
+// the change. Rows are stored one after the other, so inserted rows change the
+// last pages of their table, while a value changing length or a deleted row
+// moves the rows after it, whose pages are rewritten. A table needing more or
+// fewer pages moves the tables after it.
+//
+// Pages are overwritten in place, guarded by a rollback journal next to the
+// file. The previous content of the pages about to change is written to the
+// journal and synced before any page is written, and the save is complete
+// once the journal is removed. A journal found when opening or saving the file
+// is left by an incomplete save. If it was completely written the previous
+// content is restored from it, otherwise no page was written yet and it is
+// discarded.
+//
+// Format version 1. Integers are little endian.
+//
+// The file is a sequence of pages of PAGE_SIZE bytes. Each page starts with:
+//   u32  CRC-32 (IEEE) of the rest of the page
+//   u32  number of payload bytes following, the rest of the page is zeroed
+// The payload of page 0 is the file header:
+//   8 bytes  magic "RUSTQLDB"
+//   u32      format version
+//   u32      page size
+//   u64      length of the catalog
+// The following pages hold segments, whose data are the concatenated payloads
+// of their pages. Each segment starts on a page of its own and all its pages
+// but the last are full. The catalog segment comes first:
+//   u64  number of tables
+//   then for each table:
+//     string  name
+//     u64     number of columns
+//     for each column, a string name and a u8 type: 0 Text, 1 Bool,
+//     2 Integer, 3 Real
+//     u64     length of the table segment
+// followed by the segment of each table, in the order of the catalog:
+//   u64  number of rows
+//   for each row, its value in every column
+// Strings are a u64 byte length followed by UTF-8 bytes. Values are a tag
+// byte followed by the value: 0 NULL, 1 Text as a string, 2 Bool as a byte,
+// 3 Integer as an i64, 4 Real as the bits of an f64.
+//
+// The journal is:
+//   8 bytes  magic "RUSTQLJN"
+//   u64      length of the file before the save
+//   u64      number of pages
+//   for each page, its u64 number and its PAGE_SIZE bytes before the save
+//   u32      CRC-32 of the bytes before it
+
+use definitions::*;
+use schema::*;
+use tables::MemoryTable;
+use std::cmp::min;
+use std::ffi::OsString;
+use std::fs::{self,File,OpenOptions,rename};
+use std::io::{self,Read,Seek,SeekFrom,Write};
+use std::path::{Path,PathBuf};
+
+pub const FORMAT_VERSION: u32 = 1;
+pub const PAGE_SIZE: usize = 4096;
+const MAGIC: &'static [u8] = b"RUSTQLDB";
+const JOURNAL_MAGIC: &'static [u8] = b"RUSTQLJN";
+const PAGE_HEADER_SIZE: usize = 8;
+const PAGE_PAYLOAD_SIZE: usize = PAGE_SIZE - PAGE_HEADER_SIZE;
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes.iter() {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn put_u32(buf: &mut Vec<u8>, value: u32) {
+    for i in 0..4 {
+        buf.push((value >> (i * 8)) as u8);
+    }
+}
+
+fn put_u64(buf: &mut Vec<u8>, value: u64) {
+    for i in 0..8 {
+        buf.push((value >> (i * 8)) as u8);
+    }
+}
+
+fn put_string(buf: &mut Vec<u8>, s: &str) {
+    put_u64(buf, s.len() as u64);
+    buf.extend(s.as_bytes().iter().cloned());
+}
+
+fn put_value(buf: &mut Vec<u8>, value: &LiteralValue) {
+    match *value {
+        LiteralValue::Null => buf.push(0),
+        LiteralValue::Text(ref s) => {
+            buf.push(1);
+            put_string(buf, s);
+        },
+        LiteralValue::Bool(b) => {
+            buf.push(2);
+            buf.push(b as u8);
+        },
+        LiteralValue::Integer(i) => {
+            buf.push(3);
+            put_u64(buf, i as u64);
+        },
+        LiteralValue::Real(r) => {
+            buf.push(4);
+            put_u64(buf, r.to_bits());
+        }
+    }
+}
+
+fn type_tag(ctype: ColumnType) -> u8 {
+    match ctype {
+        ColumnType::Text => 0,
+        ColumnType::Bool => 1,
+        ColumnType::Integer => 2,
+        ColumnType::Real => 3
+    }
+}
+
+// Reads the data back, failing on anything a written file cannot hold
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> SqlError<&'a [u8]> {
+        if len > self.data.len() - self.position {
+            return Err(Error::Corrupted("Unexpected end of data".to_string()));
+        }
+        let bytes = &self.data[self.position..self.position + len];
+        self.position += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> SqlError<u8> {
+        Ok(try!(self.bytes(1))[0])
+    }
+
+    fn u32(&mut self) -> SqlError<u32> {
+        Ok(try!(self.bytes(4)).iter().rev().fold(0u32, |value, &byte| (value << 8) | byte as u32))
+    }
+
+    fn u64(&mut self) -> SqlError<u64> {
+        Ok(try!(self.bytes(8)).iter().rev().fold(0u64, |value, &byte| (value << 8) | byte as u64))
+    }
+
+    // Counts are checked against the data left so a corrupted count cannot
+    // make the reader allocate more than the file holds
+    fn count(&mut self) -> SqlError<usize> {
+        let count = try!(self.u64());
+        if count > (self.data.len() - self.position) as u64 {
+            return Err(Error::Corrupted(format!("Count {} exceeds the data", count)));
+        }
+        Ok(count as usize)
+    }
+
+    fn string(&mut self) -> SqlError<String> {
+        let len = try!(self.count());
+        let bytes = try!(self.bytes(len));
+        String::from_utf8(bytes.to_vec()).map_err(|_| Error::Corrupted("Invalid UTF-8 string".to_string()))
+    }
+
+    fn column_type(&mut self) -> SqlError<ColumnType> {
+        match try!(self.u8()) {
+            0 => Ok(ColumnType::Text),
+            1 => Ok(ColumnType::Bool),
+            2 => Ok(ColumnType::Integer),
+            3 => Ok(ColumnType::Real),
+            tag => Err(Error::Corrupted(format!("Invalid column type {}", tag)))
+        }
+    }
+
+    fn value(&mut self) -> SqlError<LiteralValue> {
+        match try!(self.u8()) {
+            0 => Ok(LiteralValue::Null),
+            1 => Ok(LiteralValue::Text(try!(self.string()))),
+            2 => Ok(LiteralValue::Bool(try!(self.u8()) != 0)),
+            3 => Ok(LiteralValue::Integer(try!(self.u64()) as i64)),
+            4 => Ok(LiteralValue::Real(f64::from_bits(try!(self.u64())))),
+            tag => Err(Error::Corrupted(format!("Invalid value tag {}", tag)))
+        }
+    }
+}
+
+// The data of the catalog segment followed by the data of each table segment
+fn encode_segments(schema: &Schema) -> SqlError<Vec<Vec<u8>>> {
+    let mut tables = Vec::new();
+    for name in schema.table_names() {
+        let index = try!(schema.find_table_or_err(&name));
+        if try!(schema.map_on_table(index, |table| Ok(table.is_persistent()))) {
+            tables.push((name, index));
+        }
+    }
+
+    let mut catalog = Vec::new();
+    let mut segments = Vec::new();
+    put_u64(&mut catalog, tables.len() as u64);
+    for &(ref name, index) in tables.iter() {
+        let mut data = Vec::new();
+        put_string(&mut catalog, name);
+        try!(schema.map_on_table(index, |table| {
+            put_u64(&mut catalog, table.columns().len() as u64);
+            for column in table.columns().iter() {
+                put_string(&mut catalog, &column.name);
+                catalog.push(type_tag(column.ctype));
+            }
+            let mut rows = Vec::new();
+            for rowid in try!(table.scan()) {
+                rows.push(try!(table.get_row(rowid)));
+            }
+            put_u64(&mut data, rows.len() as u64);
+            for row in rows.iter() {
+                for value in row.iter() {
+                    put_value(&mut data, value);
+                }
+            }
+            Ok(())
+        }));
+        put_u64(&mut catalog, data.len() as u64);
+        segments.push(data);
+    }
+    segments.insert(0, catalog);
+    Ok(segments)
+}
+
+// Fills a table with the rows of its segment, checking each value suits the
+// type of its column
+fn decode_rows(data: &[u8], types: &[ColumnType], table: &mut MemoryTable) -> SqlError<()> {
+    let mut reader = Reader {
+        data: data,
+        position: 0
+    };
+    let row_count = try!(reader.count());
+    // Every value takes at least a byte
+    match row_count.checked_mul(types.len()) {
+        Some(values) if values <= data.len() - reader.position => {},
+        _ => {
+            return Err(Error::Corrupted(format!("{} rows of {} columns exceed the data", row_count, types.len())));
+        }
+    }
+    for _ in 0..row_count {
+        let mut row = Vec::with_capacity(types.len());
+        for &ctype in types.iter() {
+            let value = try!(reader.value());
+            if value != LiteralValue::Null && ColumnType::accomodate_literal(&value) != ctype {
+                return Err(Error::Corrupted(format!("{:?} value in a {:?} column", ColumnType::accomodate_literal(&value), ctype)));
+            }
+            row.push(value);
+        }
+        try!(table.insert_row(row));
+    }
+    if reader.position != data.len() {
+        return Err(Error::Corrupted("Unexpected data after the last row".to_string()));
+    }
+    Ok(())
+}
+
+fn push_page(file: &mut Vec<u8>, payload: &[u8]) {
+    let start = file.len();
+    put_u32(file, 0);
+    put_u32(file, payload.len() as u32);
+    file.extend(payload.iter().cloned());
+    file.resize(start + PAGE_SIZE, 0);
+    let checksum = crc32(&file[start + 4..]);
+    for i in 0..4 {
+        file[start + i] = (checksum >> (i * 8)) as u8;
+    }
+}
+
+// Checks a page and returns its payload
+fn page_payload(file: &[u8], page: usize) -> SqlError<&[u8]> {
+    let bytes = &file[page * PAGE_SIZE..(page + 1) * PAGE_SIZE];
+    let mut reader = Reader {
+        data: bytes,
+        position: 0
+    };
+    let checksum = try!(reader.u32());
+    if checksum != crc32(&bytes[4..]) {
+        return Err(Error::Corrupted(format!("Checksum mismatch in page {}", page)));
+    }
+    let len = try!(reader.u32()) as usize;
+    if len > PAGE_PAYLOAD_SIZE {
+        return Err(Error::Corrupted(format!("Invalid payload length {} in page {}", len, page)));
+    }
+    Ok(&bytes[PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + len])
+}
+
+// Reads the data of a segment of the given length starting at a page. Returns
+// it along with the page following the segment.
+fn read_segment(file: &[u8], first_page: usize, len: u64) -> SqlError<(Vec<u8>, usize)> {
+    let mut data = Vec::new();
+    let mut page = first_page;
+    while (data.len() as u64) < len {
+        if page >= file.len() / PAGE_SIZE {
+            return Err(Error::Corrupted(format!("Segment starting at page {} exceeds the file", first_page)));
+        }
+        let payload = try!(page_payload(file, page));
+        let expected = min(PAGE_PAYLOAD_SIZE as u64, len - data.len() as u64) as usize;
+        if payload.len() != expected {
+            return Err(Error::Corrupted(format!("Expected {} payload bytes in page {}, found {}", expected, page, payload.len())));
+        }
+        data.extend(payload.iter().cloned());
+        page += 1;
+    }
+    Ok((data, page))
+}
+
+// The whole content of a database file holding the tables of the schema
+pub fn encode(schema: &Schema) -> SqlError<Vec<u8>> {
+    let segments = try!(encode_segments(schema));
+
+    let mut header = Vec::new();
+    header.extend(MAGIC.iter().cloned());
+    put_u32(&mut header, FORMAT_VERSION);
+    put_u32(&mut header, PAGE_SIZE as u32);
+    put_u64(&mut header, segments[0].len() as u64);
+
+    let mut file = Vec::new();
+    push_page(&mut file, &header);
+    for segment in segments.iter() {
+        for chunk in segment.chunks(PAGE_PAYLOAD_SIZE) {
+            push_page(&mut file, chunk);
+        }
+    }
+    Ok(file)
+}
+
+// Rebuilds the tables stored in the content of a database file
+pub fn decode(file: &[u8]) -> SqlError<Schema> {
+    if file.len() < PAGE_SIZE || file.len() % PAGE_SIZE != 0 {
+        return Err(Error::Corrupted(format!("File size {} is not a multiple of the page size", file.len())));
+    }
+    let mut header = Reader {
+        data: try!(page_payload(file, 0)),
+        position: 0
+    };
+    if try!(header.bytes(MAGIC.len())) != MAGIC {
+        return Err(Error::Corrupted("Not a database file".to_string()));
+    }
+    let version = try!(header.u32());
+    if version != FORMAT_VERSION {
+        return Err(Error::Unsupported(format!("Database file format version {}", version)));
+    }
+    let page_size = try!(header.u32());
+    if page_size as usize != PAGE_SIZE {
+        return Err(Error::Unsupported(format!("Database page size {}", page_size)));
+    }
+    let catalog_len = try!(header.u64());
+
+    let (catalog, mut page) = try!(read_segment(file, 1, catalog_len));
+    let mut reader = Reader {
+        data: &catalog,
+        position: 0
+    };
+    let mut schema = Schema::new();
+    let table_count = try!(reader.count());
+    for _ in 0..table_count {
+        let name = try!(reader.string());
+        let mut table = MemoryTable::new();
+        let column_count = try!(reader.count());
+        let mut types = Vec::with_capacity(column_count);
+        for _ in 0..column_count {
+            let name = try!(reader.string());
+            let ctype = try!(reader.column_type());
+            table.add_column(ColumnDefinition {
+                name: name,
+                ctype: ctype
+            });
+            types.push(ctype);
+        }
+        let (data, next_page) = try!(read_segment(file, page, try!(reader.u64())));
+        page = next_page;
+        try!(decode_rows(&data, &types, &mut table));
+        try!(schema.create_table(&name, Box::new(table)));
+    }
+    if reader.position != catalog.len() {
+        return Err(Error::Corrupted("Unexpected data after the last table".to_string()));
+    }
+    if page != file.len() / PAGE_SIZE {
+        return Err(Error::Corrupted(format!("Unexpected pages after page {}", page)));
+    }
+    Ok(schema)
+}
+
+fn io_error(path: &Path, err: io::Error) -> Error {
+    Error::Io(format!("{}: {}", path.display(), err))
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = OsString::from(path.as_os_str());
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+fn journal_path(path: &Path) -> PathBuf {
+    with_suffix(path, ".journal")
+}
+
+// Syncs the directory holding a file, making the creation, renaming or
+// removal of the file durable
+fn sync_directory(path: &Path) -> SqlError<()> {
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from(".")
+    };
+    File::open(&directory).and_then(|directory| directory.sync_all()).map_err(|err| io_error(&directory, err))
+}
+
+// Writes a new file and syncs it. The file is removed if that fails.
+fn write_file(path: &Path, content: &[u8]) -> SqlError<()> {
+    match File::create(path).and_then(|mut file| file.write_all(content).and_then(|_| file.sync_all())) {
+        Ok(()) => Ok(()),
+        Err(err) => {
+            let _ = fs::remove_file(path);
+            Err(io_error(path, err))
+        }
+    }
+}
+
+// Writes pages at their place in a file, then sets its length and syncs it
+fn write_pages(file: &mut File, pages: &[(usize, &[u8])], len: u64) -> io::Result<()> {
+    for &(page, bytes) in pages.iter() {
+        try!(file.seek(SeekFrom::Start((page * PAGE_SIZE) as u64)));
+        try!(file.write_all(bytes));
+    }
+    try!(file.set_len(len));
+    file.sync_all()
+}
+
+fn encode_journal(len: u64, pages: &[(usize, &[u8])]) -> Vec<u8> {
+    let mut journal = Vec::with_capacity(pages.len() * (PAGE_SIZE + 8) + 28);
+    journal.extend(JOURNAL_MAGIC.iter().cloned());
+    put_u64(&mut journal, len);
+    put_u64(&mut journal, pages.len() as u64);
+    for &(page, bytes) in pages.iter() {
+        put_u64(&mut journal, page as u64);
+        journal.extend(bytes.iter().cloned());
+    }
+    let checksum = crc32(&journal);
+    put_u32(&mut journal, checksum);
+    journal
+}
+
+// The length of the file before the save and its pages held by a journal,
+// None if the journal was not completely written
+fn decode_journal(journal: &[u8]) -> SqlError<Option<(u64, Vec<(usize, &[u8])>)>> {
+    if journal.len() < JOURNAL_MAGIC.len() + 20 {
+        return Ok(None);
+    }
+    let content = &journal[..journal.len() - 4];
+    let mut checksum = Reader {
+        data: &journal[journal.len() - 4..],
+        position: 0
+    };
+    if try!(checksum.u32()) != crc32(content) {
+        return Ok(None);
+    }
+    let mut reader = Reader {
+        data: content,
+        position: 0
+    };
+    if try!(reader.bytes(JOURNAL_MAGIC.len())) != JOURNAL_MAGIC {
+        return Err(Error::Corrupted("Not a database journal".to_string()));
+    }
+    let len = try!(reader.u64());
+    let page_count = try!(reader.count());
+    let mut pages = Vec::with_capacity(page_count);
+    for _ in 0..page_count {
+        let page = try!(reader.u64()) as usize;
+        pages.push((page, try!(reader.bytes(PAGE_SIZE))));
+    }
+    if reader.position != content.len() {
+        return Err(Error::Corrupted("Unexpected data after the last journal page".to_string()));
+    }
+    Ok(Some((len, pages)))
+}
+
+// Undoes the incomplete save that left a journal next to the file, if any
+fn recover(path: &Path) -> SqlError<()> {
+    let journal_path = journal_path(path);
+    let mut journal = Vec::new();
+    match File::open(&journal_path) {
+        Ok(mut file) => {
+            try!(file.read_to_end(&mut journal).map_err(|err| io_error(&journal_path, err)));
+        },
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => {
+            return Ok(());
+        },
+        Err(err) => {
+            return Err(io_error(&journal_path, err));
+        }
+    }
+    if let Some((len, pages)) = try!(decode_journal(&journal)) {
+        try!(OpenOptions::new().write(true).open(path)
+             .and_then(|mut file| write_pages(&mut file, &pages, len))
+             .map_err(|err| io_error(path, err)));
+    }
+    try!(fs::remove_file(&journal_path).map_err(|err| io_error(&journal_path, err)));
+    sync_directory(path)
+}
+
+// A database file along with the content last written to it, which the
+// content of the next save is compared with
+pub struct DatabaseFile {
+    path: PathBuf,
+    content: Vec<u8>
+}
+
+impl DatabaseFile {
+    // Opens a database file and reads its tables. A missing file is created
+    // empty, written to a temporary file renamed once complete.
+    pub fn open(path: &Path) -> SqlError<(DatabaseFile, Schema)> {
+        if !path.exists() {
+            let schema = Schema::new();
+            let content = try!(encode(&schema));
+            let temp_path = with_suffix(path, ".tmp");
+            try!(write_file(&temp_path, &content));
+            if let Err(err) = rename(&temp_path, path) {
+                let _ = fs::remove_file(&temp_path);
+                return Err(io_error(path, err));
+            }
+            try!(sync_directory(path));
+            return Ok((DatabaseFile { path: path.to_path_buf(), content: content }, schema));
+        }
+
+        try!(recover(path));
+        let mut content = Vec::new();
+        try!(File::open(path)
+             .and_then(|mut file| file.read_to_end(&mut content))
+             .map_err(|err| io_error(path, err)));
+        let schema = try!(decode(&content));
+        Ok((DatabaseFile { path: path.to_path_buf(), content: content }, schema))
+    }
+
+    // Replaces the content of the file with the tables of the schema, writing
+    // the pages that changed since the last save
+    pub fn save(&mut self, schema: &Schema) -> SqlError<()> {
+        let content = try!(encode(schema));
+        // A failed save may have left some of its pages written
+        try!(recover(&self.path));
+        {
+            let new_pages = content.len() / PAGE_SIZE;
+            let changed: Vec<(usize, &[u8])> = content.chunks(PAGE_SIZE).enumerate().filter(|&(page, bytes)| {
+                (page + 1) * PAGE_SIZE > self.content.len() || &self.content[page * PAGE_SIZE..(page + 1) * PAGE_SIZE] != bytes
+            }).collect();
+            if changed.is_empty() && content.len() == self.content.len() {
+                return Ok(());
+            }
+            // The pages a rollback must restore, the changed ones and those
+            // past the new end of the file
+            let previous: Vec<(usize, &[u8])> = self.content.chunks(PAGE_SIZE).enumerate().filter(|&(page, bytes)| {
+                page >= new_pages || &content[page * PAGE_SIZE..(page + 1) * PAGE_SIZE] != bytes
+            }).collect();
+
+            let journal_path = journal_path(&self.path);
+            try!(write_file(&journal_path, &encode_journal(self.content.len() as u64, &previous)));
+            try!(sync_directory(&self.path));
+            // Until the journal is removed the save is rolled back on failure
+            try!(OpenOptions::new().write(true).open(&self.path)
+                 .and_then(|mut file| write_pages(&mut file, &changed, content.len() as u64))
+                 .map_err(|err| io_error(&self.path, err)));
+            try!(fs::remove_file(&journal_path).map_err(|err| io_error(&journal_path, err)));
+            try!(sync_directory(&self.path));
+        }
+        self.content = content;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::{crc32,put_u64,put_value,decode_rows,encode_journal,journal_path};
+    use definitions::*;
+    use schema::*;
+    use tables::MemoryTable;
+    use std::env;
+    use std::fs::{self,File};
+    use std::io::Write;
+    use libc;
+
+    #[test]
+    fn checksum() {
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn pages() {
+        let mut table = MemoryTable::new();
+        table.add_column(ColumnDefinition { name: "t".to_string(), ctype: ColumnType::Text });
+        // Enough rows to span several pages
+        for i in 0..1000 {
+            table.insert_row(vec![LiteralValue::Text(format!("row {}", i))]).unwrap();
+        }
+        let mut schema = Schema::new();
+        schema.create_table(&"big".to_string(), Box::new(table)).unwrap();
+
+        let mut file = encode(&schema).unwrap();
+        assert_eq!(file.len() % PAGE_SIZE, 0);
+        assert!(file.len() / PAGE_SIZE > 2);
+        let decoded = decode(&file).unwrap();
+        let index = decoded.find_table("big").unwrap();
+        assert_eq!(decoded.map_on_table(index, |table| table.get_row(999)).unwrap(), vec![LiteralValue::Text("row 999".to_string())]);
+
+        // Flipping a bit of any page is detected
+        file[2 * PAGE_SIZE + 100] ^= 1;
+        assert_eq!(decode(&file).unwrap_err().code(), "XX001");
+        file[2 * PAGE_SIZE + 100] ^= 1;
+        file.truncate(file.len() - PAGE_SIZE);
+        assert_eq!(decode(&file).unwrap_err().code(), "XX001");
+    }
+
+    #[test]
+    fn rows() {
+        let mut table = MemoryTable::new();
+        let types = [ColumnType::Integer, ColumnType::Text, ColumnType::Bool];
+        // Four rows of three values cannot fit in four bytes
+        let mut data = Vec::new();
+        put_u64(&mut data, 4);
+        data.extend([0u8, 0, 0, 0].iter().cloned());
+        assert_eq!(decode_rows(&data, &types, &mut table).unwrap_err().code(), "XX001");
+        let mut data = Vec::new();
+        put_u64(&mut data, 1);
+        put_value(&mut data, &LiteralValue::Text("1".to_string()));
+        assert_eq!(decode_rows(&data, &types[..1], &mut table).unwrap_err().code(), "XX001");
+    }
+
+    #[test]
+    fn journal() {
+        let path = env::temp_dir().join(format!("rustql-journal-test-{}.db", unsafe { libc::getpid() }));
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(journal_path(&path));
+        let text = |s: &str| vec![LiteralValue::Text(s.to_string())];
+        let first_row = |schema: &Schema| {
+            let index = schema.find_table("t").unwrap();
+            schema.map_on_table(index, |table| table.get_row(0)).unwrap()
+        };
+
+        let (mut file, mut schema) = DatabaseFile::open(&path).unwrap();
+        let mut table = MemoryTable::new();
+        table.add_column(ColumnDefinition { name: "t".to_string(), ctype: ColumnType::Text });
+        table.insert_row(text("before")).unwrap();
+        schema.create_table(&"t".to_string(), Box::new(table)).unwrap();
+        file.save(&schema).unwrap();
+        let before = encode(&schema).unwrap();
+        let index = schema.find_table("t").unwrap();
+        schema.map_on_table_mut(index, |table| table.update(0, text("after"))).unwrap();
+        let after = encode(&schema).unwrap();
+        let previous: Vec<(usize, &[u8])> = before.chunks(PAGE_SIZE).enumerate().collect();
+
+        // A save interrupted once its pages are written is rolled back
+        File::create(journal_path(&path)).unwrap().write_all(&encode_journal(before.len() as u64, &previous)).unwrap();
+        File::create(&path).unwrap().write_all(&after).unwrap();
+        let (_, recovered) = DatabaseFile::open(&path).unwrap();
+        assert_eq!(first_row(&recovered), text("before"));
+        assert!(!journal_path(&path).exists());
+
+        // A journal that was not completely written is discarded
+        let mut journal = encode_journal(before.len() as u64, &previous);
+        journal.pop();
+        File::create(journal_path(&path)).unwrap().write_all(&journal).unwrap();
+        let (mut file, recovered) = DatabaseFile::open(&path).unwrap();
+        assert_eq!(first_row(&recovered), text("before"));
+        assert!(!journal_path(&path).exists());
+
+        file.save(&schema).unwrap();
+        let (_, saved) = DatabaseFile::open(&path).unwrap();
+        assert_eq!(first_row(&saved), text("after"));
+        fs::remove_file(&path).unwrap();
+    }
+}
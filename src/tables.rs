@@ -2,9 +2,70 @@ use std::vec::Vec;
 use definitions::*;
 use std::iter::repeat;
 use bit_vec::BitVec;
+use std::fmt;
 
 pub type RowId = usize;
 
+// Storage for the rows of a table. Rows are identified by the RowId they were
+// given when inserted, which must stay valid until they are deleted. Tables
+// that cannot be modified can rely on the default insert, update and delete,
+// which fail.
+pub trait Table {
+    fn columns(&self) -> &[ColumnDefinition];
+
+    // The number of rows, used to plan queries
+    fn len(&self) -> usize;
+
+    // The ids of every row, read by full table scans
+    fn scan(&self) -> SqlError<Box<Iterator<Item=RowId>>>;
+
+    fn get_row(&self, rowid: RowId) -> SqlError<Vec<LiteralValue>>;
+
+    fn insert(&mut self, _row: Vec<LiteralValue>) -> SqlError<RowId> {
+        Err(Error::Unsupported("Inserting into a read-only table".to_string()))
+    }
+
+    fn update(&mut self, _rowid: RowId, _row: Vec<LiteralValue>) -> SqlError<()> {
+        Err(Error::Unsupported("Updating a read-only table".to_string()))
+    }
+
+    fn delete(&mut self, _rowid: RowId) -> SqlError<()> {
+        Err(Error::Unsupported("Deleting from a read-only table".to_string()))
+    }
+
+    // The ids of the rows whose column holds the value, found through an
+    // index. None if the table has no index on the column, in which case it
+    // is scanned.
+    fn lookup(&self, _column: usize, _value: &LiteralValue) -> SqlError<Option<Vec<RowId>>> {
+        Ok(None)
+    }
+
+    // Whether the rows are saved to the database file of an engine opened on
+    // one. Other backends keep their rows themselves and are registered again
+    // each time the file is opened.
+    fn is_persistent(&self) -> bool {
+        false
+    }
+
+    // A copy of the table, made when a table shared with a snapshot is
+    // modified so the snapshot keeps seeing the rows as they were. Tables
+    // whose rows live elsewhere can return another handle on them, in which
+    // case open cursors see later changes.
+    fn box_clone(&self) -> Box<Table>;
+}
+
+impl fmt::Debug for Table {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut rows = Vec::new();
+        if let Ok(rowids) = self.scan() {
+            for rowid in rowids {
+                rows.push(self.get_row(rowid));
+            }
+        }
+        write!(f, "Table {{ columns: {:?}, rows: {:?} }}", self.columns(), rows)
+    }
+}
+
 #[derive(Clone,PartialEq,Debug)]
 pub struct MemoryTable {
     column_defs: Vec<ColumnDefinition>,
@@ -44,7 +105,7 @@ impl MemoryTable {
     pub fn insert_row(&mut self, mut row: Vec<LiteralValue>) -> SqlError<RowId> {
         let num_columns = row.len();
         if num_columns != self.column_defs.len() {
-            return Err("Wrong number of columns for table".to_string());
+            return Err(Error::Internal("Wrong number of columns for table".to_string()));
         }
 
         for (dst, col) in self.columns_data.iter_mut().zip(row.drain(..)) {
@@ -55,10 +116,38 @@ impl MemoryTable {
         return Ok(self.raw_len()-1);
     }
 
+    pub fn update_row(&mut self, rowid: RowId, mut row: Vec<LiteralValue>) -> SqlError<()> {
+        if row.len() != self.column_defs.len() {
+            return Err(Error::Internal("Wrong number of columns for table".to_string()));
+        }
+
+        match self.rows_status.get(rowid) {
+            None => { Err(Error::Internal(format!("Row {} doesn't exist", rowid))) },
+            Some(false) => { Err(Error::Internal(format!("Row {} is no longer valid", rowid))) },
+            Some(true) => {
+                for (dst, col) in self.columns_data.iter_mut().zip(row.drain(..)) {
+                    dst[rowid] = col;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    pub fn delete_row(&mut self, rowid: RowId) -> SqlError<()> {
+        match self.rows_status.get(rowid) {
+            None => { Err(Error::Internal(format!("Row {} doesn't exist", rowid))) },
+            Some(false) => { Err(Error::Internal(format!("Row {} is no longer valid", rowid))) },
+            Some(true) => {
+                self.rows_status.set(rowid, false);
+                Ok(())
+            }
+        }
+    }
+
     pub fn get_row(&self, rowid: RowId) -> SqlError<Vec<LiteralValue>> {
         match self.rows_status.get(rowid) {
-            None => { Err(format!("Row {} doesn't exist", rowid)) },
-            Some(false) => { Err(format!("Row {} is no longer valid", rowid)) },
+            None => { Err(Error::Internal(format!("Row {} doesn't exist", rowid))) },
+            Some(false) => { Err(Error::Internal(format!("Row {} is no longer valid", rowid))) },
             Some(true) => {
                 let mut row = Vec::new();
                 for col in self.columns_data.iter() {
@@ -81,6 +170,44 @@ impl MemoryTable {
     }
 }
 
+impl Table for MemoryTable {
+    fn columns(&self) -> &[ColumnDefinition] {
+        MemoryTable::columns(self)
+    }
+
+    fn len(&self) -> usize {
+        MemoryTable::len(self)
+    }
+
+    fn scan(&self) -> SqlError<Box<Iterator<Item=RowId>>> {
+        Ok(Box::new(self.rowid_iter()))
+    }
+
+    fn get_row(&self, rowid: RowId) -> SqlError<Vec<LiteralValue>> {
+        MemoryTable::get_row(self, rowid)
+    }
+
+    fn insert(&mut self, row: Vec<LiteralValue>) -> SqlError<RowId> {
+        self.insert_row(row)
+    }
+
+    fn update(&mut self, rowid: RowId, row: Vec<LiteralValue>) -> SqlError<()> {
+        self.update_row(rowid, row)
+    }
+
+    fn delete(&mut self, rowid: RowId) -> SqlError<()> {
+        self.delete_row(rowid)
+    }
+
+    fn is_persistent(&self) -> bool {
+        true
+    }
+
+    fn box_clone(&self) -> Box<Table> {
+        Box::new(self.clone())
+    }
+}
+
 pub struct RowIdIterator {
     bit_vec: BitVec,
     next_rowid: usize
@@ -90,15 +217,16 @@ impl Iterator for RowIdIterator {
     type Item = RowId;
 
     fn next(&mut self) -> Option<RowId> {
-        let rowid = self.next_rowid;
         loop {
+            let rowid = self.next_rowid;
             match self.bit_vec.get(rowid) {
                 Some(true) => {
                     self.next_rowid += 1;
                     return Some(rowid);
                 },
                 Some(false) => {
-                    continue;
+                    // Deleted row, skip it
+                    self.next_rowid += 1;
                 },
                 None => {
                     return None
@@ -146,4 +274,20 @@ mod tests {
 
         assert_eq!(Ok(row_vec.clone()),row_ret);
     }
+
+    #[test]
+    fn deleted_rows() {
+        let mut mt = create_table();
+        for val in ["a", "b", "c", "d"].iter() {
+            mt.insert_row(vec![LiteralValue::Text(val.to_string())]).unwrap();
+        }
+        mt.delete_row(0).unwrap();
+        mt.delete_row(2).unwrap();
+
+        assert!(mt.get_row(2).is_err());
+        assert!(mt.delete_row(2).is_err());
+        assert_eq!(mt.len(), 2);
+        assert_eq!(mt.raw_len(), 4);
+        assert_eq!(mt.rowid_iter().collect::<Vec<RowId>>(), vec![1, 3]);
+    }
 }
@@ -3,22 +3,32 @@ pub mod tests {
     use sql_parse::sql_expression;
     use definitions::*;
     use engine::*;
+    use std::cmp::Ordering;
 
     #[test]
     fn parser_select() {
         // Base test
         let mut stmt = SelectStmt {
+            distinct: false,
             projection: vec![SelectProjectionColumn::Wildcard],
             from: vec![SelectFromTable::NamedTable("dual".to_string())],
-            filter: vec![]
+            filter: None,
+            group_by: vec![],
+            having: None,
+            order_by: vec![],
+            limit: None,
+            offset: None
         };
         assert_eq!(sql_expression("SELECT * FROM DUAL"), Ok(SqlStmt::Select(stmt.clone())));
 
         let mut where_stmt = stmt.clone();
-        where_stmt.filter = vec![SelectWhereFilter::ColumnLiteral("dummy".to_string(),Comparator::Equals,LiteralValue::Text("X".to_string()))];
-        assert_eq!(sql_expression("SELECT * FROM DUAL WHERE dummy = 'X'"), Ok(SqlStmt::Select(where_stmt)));
+        where_stmt.filter = Some(Expr::binary(Expr::Column("dummy".to_string()), BinaryOperator::Equals, Expr::LiteralValue(LiteralValue::Text("X".to_string()))));
+        assert_eq!(sql_expression("SELECT * FROM DUAL WHERE dummy = 'X'"), Ok(SqlStmt::Select(where_stmt.clone())));
 
-        stmt.projection = vec![SelectProjectionColumn::Named("my_column1".to_string()),SelectProjectionColumn::Named("my_column2".to_string())];
+        where_stmt.filter = Some(Expr::binary(Expr::Column("dummy".to_string()), BinaryOperator::Equals, Expr::Column("other".to_string())));
+        assert_eq!(sql_expression("SELECT * FROM DUAL WHERE dummy = other"), Ok(SqlStmt::Select(where_stmt)));
+
+        stmt.projection = vec![SelectProjectionColumn::Expr(Expr::Column("my_column1".to_string())),SelectProjectionColumn::Expr(Expr::Column("my_column2".to_string()))];
         assert_eq!(sql_expression("SELECT my_column1,my_column2 FROM DUAL"), Ok(SqlStmt::Select(stmt.clone())));
 
         stmt.from = vec![SelectFromTable::Function(
@@ -38,6 +48,75 @@ pub mod tests {
 
     }
 
+    #[test]
+    fn parse_entry_point() {
+        assert_eq!(::parse("SELECT * FROM DUAL").unwrap(), sql_expression("SELECT * FROM DUAL").unwrap());
+        match ::parse("SELECT *\nFROM") {
+            Err(Error::Parse{line, column, expected, ..}) => {
+                assert_eq!(line, 2);
+                assert_eq!(column, 5);
+                assert!(!expected.is_empty());
+            },
+            other => panic!("Expected a parse error, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn parser_join() {
+        let join = SelectJoin {
+            join_type: JoinType::LeftOuter,
+            left: SelectFromTable::NamedTable("a".to_string()),
+            right: SelectFromTable::NamedTable("b".to_string()),
+            on: Some(Expr::binary(
+                Expr::binary(Expr::Column("x".to_string()), BinaryOperator::Equals, Expr::Column("y".to_string())),
+                BinaryOperator::And,
+                Expr::binary(Expr::Column("z".to_string()), BinaryOperator::Equals, Expr::LiteralValue(LiteralValue::Bool(true)))
+            ))
+        };
+        let cross = SelectJoin {
+            join_type: JoinType::Cross,
+            left: SelectFromTable::Join(Box::new(join)),
+            right: SelectFromTable::NamedTable("c".to_string()),
+            on: None
+        };
+        let stmt = SelectStmt {
+            distinct: false,
+            projection: vec![SelectProjectionColumn::Wildcard],
+            from: vec![SelectFromTable::Join(Box::new(cross)), SelectFromTable::NamedTable("d".to_string())],
+            filter: None,
+            group_by: vec![],
+            having: None,
+            order_by: vec![],
+            limit: None,
+            offset: None
+        };
+        assert_eq!(sql_expression("SELECT * FROM a LEFT OUTER JOIN b ON x = y AND z = TRUE CROSS JOIN c, d"), Ok(SqlStmt::Select(stmt)));
+        assert!(sql_expression("SELECT * FROM a JOIN b").is_err());
+    }
+
+    #[test]
+    fn parser_aliases() {
+        let stmt = SelectStmt {
+            distinct: false,
+            projection: vec![
+                SelectProjectionColumn::Aliased(Box::new(SelectProjectionColumn::Expr(Expr::Column("o.id".to_string()))), "order_id".to_string()),
+                SelectProjectionColumn::Aliased(Box::new(SelectProjectionColumn::Expr(Expr::Column("name".to_string()))), "n".to_string()),
+                SelectProjectionColumn::TableWildcard("c".to_string())
+            ],
+            from: vec![
+                SelectFromTable::Aliased(Box::new(SelectFromTable::NamedTable("orders".to_string())), "o".to_string()),
+                SelectFromTable::Aliased(Box::new(SelectFromTable::NamedTable("customers".to_string())), "c".to_string())
+            ],
+            filter: Some(Expr::binary(Expr::Column("o.customer".to_string()), BinaryOperator::Equals, Expr::Column("c.id".to_string()))),
+            group_by: vec![],
+            having: None,
+            order_by: vec![],
+            limit: None,
+            offset: None
+        };
+        assert_eq!(sql_expression("SELECT o.id AS order_id, name n, c.* FROM orders o, customers AS c WHERE o.customer = c.id"), Ok(SqlStmt::Select(stmt)));
+    }
+
     #[test]
     fn parser_create() {
         let stmt = CreateTableStmt {
@@ -52,6 +131,82 @@ pub mod tests {
         assert_eq!(sql_expression("create table test_table_1(test_column1 text)"), Ok(SqlStmt::CreateTable(stmt.clone())));
     }
 
+    #[test]
+    fn parser_update() {
+        let stmt = UpdateStmt {
+            table_name: "abc".to_string(),
+            assignments: vec![
+                UpdateAssignment {
+                    column_name: "col1".to_string(),
+                    value: Expr::LiteralValue(LiteralValue::Text("x".to_string()))
+                },
+                UpdateAssignment {
+                    column_name: "col2".to_string(),
+                    value: Expr::Column("col1".to_string())
+                }
+            ],
+            filter: Some(Expr::binary(Expr::Column("col3".to_string()), BinaryOperator::Equals, Expr::LiteralValue(LiteralValue::Bool(true))))
+        };
+        assert_eq!(sql_expression("UPDATE abc SET col1 = 'x', col2 = col1 WHERE col3 = TRUE"), Ok(SqlStmt::Update(stmt)));
+    }
+
+    #[test]
+    fn parser_delete() {
+        let mut stmt = DeleteStmt {
+            table_name: "abc".to_string(),
+            filter: None
+        };
+        assert_eq!(sql_expression("DELETE FROM abc"), Ok(SqlStmt::Delete(stmt.clone())));
+
+        stmt.filter = Some(Expr::binary(Expr::Column("col1".to_string()), BinaryOperator::Equals, Expr::LiteralValue(LiteralValue::Text("x".to_string()))));
+        assert_eq!(sql_expression("DELETE FROM abc WHERE col1 = 'x'"), Ok(SqlStmt::Delete(stmt)));
+    }
+
+    #[test]
+    fn parser_numeric() {
+        let stmt = CreateTableStmt {
+            table_name : "t".to_string(),
+            column_defs : vec![
+                ColumnDefinition {
+                    name: "a".to_string(),
+                    ctype: ColumnType::Integer
+                },
+                ColumnDefinition {
+                    name: "b".to_string(),
+                    ctype: ColumnType::Real
+                }
+            ]
+        };
+        assert_eq!(sql_expression("CREATE TABLE t(a INTEGER, b DOUBLE)"), Ok(SqlStmt::CreateTable(stmt)));
+
+        let insert = InsertStmt {
+            table_name: "t".to_string(),
+            column_names: vec!["a".to_string(), "b".to_string(), "c".to_string()],
+            column_values: vec![Expr::LiteralValue(LiteralValue::Integer(-42)), Expr::LiteralValue(LiteralValue::Real(1.5)), Expr::LiteralValue(LiteralValue::Real(2e3))]
+        };
+        assert_eq!(sql_expression("INSERT INTO t(a, b, c) VALUES (-42, 1.5, 2e3)"), Ok(SqlStmt::Insert(insert)));
+
+        // Integers out of range are read as Real
+        assert_eq!(LiteralValue::from_numeric("9223372036854775807"), LiteralValue::Integer(9223372036854775807));
+        assert_eq!(LiteralValue::from_numeric("9223372036854775808"), LiteralValue::Real(9223372036854775808.0));
+        assert_eq!(LiteralValue::from_numeric("-99999999999999999999"), LiteralValue::Real(-99999999999999999999.0));
+        assert!(sql_expression("SELECT 9223372036854775808").is_ok());
+    }
+
+    #[test]
+    fn numeric_compare() {
+        assert_eq!(LiteralValue::Integer(2).compare(&LiteralValue::Real(2.0)), Some(Ordering::Equal));
+        assert_eq!(LiteralValue::Integer(9).compare(&LiteralValue::Integer(10)), Some(Ordering::Less));
+        assert_eq!(LiteralValue::Text("9".to_string()).compare(&LiteralValue::Text("10".to_string())), Some(Ordering::Greater));
+        assert_eq!(LiteralValue::Integer(1).compare(&LiteralValue::Text("1".to_string())), None);
+        assert_eq!(LiteralValue::Null.compare(&LiteralValue::Null), None);
+        assert_eq!(ColumnType::Integer.widen(ColumnType::Real), Some(ColumnType::Real));
+        assert_eq!(ColumnType::Integer.accomodate(&LiteralValue::Real(0.5)), Some(ColumnType::Real));
+        assert_eq!(ColumnType::Bool.accomodate(&LiteralValue::Integer(1)), None);
+        assert_eq!(LiteralValue::Real(3.0).to_type(ColumnType::Integer), Ok(LiteralValue::Integer(3)));
+        assert!(LiteralValue::Real(3.5).to_type(ColumnType::Integer).is_err());
+    }
+
     #[test]
     fn create_table_insert_select() {
         let mut engine = SqlEngine::new();
@@ -59,7 +214,8 @@ pub mod tests {
         engine.excecute_stmt(sql_expression("INSERT INTO ABC(COL1, COL2) VALUES (TRUE, FALSE)").unwrap()).unwrap();
         assert!(engine.excecute_stmt(sql_expression("INSERT INTO ABC(COL1, COL2) VALUES (TRUE)").unwrap()).is_err());
         assert!(engine.excecute_stmt(sql_expression("INSERT INTO ABC(COL1) VALUES (TRUE,FALSE)").unwrap()).is_err());
-        assert!(engine.excecute_stmt(sql_expression("INSERT INTO ABC(COL1,COL1) VALUES (TRUE,FALSE)").unwrap()).is_err());
+        assert_eq!(engine.excecute_stmt(sql_expression("INSERT INTO ABC(COL1,COL1) VALUES (TRUE,FALSE)").unwrap()).unwrap_err(),
+                   Error::DuplicateColumn("col1".to_string()));
 
         let mut result = engine.excecute_stmt(sql_expression("SELECT * FROM ABC").unwrap()).unwrap();
         let mut expected_result = SqlResult::Rows(vec![vec![LiteralValue::Bool(true), LiteralValue::Bool(false)]]);
@@ -75,4 +231,1274 @@ pub mod tests {
 
     }
 
+    #[test]
+    fn select_where() {
+        let mut engine = SqlEngine::new();
+        engine.excecute_stmt(sql_expression("CREATE TABLE ABC(COL1 TEXT, COL2 TEXT)").unwrap()).unwrap();
+        engine.excecute_stmt(sql_expression("INSERT INTO ABC(COL1, COL2) VALUES ('a', 'b')").unwrap()).unwrap();
+        engine.excecute_stmt(sql_expression("INSERT INTO ABC(COL1, COL2) VALUES ('c', 'c')").unwrap()).unwrap();
+
+        let mut result = engine.excecute_stmt(sql_expression("SELECT COL2 FROM ABC WHERE COL1 = 'a'").unwrap()).unwrap();
+        let mut expected_result = SqlResult::Rows(vec![vec![LiteralValue::Text("b".to_string())]]);
+        assert_eq!(result,expected_result);
+
+        result = engine.excecute_stmt(sql_expression("SELECT COL1 FROM ABC WHERE COL1 = COL2").unwrap()).unwrap();
+        expected_result = SqlResult::Rows(vec![vec![LiteralValue::Text("c".to_string())]]);
+        assert_eq!(result,expected_result);
+
+        result = engine.excecute_stmt(sql_expression("SELECT * FROM ABC WHERE COL1 = 'z'").unwrap()).unwrap();
+        assert_eq!(result,SqlResult::Rows(vec![]));
+        assert!(engine.excecute_stmt(sql_expression("SELECT * FROM ABC WHERE COL3 = 'a'").unwrap()).is_err());
+    }
+
+    #[test]
+    fn select_all_rows() {
+        let mut engine = SqlEngine::new();
+        engine.excecute_stmt(sql_expression("CREATE TABLE ABC(COL1 TEXT)").unwrap()).unwrap();
+
+        let mut result = engine.excecute_stmt(sql_expression("SELECT * FROM ABC").unwrap()).unwrap();
+        assert_eq!(result,SqlResult::Rows(vec![]));
+
+        engine.excecute_stmt(sql_expression("INSERT INTO ABC(COL1) VALUES ('a')").unwrap()).unwrap();
+        engine.excecute_stmt(sql_expression("INSERT INTO ABC(COL1) VALUES ('b')").unwrap()).unwrap();
+        engine.excecute_stmt(sql_expression("INSERT INTO ABC(COL1) VALUES ('c')").unwrap()).unwrap();
+
+        result = engine.excecute_stmt(sql_expression("SELECT * FROM ABC").unwrap()).unwrap();
+        let expected_result = SqlResult::Rows(vec![
+            vec![LiteralValue::Text("a".to_string())],
+            vec![LiteralValue::Text("b".to_string())],
+            vec![LiteralValue::Text("c".to_string())]
+        ]);
+        assert_eq!(result,expected_result);
+    }
+
+    #[test]
+    fn cursor() {
+        let mut engine = SqlEngine::new();
+        engine.excecute_stmt(sql_expression("CREATE TABLE ABC(COL1 TEXT, COL2 BOOL)").unwrap()).unwrap();
+        engine.excecute_stmt(sql_expression("INSERT INTO ABC(COL1, COL2) VALUES ('a', TRUE)").unwrap()).unwrap();
+
+        let select = match sql_expression("SELECT COL2, COL1 FROM ABC").unwrap() {
+            SqlStmt::Select(s) => s,
+            _ => panic!("Expected a select statement")
+        };
+        let mut cursor = engine.query(select).unwrap();
+        assert_eq!(cursor.column_names(), vec!["col2".to_string(), "col1".to_string()]);
+        assert_eq!(cursor.columns()[0].ctype, ColumnType::Bool);
+
+        // Rows inserted after the cursor was opened are not visible to it
+        engine.excecute_stmt(sql_expression("INSERT INTO ABC(COL1, COL2) VALUES ('b', FALSE)").unwrap()).unwrap();
+        assert_eq!(cursor.next(), Some(Ok(vec![LiteralValue::Bool(true), LiteralValue::Text("a".to_string())])));
+        assert_eq!(cursor.next(), None);
+    }
+
+    #[test]
+    fn error_kinds() {
+        let mut engine = SqlEngine::new();
+        engine.excecute_stmt(sql_expression("CREATE TABLE ABC(COL1 TEXT)").unwrap()).unwrap();
+
+        let err = engine.excecute_stmt(sql_expression("CREATE TABLE ABC(COL1 TEXT)").unwrap()).unwrap_err();
+        assert_eq!(err, Error::DuplicateTable("abc".to_string()));
+        assert_eq!(err.code(), "42P07");
+
+        let err = engine.excecute_stmt(sql_expression("SELECT * FROM XYZ").unwrap()).unwrap_err();
+        assert_eq!(err, Error::UnknownTable("xyz".to_string()));
+        assert_eq!(err.code(), "42P01");
+
+        let err = engine.excecute_stmt(sql_expression("SELECT COL2 FROM ABC").unwrap()).unwrap_err();
+        assert_eq!(err, Error::UnknownColumn("col2".to_string()));
+        assert_eq!(err.code(), "42703");
+    }
+
+    #[test]
+    fn update() {
+        let mut engine = SqlEngine::new();
+        engine.excecute_stmt(sql_expression("CREATE TABLE ABC(COL1 TEXT, COL2 TEXT)").unwrap()).unwrap();
+        engine.excecute_stmt(sql_expression("INSERT INTO ABC(COL1, COL2) VALUES ('a', 'b')").unwrap()).unwrap();
+        engine.excecute_stmt(sql_expression("INSERT INTO ABC(COL1, COL2) VALUES ('c', 'd')").unwrap()).unwrap();
+
+        let mut result = engine.excecute_stmt(sql_expression("UPDATE ABC SET COL2 = 'x' WHERE COL1 = 'a'").unwrap()).unwrap();
+        assert_eq!(result,SqlResult::RowsUpdated(1));
+
+        result = engine.excecute_stmt(sql_expression("UPDATE ABC SET COL1 = COL2, COL2 = COL1").unwrap()).unwrap();
+        assert_eq!(result,SqlResult::RowsUpdated(2));
+
+        result = engine.excecute_stmt(sql_expression("SELECT * FROM ABC").unwrap()).unwrap();
+        let expected_result = SqlResult::Rows(vec![
+            vec![LiteralValue::Text("x".to_string()), LiteralValue::Text("a".to_string())],
+            vec![LiteralValue::Text("d".to_string()), LiteralValue::Text("c".to_string())]
+        ]);
+        assert_eq!(result,expected_result);
+
+        assert!(engine.excecute_stmt(sql_expression("UPDATE ABC SET COL1 = 'a', COL1 = 'b'").unwrap()).is_err());
+        assert!(engine.excecute_stmt(sql_expression("UPDATE ABC SET COL3 = 'a'").unwrap()).is_err());
+    }
+
+    #[test]
+    fn delete() {
+        let mut engine = SqlEngine::new();
+        engine.excecute_stmt(sql_expression("CREATE TABLE ABC(COL1 TEXT)").unwrap()).unwrap();
+        engine.excecute_stmt(sql_expression("INSERT INTO ABC(COL1) VALUES ('a')").unwrap()).unwrap();
+        engine.excecute_stmt(sql_expression("INSERT INTO ABC(COL1) VALUES ('b')").unwrap()).unwrap();
+        engine.excecute_stmt(sql_expression("INSERT INTO ABC(COL1) VALUES ('a')").unwrap()).unwrap();
+
+        let mut result = engine.excecute_stmt(sql_expression("DELETE FROM ABC WHERE COL1 = 'a'").unwrap()).unwrap();
+        assert_eq!(result,SqlResult::RowsUpdated(2));
+
+        result = engine.excecute_stmt(sql_expression("SELECT * FROM ABC").unwrap()).unwrap();
+        assert_eq!(result,SqlResult::Rows(vec![vec![LiteralValue::Text("b".to_string())]]));
+
+        result = engine.excecute_stmt(sql_expression("DELETE FROM ABC").unwrap()).unwrap();
+        assert_eq!(result,SqlResult::RowsUpdated(1));
+
+        result = engine.excecute_stmt(sql_expression("SELECT * FROM ABC").unwrap()).unwrap();
+        assert_eq!(result,SqlResult::Rows(vec![]));
+    }
+
+    #[test]
+    fn select_numeric() {
+        let mut engine = SqlEngine::new();
+        engine.excecute_stmt(sql_expression("CREATE TABLE ABC(ID INTEGER, PRICE REAL)").unwrap()).unwrap();
+        engine.excecute_stmt(sql_expression("INSERT INTO ABC(ID, PRICE) VALUES (1, 9.5)").unwrap()).unwrap();
+        engine.excecute_stmt(sql_expression("INSERT INTO ABC(ID, PRICE) VALUES (2, 10.0)").unwrap()).unwrap();
+
+        let result = engine.excecute_stmt(sql_expression("SELECT ID FROM ABC WHERE PRICE = 10").unwrap()).unwrap();
+        assert_eq!(result,SqlResult::Rows(vec![vec![LiteralValue::Integer(2)]]));
+    }
+
+    #[test]
+    fn insert_type_checks() {
+        let mut engine = SqlEngine::new();
+        engine.excecute_stmt(sql_expression("CREATE TABLE ABC(FLAG BOOL, PRICE REAL, QTY INTEGER)").unwrap()).unwrap();
+
+        let err = engine.excecute_stmt(sql_expression("INSERT INTO ABC(FLAG) VALUES ('hello')").unwrap()).unwrap_err();
+        assert_eq!(err.code(), "42804");
+        let message = format!("{}", err);
+        assert!(message.contains("flag"));
+        assert!(message.contains("Bool"));
+        assert!(message.contains("hello"));
+
+        assert!(engine.excecute_stmt(sql_expression("INSERT INTO ABC(QTY) VALUES (1.5)").unwrap()).is_err());
+        assert!(engine.excecute_stmt(sql_expression("UPDATE ABC SET FLAG = 'hello'").unwrap()).is_err());
+
+        // Integers are widened to reals
+        engine.excecute_stmt(sql_expression("INSERT INTO ABC(FLAG, PRICE, QTY) VALUES (TRUE, 3, 4)").unwrap()).unwrap();
+        let result = engine.excecute_stmt(sql_expression("SELECT * FROM ABC").unwrap()).unwrap();
+        assert_eq!(result,SqlResult::Rows(vec![vec![LiteralValue::Bool(true), LiteralValue::Real(3.0), LiteralValue::Integer(4)]]));
+    }
+
+    #[test]
+    fn catalog_listing() {
+        let mut engine = SqlEngine::new();
+        engine.excecute_stmt(sql_expression("CREATE TABLE ABC(COL1 TEXT)").unwrap()).unwrap();
+        engine.excecute_stmt(sql_expression("CREATE TABLE DEF(COL1 INTEGER, COL2 BOOL)").unwrap()).unwrap();
+        engine.excecute_stmt(sql_expression("DROP TABLE ABC").unwrap()).unwrap();
+
+        assert_eq!(engine.table_names(), vec!["def".to_string()]);
+        assert_eq!(engine.table_columns("def").unwrap().len(), 2);
+        assert!(engine.table_columns("abc").is_err());
+    }
+
+    fn join_tables() -> SqlEngine {
+        let mut engine = SqlEngine::new();
+        engine.excecute_stmt(sql_expression("CREATE TABLE ORDERS(ORDER_ID INTEGER, CUSTOMER INTEGER)").unwrap()).unwrap();
+        engine.excecute_stmt(sql_expression("CREATE TABLE CUSTOMERS(CUST_ID INTEGER, NAME TEXT)").unwrap()).unwrap();
+        engine.excecute_stmt(sql_expression("INSERT INTO ORDERS(ORDER_ID, CUSTOMER) VALUES (1, 10)").unwrap()).unwrap();
+        engine.excecute_stmt(sql_expression("INSERT INTO ORDERS(ORDER_ID, CUSTOMER) VALUES (2, 30)").unwrap()).unwrap();
+        engine.excecute_stmt(sql_expression("INSERT INTO CUSTOMERS(CUST_ID, NAME) VALUES (10, 'ann')").unwrap()).unwrap();
+        engine.excecute_stmt(sql_expression("INSERT INTO CUSTOMERS(CUST_ID, NAME) VALUES (20, 'bob')").unwrap()).unwrap();
+        engine
+    }
+
+    #[test]
+    fn joins() {
+        let mut engine = join_tables();
+        let ann = LiteralValue::Text("ann".to_string());
+        let bob = LiteralValue::Text("bob".to_string());
+
+        let mut result = engine.excecute_stmt(sql_expression("SELECT ORDER_ID, NAME FROM ORDERS, CUSTOMERS").unwrap()).unwrap();
+        assert_eq!(result,SqlResult::Rows(vec![
+            vec![LiteralValue::Integer(1), ann.clone()],
+            vec![LiteralValue::Integer(1), bob.clone()],
+            vec![LiteralValue::Integer(2), ann.clone()],
+            vec![LiteralValue::Integer(2), bob.clone()]
+        ]));
+
+        result = engine.excecute_stmt(sql_expression("SELECT ORDER_ID, NAME FROM ORDERS, CUSTOMERS WHERE CUSTOMER = CUST_ID").unwrap()).unwrap();
+        assert_eq!(result,SqlResult::Rows(vec![vec![LiteralValue::Integer(1), ann.clone()]]));
+
+        result = engine.excecute_stmt(sql_expression("SELECT * FROM ORDERS JOIN CUSTOMERS ON CUSTOMER = CUST_ID").unwrap()).unwrap();
+        assert_eq!(result,SqlResult::Rows(vec![
+            vec![LiteralValue::Integer(1), LiteralValue::Integer(10), LiteralValue::Integer(10), ann.clone()]
+        ]));
+
+        result = engine.excecute_stmt(sql_expression("SELECT ORDER_ID, NAME FROM ORDERS LEFT JOIN CUSTOMERS ON CUSTOMER = CUST_ID").unwrap()).unwrap();
+        assert_eq!(result,SqlResult::Rows(vec![
+            vec![LiteralValue::Integer(1), ann.clone()],
+            vec![LiteralValue::Integer(2), LiteralValue::Null]
+        ]));
+
+        result = engine.excecute_stmt(sql_expression("SELECT ORDER_ID, NAME FROM ORDERS RIGHT JOIN CUSTOMERS ON CUSTOMER = CUST_ID").unwrap()).unwrap();
+        assert_eq!(result,SqlResult::Rows(vec![
+            vec![LiteralValue::Integer(1), ann.clone()],
+            vec![LiteralValue::Null, bob.clone()]
+        ]));
+
+        result = engine.excecute_stmt(sql_expression("SELECT ORDER_ID, NAME FROM ORDERS FULL OUTER JOIN CUSTOMERS ON CUSTOMER = CUST_ID").unwrap()).unwrap();
+        assert_eq!(result,SqlResult::Rows(vec![
+            vec![LiteralValue::Integer(1), ann.clone()],
+            vec![LiteralValue::Integer(2), LiteralValue::Null],
+            vec![LiteralValue::Null, bob.clone()]
+        ]));
+    }
+
+    #[test]
+    fn join_algorithms() {
+        let int = |i: i64| LiteralValue::Integer(i);
+        let text = |s: &str| LiteralValue::Text(s.to_string());
+        let null = LiteralValue::Null;
+        let queries = vec![
+            ("SELECT ORDER_ID, NAME FROM ORDERS, CUSTOMERS WHERE CUSTOMER = CUST_ID",
+             vec![vec![int(1), text("ann")], vec![int(4), text("ann")]]),
+            ("SELECT ORDER_ID, NAME FROM ORDERS LEFT JOIN CUSTOMERS ON CUSTOMER = CUST_ID",
+             vec![vec![int(1), text("ann")], vec![int(2), null.clone()], vec![int(3), null.clone()], vec![int(4), text("ann")]]),
+            ("SELECT ORDER_ID, NAME FROM ORDERS RIGHT JOIN CUSTOMERS ON CUSTOMER = CUST_ID",
+             vec![vec![null.clone(), text("bob")], vec![null.clone(), text("eve")], vec![int(1), text("ann")], vec![int(4), text("ann")]]),
+            ("SELECT ORDER_ID, NAME FROM ORDERS FULL JOIN CUSTOMERS ON CUSTOMER = CUST_ID AND NAME = 'ann'",
+             vec![vec![null.clone(), text("bob")], vec![null.clone(), text("eve")], vec![int(1), text("ann")],
+                  vec![int(2), null.clone()], vec![int(3), null.clone()], vec![int(4), text("ann")]])
+        ];
+        // Join operators produce rows in different orders
+        let sorted = |mut rows: Vec<Vec<LiteralValue>>| {
+            rows.sort_by(|a, b| {
+                a.iter().zip(b.iter()).map(|(x, y)| x.sort_cmp(y)).find(|o| *o != Ordering::Equal).unwrap_or(Ordering::Equal)
+            });
+            rows
+        };
+
+        for &(query, ref expected) in queries.iter() {
+            // The default budget uses a hash join, no budget at all forces a
+            // sort-merge join
+            for &budget in [None, Some(0)].iter() {
+                let mut engine = join_tables();
+                if let Some(budget) = budget {
+                    engine.options_mut().join_memory_budget = budget;
+                    engine.options_mut().sort_memory_budget = budget;
+                }
+                engine_insert_nulls(&mut engine);
+                assert_eq!(sorted(select_rows(&mut engine, query)), *expected);
+            }
+        }
+
+        // Every operator finds integers and reals equal only when they are
+        // exactly equal, the last integer is not 2^53 even though converting
+        // it to a real gives 2^53
+        let queries = [
+            "SELECT I, R FROM A JOIN B ON I = R",
+            // Not an equality, so the rows are joined by a nested loop
+            "SELECT I, R FROM A JOIN B ON I <= R AND I >= R"
+        ];
+        for query in queries.iter() {
+            for &budget in [None, Some(0)].iter() {
+                let mut engine = SqlEngine::new();
+                if let Some(budget) = budget {
+                    engine.options_mut().join_memory_budget = budget;
+                }
+                engine.excecute_stmt(sql_expression("CREATE TABLE A(I INTEGER)").unwrap()).unwrap();
+                engine.excecute_stmt(sql_expression("CREATE TABLE B(R REAL)").unwrap()).unwrap();
+                for value in ["2", "3", "9007199254740993"].iter() {
+                    engine.excecute_stmt(sql_expression(&format!("INSERT INTO A(I) VALUES ({})", value)).unwrap()).unwrap();
+                }
+                for value in ["2.0", "2.5", "9007199254740992.0"].iter() {
+                    engine.excecute_stmt(sql_expression(&format!("INSERT INTO B(R) VALUES ({})", value)).unwrap()).unwrap();
+                }
+                assert_eq!(sorted(select_rows(&mut engine, query)), vec![vec![int(2), LiteralValue::Real(2.0)]]);
+            }
+        }
+    }
+
+    fn engine_insert_nulls(engine: &mut SqlEngine) {
+        engine.excecute_stmt(sql_expression("INSERT INTO ORDERS(ORDER_ID) VALUES (3)").unwrap()).unwrap();
+        engine.excecute_stmt(sql_expression("INSERT INTO ORDERS(ORDER_ID, CUSTOMER) VALUES (4, 10)").unwrap()).unwrap();
+        engine.excecute_stmt(sql_expression("INSERT INTO CUSTOMERS(NAME) VALUES ('eve')").unwrap()).unwrap();
+    }
+
+    #[test]
+    fn qualified_columns() {
+        let mut engine = SqlEngine::new();
+        engine.excecute_stmt(sql_expression("CREATE TABLE ORDERS(ID INTEGER, CUSTOMER INTEGER)").unwrap()).unwrap();
+        engine.excecute_stmt(sql_expression("CREATE TABLE CUSTOMERS(ID INTEGER, NAME TEXT)").unwrap()).unwrap();
+        engine.excecute_stmt(sql_expression("INSERT INTO ORDERS(ID, CUSTOMER) VALUES (1, 10)").unwrap()).unwrap();
+        engine.excecute_stmt(sql_expression("INSERT INTO CUSTOMERS(ID, NAME) VALUES (10, 'ann')").unwrap()).unwrap();
+
+        let err = engine.excecute_stmt(sql_expression("SELECT ID FROM ORDERS, CUSTOMERS").unwrap()).unwrap_err();
+        assert_eq!(err, Error::AmbiguousColumn("id".to_string()));
+
+        let result = engine.excecute_stmt(sql_expression("SELECT o.id, c.* FROM orders o JOIN customers c ON o.customer = c.id").unwrap()).unwrap();
+        assert_eq!(result, SqlResult::Rows(vec![vec![LiteralValue::Integer(1), LiteralValue::Integer(10), LiteralValue::Text("ann".to_string())]]));
+
+        let result = engine.excecute_stmt(sql_expression("SELECT customers.name FROM orders, customers WHERE orders.customer = customers.id").unwrap()).unwrap();
+        assert_eq!(result, SqlResult::Rows(vec![vec![LiteralValue::Text("ann".to_string())]]));
+
+        let select = match sql_expression("SELECT o.id AS order_id, c.name customer FROM orders o, customers c").unwrap() {
+            SqlStmt::Select(s) => s,
+            _ => panic!("Expected a select statement")
+        };
+        let cursor = engine.query(select).unwrap();
+        assert_eq!(cursor.column_names(), vec!["order_id".to_string(), "customer".to_string()]);
+
+        assert!(engine.excecute_stmt(sql_expression("SELECT orders.id FROM orders o").unwrap()).is_err());
+        assert!(engine.excecute_stmt(sql_expression("SELECT * FROM orders, orders").unwrap()).is_err());
+        assert!(engine.excecute_stmt(sql_expression("SELECT x.* FROM orders").unwrap()).is_err());
+
+        // ON only sees the tables of its own join, not those listed before it
+        engine.excecute_stmt(sql_expression("CREATE TABLE ITEMS(ORDER_ID INTEGER, QTY INTEGER)").unwrap()).unwrap();
+        engine.excecute_stmt(sql_expression("INSERT INTO ITEMS(ORDER_ID, QTY) VALUES (1, 3)").unwrap()).unwrap();
+        let err = engine.excecute_stmt(sql_expression("SELECT * FROM orders o, customers c JOIN items i ON o.id = i.order_id").unwrap()).unwrap_err();
+        assert_eq!(err, Error::UnknownTable("o".to_string()));
+        let err = engine.excecute_stmt(sql_expression("SELECT * FROM orders, customers c JOIN items ON customer = c.id").unwrap()).unwrap_err();
+        assert_eq!(err, Error::UnknownColumn("customer".to_string()));
+        let result = engine.excecute_stmt(sql_expression("SELECT c.name, i.qty FROM orders o JOIN customers c ON o.customer = c.id JOIN items i ON o.id = i.order_id").unwrap()).unwrap();
+        assert_eq!(result, SqlResult::Rows(vec![vec![LiteralValue::Text("ann".to_string()), LiteralValue::Integer(3)]]));
+        let result = engine.excecute_stmt(sql_expression("SELECT c.name, i.qty FROM orders o, customers c JOIN items i ON c.id = i.order_id + 9 WHERE o.customer = c.id").unwrap()).unwrap();
+        assert_eq!(result, SqlResult::Rows(vec![vec![LiteralValue::Text("ann".to_string()), LiteralValue::Integer(3)]]));
+    }
+
+    #[test]
+    fn parser_expressions() {
+        let a = || Expr::Column("a".to_string());
+        let int = |i| Expr::LiteralValue(LiteralValue::Integer(i));
+        let stmt = match sql_expression("SELECT a + 2 * 3, -a FROM t WHERE NOT a IS NULL AND a BETWEEN 1 AND 2 OR a NOT IN (1, 2)").unwrap() {
+            SqlStmt::Select(s) => s,
+            _ => panic!("Expected a select statement")
+        };
+        assert_eq!(stmt.projection, vec![
+            SelectProjectionColumn::Expr(Expr::binary(a(), BinaryOperator::Add, Expr::binary(int(2), BinaryOperator::Multiply, int(3)))),
+            SelectProjectionColumn::Expr(Expr::Unary(UnaryOperator::Minus, Box::new(a())))
+        ]);
+        let is_not_null = Expr::Unary(UnaryOperator::Not, Box::new(Expr::IsNull { expr: Box::new(a()), negated: false }));
+        let between = Expr::Between { expr: Box::new(a()), negated: false, low: Box::new(int(1)), high: Box::new(int(2)) };
+        let not_in = Expr::InList { expr: Box::new(a()), negated: true, list: vec![int(1), int(2)] };
+        assert_eq!(stmt.filter, Some(Expr::binary(Expr::binary(is_not_null, BinaryOperator::And, between), BinaryOperator::Or, not_in)));
+
+        assert_eq!(sql_expression("SELECT (a - 1) - 2 FROM t"), sql_expression("SELECT a - 1 - 2 FROM t"));
+        assert!(sql_expression("SELECT a || 'x' FROM t WHERE a NOT LIKE 'b%' AND a <> NULL").is_ok());
+        assert!(sql_expression("SELECT a FROM t WHERE a = ").is_err());
+    }
+
+    fn select_rows(engine: &mut SqlEngine, query: &str) -> Vec<Vec<LiteralValue>> {
+        match engine.excecute_stmt(sql_expression(query).unwrap()).unwrap() {
+            SqlResult::Rows(rows) => rows,
+            other => panic!("Expected rows, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn expressions() {
+        let mut engine = SqlEngine::new();
+        engine.excecute_stmt(sql_expression("CREATE TABLE ABC(ID INTEGER, NAME TEXT, PRICE REAL)").unwrap()).unwrap();
+        engine.excecute_stmt(sql_expression("INSERT INTO ABC(ID, NAME, PRICE) VALUES (1, 'apple', 1.5)").unwrap()).unwrap();
+        engine.excecute_stmt(sql_expression("INSERT INTO ABC(ID, NAME, PRICE) VALUES (2 * 5, 'app' || 'ly', NULL)").unwrap()).unwrap();
+        engine.excecute_stmt(sql_expression("INSERT INTO ABC(ID, NAME) VALUES (3, 'banana')").unwrap()).unwrap();
+
+        assert_eq!(select_rows(&mut engine, "SELECT ID * 2 + 1, ID / 4, NAME || '!' FROM ABC WHERE ID = 10"),
+                   vec![vec![LiteralValue::Integer(21), LiteralValue::Integer(2), LiteralValue::Text("apply!".to_string())]]);
+        assert_eq!(select_rows(&mut engine, "SELECT ID + PRICE, PRICE * 2 FROM ABC WHERE ID = 1"),
+                   vec![vec![LiteralValue::Real(2.5), LiteralValue::Real(3.0)]]);
+
+        // NULL never satisfies a comparison, in either direction
+        assert_eq!(select_rows(&mut engine, "SELECT ID FROM ABC WHERE PRICE > 1 OR NOT PRICE > 1").len(), 1);
+        assert_eq!(select_rows(&mut engine, "SELECT ID FROM ABC WHERE PRICE IS NULL").len(), 2);
+        assert_eq!(select_rows(&mut engine, "SELECT ID FROM ABC WHERE PRICE IS NOT NULL OR ID = 3").len(), 2);
+        assert_eq!(select_rows(&mut engine, "SELECT PRICE > 1 AND ID = 2, PRICE > 1 OR ID = 3 FROM ABC WHERE ID = 3"),
+                   vec![vec![LiteralValue::Bool(false), LiteralValue::Bool(true)]]);
+        assert_eq!(select_rows(&mut engine, "SELECT ID FROM ABC WHERE ID NOT IN (1, NULL)"), Vec::<Vec<LiteralValue>>::new());
+        assert_eq!(select_rows(&mut engine, "SELECT ID FROM ABC WHERE ID IN (1, NULL)"), vec![vec![LiteralValue::Integer(1)]]);
+
+        assert_eq!(select_rows(&mut engine, "SELECT ID FROM ABC WHERE ID BETWEEN 2 AND 10"),
+                   vec![vec![LiteralValue::Integer(10)], vec![LiteralValue::Integer(3)]]);
+        assert_eq!(select_rows(&mut engine, "SELECT NAME FROM ABC WHERE NAME LIKE 'app%' AND NAME NOT LIKE '_pple'"),
+                   vec![vec![LiteralValue::Text("apply".to_string())]]);
+
+        let cursor = match sql_expression("SELECT ID + 1, NAME AS N FROM ABC").unwrap() {
+            SqlStmt::Select(select) => engine.query(select).unwrap(),
+            _ => panic!("Expected a select statement")
+        };
+        assert_eq!(cursor.column_names(), vec!["?column?".to_string(), "n".to_string()]);
+        assert_eq!(cursor.columns()[0].ctype, ColumnType::Integer);
+
+        engine.excecute_stmt(sql_expression("UPDATE ABC SET PRICE = ID * 0.5 WHERE PRICE IS NULL").unwrap()).unwrap();
+        assert_eq!(select_rows(&mut engine, "SELECT PRICE FROM ABC WHERE ID = 3"), vec![vec![LiteralValue::Real(1.5)]]);
+
+        let err = engine.excecute_stmt(sql_expression("SELECT ID / 0 FROM ABC").unwrap()).unwrap_err();
+        assert_eq!(err, Error::DivisionByZero);
+        assert_eq!(err.code(), "22012");
+        let err = engine.excecute_stmt(sql_expression("INSERT INTO ABC(ID) VALUES (9223372036854775807 + 1)").unwrap()).unwrap_err();
+        assert_eq!(err.code(), "22003");
+        assert_eq!(engine.excecute_stmt(sql_expression("SELECT ID FROM ABC WHERE NAME > 1").unwrap()).unwrap_err().code(), "42804");
+        assert_eq!(engine.excecute_stmt(sql_expression("SELECT NAME + 1 FROM ABC").unwrap()).unwrap_err().code(), "42804");
+        assert_eq!(engine.excecute_stmt(sql_expression("SELECT ID FROM ABC WHERE ID").unwrap()).unwrap_err().code(), "42804");
+        assert!(engine.excecute_stmt(sql_expression("INSERT INTO ABC(ID) VALUES (ID)").unwrap()).is_err());
+    }
+
+    #[test]
+    fn parser_order_by() {
+        let stmt = match sql_expression("SELECT a FROM t ORDER BY a DESC, b + 1 NULLS FIRST, c ASC NULLS LAST").unwrap() {
+            SqlStmt::Select(s) => s,
+            _ => panic!("Expected a select statement")
+        };
+        assert_eq!(stmt.order_by, vec![
+            OrderByItem { expr: Expr::Column("a".to_string()), descending: true, nulls_first: None },
+            OrderByItem {
+                expr: Expr::binary(Expr::Column("b".to_string()), BinaryOperator::Add, Expr::LiteralValue(LiteralValue::Integer(1))),
+                descending: false,
+                nulls_first: Some(true)
+            },
+            OrderByItem { expr: Expr::Column("c".to_string()), descending: false, nulls_first: Some(false) }
+        ]);
+        assert!(sql_expression("SELECT a FROM t ORDER a").is_err());
+    }
+
+    #[test]
+    fn order_by() {
+        let mut engine = SqlEngine::new();
+        engine.excecute_stmt(sql_expression("CREATE TABLE ABC(ID INTEGER, NAME TEXT, PRICE REAL)").unwrap()).unwrap();
+        for &(id, name, price) in [(1, "b", "2.5"), (2, "a", "NULL"), (3, "c", "1"), (4, "a", "2.5"), (5, "b", "NULL")].iter() {
+            let insert = format!("INSERT INTO ABC(ID, NAME, PRICE) VALUES ({}, '{}', {})", id, name, price);
+            engine.excecute_stmt(sql_expression(&insert).unwrap()).unwrap();
+        }
+        let ids = |rows: Vec<Vec<LiteralValue>>| -> Vec<i64> {
+            rows.iter().map(|row| row[0].as_integer().unwrap()).collect()
+        };
+
+        // NULLs sort last when ascending and first when descending unless told otherwise
+        assert_eq!(ids(select_rows(&mut engine, "SELECT ID FROM ABC ORDER BY PRICE")), vec![3, 1, 4, 2, 5]);
+        assert_eq!(ids(select_rows(&mut engine, "SELECT ID FROM ABC ORDER BY PRICE DESC")), vec![2, 5, 1, 4, 3]);
+        assert_eq!(ids(select_rows(&mut engine, "SELECT ID FROM ABC ORDER BY PRICE NULLS FIRST, ID DESC")), vec![5, 2, 3, 4, 1]);
+        assert_eq!(ids(select_rows(&mut engine, "SELECT ID FROM ABC ORDER BY NAME DESC, PRICE DESC NULLS LAST")), vec![3, 1, 5, 4, 2]);
+
+        // Aliases, positions and expressions over columns that are not selected
+        assert_eq!(ids(select_rows(&mut engine, "SELECT ID AS K, NAME FROM ABC ORDER BY K DESC")), vec![5, 4, 3, 2, 1]);
+        assert_eq!(ids(select_rows(&mut engine, "SELECT ID, NAME FROM ABC WHERE ID > 1 ORDER BY 2, 1 DESC")), vec![4, 2, 5, 3]);
+        assert_eq!(ids(select_rows(&mut engine, "SELECT ID FROM ABC ORDER BY ID % 2, ID")), vec![2, 4, 1, 3, 5]);
+        assert!(engine.excecute_stmt(sql_expression("SELECT ID FROM ABC ORDER BY 3").unwrap()).is_err());
+
+        // Without any memory every row is spilled, the result must be the same
+        let expected = select_rows(&mut engine, "SELECT * FROM ABC ORDER BY NAME, PRICE DESC");
+        engine.options_mut().sort_memory_budget = 0;
+        assert_eq!(select_rows(&mut engine, "SELECT * FROM ABC ORDER BY NAME, PRICE DESC"), expected);
+
+        // Enough rows for more runs than are merged at once, which are then
+        // merged in several passes
+        let query = "SELECT generate_series % 7, generate_series FROM generate_series(1, 40000) ORDER BY 1, 2 DESC";
+        let rows = select_rows(&mut engine, query);
+        assert_eq!(rows.len(), 40000);
+        assert_eq!(rows[0], vec![LiteralValue::Integer(0), LiteralValue::Integer(39998)]);
+        assert_eq!(rows[39999], vec![LiteralValue::Integer(6), LiteralValue::Integer(6)]);
+        engine.options_mut().sort_memory_budget = 64 * 1024 * 1024;
+        assert_eq!(select_rows(&mut engine, query), rows);
+    }
+
+    #[test]
+    fn parser_limit() {
+        let limits = |sql: &str| match sql_expression(sql).unwrap() {
+            SqlStmt::Select(s) => (s.limit, s.offset),
+            _ => panic!("Expected a select statement")
+        };
+        let int = |i| Some(Expr::LiteralValue(LiteralValue::Integer(i)));
+        assert_eq!(limits("SELECT a FROM t LIMIT 10"), (int(10), None));
+        assert_eq!(limits("SELECT a FROM t ORDER BY a LIMIT 10 OFFSET 20"), (int(10), int(20)));
+        assert_eq!(limits("SELECT a FROM t OFFSET 5 ROWS"), (None, int(5)));
+        assert_eq!(limits("SELECT a FROM t OFFSET 5 ROWS FETCH NEXT 1 ROW ONLY"), (int(1), int(5)));
+        assert_eq!(limits("SELECT a FROM t FETCH FIRST 3 ROWS ONLY"), (int(3), None));
+        assert!(sql_expression("SELECT a FROM t FETCH FIRST 3 ROWS").is_err());
+    }
+
+    #[test]
+    fn limit_offset() {
+        let mut engine = SqlEngine::new();
+        engine.excecute_stmt(sql_expression("CREATE TABLE ABC(ID INTEGER, GRP INTEGER)").unwrap()).unwrap();
+        for id in 1..21 {
+            let insert = format!("INSERT INTO ABC(ID, GRP) VALUES ({}, {})", id, id % 3);
+            engine.excecute_stmt(sql_expression(&insert).unwrap()).unwrap();
+        }
+        let ids = |rows: Vec<Vec<LiteralValue>>| -> Vec<i64> {
+            rows.iter().map(|row| row[0].as_integer().unwrap()).collect()
+        };
+
+        assert_eq!(ids(select_rows(&mut engine, "SELECT ID FROM ABC LIMIT 3")), vec![1, 2, 3]);
+        assert_eq!(ids(select_rows(&mut engine, "SELECT ID FROM ABC LIMIT 2 OFFSET 17")), vec![18, 19]);
+        assert_eq!(ids(select_rows(&mut engine, "SELECT ID FROM ABC WHERE ID > 18 OFFSET 1 ROW")), vec![20]);
+        assert_eq!(ids(select_rows(&mut engine, "SELECT ID FROM ABC LIMIT 0")), Vec::<i64>::new());
+        assert_eq!(ids(select_rows(&mut engine, "SELECT ID FROM ABC LIMIT 5 OFFSET 30")), Vec::<i64>::new());
+        assert_eq!(select_rows(&mut engine, "SELECT ID FROM ABC LIMIT NULL").len(), 20);
+
+        // The top rows keep their input order on ties, with or without a heap
+        let queries = [
+            "SELECT ID FROM ABC ORDER BY GRP DESC, ID % 2 LIMIT 4 OFFSET 1",
+            "SELECT ID FROM ABC ORDER BY GRP OFFSET 3 ROWS FETCH FIRST 5 ROWS ONLY"
+        ];
+        let expected = [vec![8, 14, 20, 5], vec![12, 15, 18, 1, 4]];
+        for (query, expected) in queries.iter().zip(expected.iter()) {
+            engine.options_mut().sort_memory_budget = 64 * 1024 * 1024;
+            assert_eq!(ids(select_rows(&mut engine, query)), *expected);
+            engine.options_mut().sort_memory_budget = 0;
+            assert_eq!(ids(select_rows(&mut engine, query)), *expected);
+        }
+
+        assert_eq!(engine.excecute_stmt(sql_expression("SELECT ID FROM ABC LIMIT -1").unwrap()).unwrap_err().code(), "2201W");
+        assert_eq!(engine.excecute_stmt(sql_expression("SELECT ID FROM ABC OFFSET 1 - 2").unwrap()).unwrap_err().code(), "2201W");
+        assert_eq!(engine.excecute_stmt(sql_expression("SELECT ID FROM ABC LIMIT 'a'").unwrap()).unwrap_err().code(), "42804");
+        assert!(engine.excecute_stmt(sql_expression("SELECT ID FROM ABC LIMIT ID").unwrap()).is_err());
+    }
+
+    #[test]
+    fn parser_group_by() {
+        let stmt = match sql_expression("SELECT a, COUNT(*), SUM(DISTINCT b) FROM t GROUP BY a HAVING MAX(b) > 1").unwrap() {
+            SqlStmt::Select(s) => s,
+            _ => panic!("Expected a select statement")
+        };
+        let aggregate = |function, distinct, arg: Option<Expr>| Expr::Aggregate { function: function, distinct: distinct, arg: arg.map(Box::new) };
+        let column = |name: &str| Expr::Column(name.to_string());
+        assert_eq!(stmt.projection, vec![
+            SelectProjectionColumn::Expr(column("a")),
+            SelectProjectionColumn::Expr(aggregate(AggregateFunction::Count, false, None)),
+            SelectProjectionColumn::Expr(aggregate(AggregateFunction::Sum, true, Some(column("b"))))
+        ]);
+        assert_eq!(stmt.group_by, vec![column("a")]);
+        assert_eq!(stmt.having, Some(Expr::binary(aggregate(AggregateFunction::Max, false, Some(column("b"))),
+                                                   BinaryOperator::Greater, Expr::LiteralValue(LiteralValue::Integer(1)))));
+        assert!(sql_expression("SELECT a FROM t GROUP a").is_err());
+        assert!(sql_expression("SELECT COUNT(DISTINCT *) FROM t").is_err());
+    }
+
+    #[test]
+    fn group_by() {
+        let mut engine = SqlEngine::new();
+        engine.excecute_stmt(sql_expression("CREATE TABLE ABC(ID INTEGER, NAME TEXT, PRICE INTEGER)").unwrap()).unwrap();
+        for &(id, name, price) in [(1, "'b'", "2"), (2, "'a'", "NULL"), (3, "'c'", "1"), (4, "'a'", "2"), (5, "'b'", "NULL"), (6, "NULL", "3")].iter() {
+            let insert = format!("INSERT INTO ABC(ID, NAME, PRICE) VALUES ({}, {}, {})", id, name, price);
+            engine.excecute_stmt(sql_expression(&insert).unwrap()).unwrap();
+        }
+        let int = LiteralValue::Integer;
+        let text = |s: &str| LiteralValue::Text(s.to_string());
+
+        // Aggregates skip NULLs, COUNT(*) does not
+        assert_eq!(select_rows(&mut engine, "SELECT COUNT(*), COUNT(PRICE), COUNT(DISTINCT PRICE), SUM(PRICE), MIN(NAME), MAX(PRICE), AVG(PRICE) FROM ABC"),
+                   vec![vec![int(6), int(4), int(3), int(8), text("a"), int(3), LiteralValue::Real(2.0)]]);
+        assert_eq!(select_rows(&mut engine, "SELECT COUNT(*), SUM(PRICE), MAX(NAME) FROM ABC WHERE ID > 10"),
+                   vec![vec![int(0), LiteralValue::Null, LiteralValue::Null]]);
+
+        // Groups come out in the order they are first seen, NULL keys form one group
+        assert_eq!(select_rows(&mut engine, "SELECT NAME, COUNT(PRICE), SUM(PRICE) FROM ABC GROUP BY NAME"), vec![
+            vec![text("b"), int(1), int(2)],
+            vec![text("a"), int(1), int(2)],
+            vec![text("c"), int(1), int(1)],
+            vec![LiteralValue::Null, int(1), int(3)]
+        ]);
+        assert_eq!(select_rows(&mut engine, "SELECT NAME, COUNT(*) AS N FROM ABC GROUP BY 1 HAVING COUNT(*) > 1 AND MIN(ID) > 1"),
+                   vec![vec![text("a"), int(2)]]);
+        assert_eq!(select_rows(&mut engine, "SELECT ID % 2 AS ODD, MAX(ID) + 1 FROM ABC GROUP BY ODD ORDER BY SUM(ID)"),
+                   vec![vec![int(1), int(6)], vec![int(0), int(7)]]);
+
+        // Sorting on the group keys aggregates a sorted stream, the result must match
+        let query = "SELECT NAME, COUNT(*), SUM(ID) FROM ABC GROUP BY NAME ORDER BY NAME DESC";
+        let expected = vec![
+            vec![LiteralValue::Null, int(1), int(6)],
+            vec![text("c"), int(1), int(3)],
+            vec![text("b"), int(2), int(6)],
+            vec![text("a"), int(2), int(6)]
+        ];
+        assert_eq!(select_rows(&mut engine, query), expected);
+        assert_eq!(select_rows(&mut engine, "SELECT NAME, COUNT(*), SUM(ID) FROM ABC GROUP BY NAME ORDER BY 3, 2, 1 DESC NULLS FIRST"), vec![
+            expected[1].clone(), expected[0].clone(), expected[2].clone(), expected[3].clone()
+        ]);
+        engine.options_mut().sort_memory_budget = 0;
+        assert_eq!(select_rows(&mut engine, query), expected);
+
+        // Input already sorted on the group keys is aggregated without sorting it again
+        assert_eq!(select_rows(&mut engine, "SELECT NAME, COUNT(*) FROM (SELECT NAME, ID FROM ABC ORDER BY NAME) AS S GROUP BY NAME"), vec![
+            vec![text("a"), int(2)],
+            vec![text("b"), int(2)],
+            vec![text("c"), int(1)],
+            vec![LiteralValue::Null, int(1)]
+        ]);
+        assert_eq!(select_rows(&mut engine, "SELECT NAME, PRICE, COUNT(*) FROM (SELECT NAME, PRICE FROM ABC ORDER BY NAME DESC) AS S \
+                                             WHERE PRICE = 2 GROUP BY PRICE, NAME ORDER BY NAME"),
+                   vec![vec![text("a"), int(2), int(1)], vec![text("b"), int(2), int(1)]]);
+        engine.options_mut().join_memory_budget = 0;
+        let join = "SELECT O.NAME, COUNT(*) FROM ABC AS O JOIN ABC AS P ON O.NAME = P.NAME GROUP BY O.NAME ORDER BY O.NAME";
+        assert_eq!(select_rows(&mut engine, join), vec![
+            vec![text("a"), int(4)],
+            vec![text("b"), int(4)],
+            vec![text("c"), int(1)]
+        ]);
+        assert_eq!(select_rows(&mut engine, &format!("{} DESC", join)), vec![
+            vec![text("c"), int(1)],
+            vec![text("b"), int(4)],
+            vec![text("a"), int(4)]
+        ]);
+
+        let error_code = |engine: &mut SqlEngine, sql: &str| engine.excecute_stmt(sql_expression(sql).unwrap()).unwrap_err().code();
+        assert_eq!(error_code(&mut engine, "SELECT NAME, ID FROM ABC GROUP BY NAME"), "42803");
+        assert_eq!(error_code(&mut engine, "SELECT COUNT(*) FROM ABC WHERE SUM(ID) > 1"), "42803");
+        assert_eq!(error_code(&mut engine, "SELECT MAX(COUNT(*)) FROM ABC"), "42803");
+        assert_eq!(error_code(&mut engine, "SELECT SUM(NAME) FROM ABC"), "42804");
+    }
+
+    #[test]
+    fn parser_set_operations() {
+        let select = |sql: &str| match sql_expression(sql).unwrap() {
+            SqlStmt::Select(s) => s,
+            _ => panic!("Expected a select statement")
+        };
+        let operation = |operator, all, left: Query, right: Query| SetOperationStmt {
+            operator: operator,
+            all: all,
+            left: Box::new(left),
+            right: Box::new(right),
+            order_by: vec![],
+            limit: None,
+            offset: None
+        };
+
+        assert!(select("SELECT DISTINCT a FROM t").distinct);
+        assert!(!select("SELECT a FROM t").distinct);
+
+        // INTERSECT binds first, ORDER BY and LIMIT apply to the whole result
+        let mut expected = operation(SetOperator::Union, true, Query::Select(select("SELECT a FROM t")),
+            Query::SetOperation(operation(SetOperator::Intersect, false, Query::Select(select("SELECT b FROM u")), Query::Select(select("SELECT c FROM v")))));
+        expected.order_by = vec![OrderByItem { expr: Expr::LiteralValue(LiteralValue::Integer(1)), descending: false, nulls_first: None }];
+        expected.limit = Some(Expr::LiteralValue(LiteralValue::Integer(2)));
+        assert_eq!(sql_expression("SELECT a FROM t UNION ALL SELECT b FROM u INTERSECT SELECT c FROM v ORDER BY 1 LIMIT 2"),
+                   Ok(SqlStmt::SetOperation(expected)));
+
+        let expected = operation(SetOperator::Intersect, false,
+            Query::SetOperation(operation(SetOperator::Except, true, Query::Select(select("SELECT a FROM t")), Query::Select(select("SELECT b FROM u")))),
+            Query::Select(select("SELECT c FROM v")));
+        assert_eq!(sql_expression("(SELECT a FROM t EXCEPT ALL SELECT b FROM u) INTERSECT SELECT c FROM v"),
+                   Ok(SqlStmt::SetOperation(expected)));
+        assert!(sql_expression("SELECT a FROM t ORDER BY a UNION SELECT b FROM u").is_err());
+    }
+
+    #[test]
+    fn set_operations() {
+        let mut engine = SqlEngine::new();
+        engine.excecute_stmt(sql_expression("CREATE TABLE A(ID INTEGER, NAME TEXT)").unwrap()).unwrap();
+        engine.excecute_stmt(sql_expression("CREATE TABLE B(ID REAL, NAME TEXT)").unwrap()).unwrap();
+        for &(id, name) in [(1, "'a'"), (2, "'b'"), (2, "'b'"), (3, "NULL"), (3, "NULL")].iter() {
+            engine.excecute_stmt(sql_expression(&format!("INSERT INTO A(ID, NAME) VALUES ({}, {})", id, name)).unwrap()).unwrap();
+        }
+        for &(id, name) in [(2, "'b'"), (3, "NULL"), (4, "'d'"), (2, "'b'")].iter() {
+            engine.excecute_stmt(sql_expression(&format!("INSERT INTO B(ID, NAME) VALUES ({}.0, {})", id, name)).unwrap()).unwrap();
+        }
+        let row = |id: f64, name: Option<&str>| vec![LiteralValue::Real(id), name.map(|n| LiteralValue::Text(n.to_string())).unwrap_or(LiteralValue::Null)];
+        let ids = |rows: Vec<Vec<LiteralValue>>| -> Vec<i64> {
+            rows.iter().map(|row| row[0].as_integer().unwrap()).collect()
+        };
+
+        // DISTINCT keeps the first of equal rows, NULLs are equal to each other
+        assert_eq!(ids(select_rows(&mut engine, "SELECT DISTINCT ID, NAME FROM A")), vec![1, 2, 3]);
+        assert_eq!(select_rows(&mut engine, "SELECT DISTINCT NAME FROM A ORDER BY NAME DESC LIMIT 2"),
+                   vec![vec![LiteralValue::Null], vec![LiteralValue::Text("b".to_string())]]);
+        assert_eq!(select_rows(&mut engine, "SELECT DISTINCT COUNT(*) FROM A GROUP BY ID ORDER BY 1").len(), 2);
+
+        // Integers are widened to match the real column of the other query
+        assert_eq!(select_rows(&mut engine, "SELECT ID, NAME FROM A UNION SELECT ID, NAME FROM B"),
+                   vec![row(1.0, Some("a")), row(2.0, Some("b")), row(3.0, None), row(4.0, Some("d"))]);
+        assert_eq!(select_rows(&mut engine, "SELECT ID, NAME FROM A UNION ALL SELECT * FROM B").len(), 9);
+        assert_eq!(select_rows(&mut engine, "SELECT * FROM A INTERSECT SELECT * FROM B"), vec![row(2.0, Some("b")), row(3.0, None)]);
+        assert_eq!(select_rows(&mut engine, "SELECT * FROM A INTERSECT ALL SELECT * FROM B"),
+                   vec![row(2.0, Some("b")), row(2.0, Some("b")), row(3.0, None)]);
+        assert_eq!(select_rows(&mut engine, "SELECT * FROM A EXCEPT SELECT * FROM B"), vec![row(1.0, Some("a"))]);
+        assert_eq!(select_rows(&mut engine, "SELECT * FROM A EXCEPT ALL SELECT * FROM B"), vec![row(1.0, Some("a")), row(3.0, None)]);
+
+        assert_eq!(ids(select_rows(&mut engine, "SELECT ID FROM A EXCEPT SELECT ID FROM B INTERSECT SELECT ID FROM B")), vec![1]);
+        assert_eq!(ids(select_rows(&mut engine, "(SELECT ID FROM A EXCEPT SELECT ID FROM B) INTERSECT SELECT ID FROM B")), Vec::<i64>::new());
+        assert_eq!(ids(select_rows(&mut engine, "SELECT ID FROM A UNION SELECT ID FROM B ORDER BY ID DESC LIMIT 2 OFFSET 1")), vec![3, 2]);
+        assert_eq!(ids(select_rows(&mut engine, "SELECT ID, NULL FROM A UNION SELECT ID, NAME FROM B ORDER BY 1")), vec![1, 2, 2, 3, 4]);
+
+        let error_code = |engine: &mut SqlEngine, sql: &str| engine.excecute_stmt(sql_expression(sql).unwrap()).unwrap_err().code();
+        assert_eq!(error_code(&mut engine, "SELECT ID FROM A UNION SELECT ID, NAME FROM B"), "42601");
+        assert_eq!(error_code(&mut engine, "SELECT ID FROM A UNION SELECT NAME FROM B"), "42804");
+        assert_eq!(error_code(&mut engine, "SELECT ID FROM A UNION SELECT ID FROM B ORDER BY NAME"), "42703");
+        assert_eq!(error_code(&mut engine, "SELECT DISTINCT ID FROM A ORDER BY NAME"), "42601");
+    }
+
+    #[test]
+    fn parser_subqueries() {
+        let select = |sql: &str| match sql_expression(sql).unwrap() {
+            SqlStmt::Select(s) => s,
+            _ => panic!("Expected a select statement")
+        };
+        let query = |sql: &str| Box::new(Query::Select(select(sql)));
+        let filter = |sql: &str| select(sql).filter.unwrap();
+        let column = |name: &str| Box::new(Expr::Column(name.to_string()));
+
+        assert_eq!(select("SELECT d.a FROM (SELECT b AS a FROM t) AS d").from,
+                   vec![SelectFromTable::Aliased(Box::new(SelectFromTable::Subquery(query("SELECT b AS a FROM t"))), "d".to_string())]);
+        assert_eq!(filter("SELECT a FROM t WHERE a NOT IN (SELECT c FROM u)"),
+                   Expr::InSubquery { expr: column("a"), negated: true, query: query("SELECT c FROM u") });
+        assert_eq!(filter("SELECT a FROM t WHERE NOT EXISTS (SELECT * FROM u WHERE u.c = t.a)"),
+                   Expr::Unary(UnaryOperator::Not, Box::new(Expr::Exists(query("SELECT * FROM u WHERE u.c = t.a")))));
+        assert_eq!(filter("SELECT a FROM t WHERE a = ((SELECT MAX(c) FROM u))"),
+                   Expr::Binary(column("a"), BinaryOperator::Equals, Box::new(Expr::Subquery(query("SELECT MAX(c) FROM u")))));
+
+        // Parentheses around expressions are not taken for queries
+        assert_eq!(filter("SELECT a FROM t WHERE a IN ((1), 2)"), Expr::InList {
+            expr: column("a"),
+            negated: false,
+            list: vec![Expr::LiteralValue(LiteralValue::Integer(1)), Expr::LiteralValue(LiteralValue::Integer(2))]
+        });
+        assert_eq!(filter("SELECT a FROM t WHERE a > ((SELECT c FROM u) + 1)"),
+                   Expr::Binary(column("a"), BinaryOperator::Greater,
+                                Box::new(Expr::Binary(Box::new(Expr::Subquery(query("SELECT c FROM u"))), BinaryOperator::Add, Box::new(Expr::LiteralValue(LiteralValue::Integer(1)))))));
+    }
+
+    #[test]
+    fn subqueries() {
+        let mut engine = SqlEngine::new();
+        engine.excecute_stmt(sql_expression("CREATE TABLE DEPT(ID INTEGER, NAME TEXT)").unwrap()).unwrap();
+        engine.excecute_stmt(sql_expression("CREATE TABLE EMP(ID INTEGER, DEPT_ID INTEGER, SALARY INTEGER)").unwrap()).unwrap();
+        for &(id, name) in [(1, "eng"), (2, "ops"), (3, "hr")].iter() {
+            engine.excecute_stmt(sql_expression(&format!("INSERT INTO DEPT(ID, NAME) VALUES ({}, '{}')", id, name)).unwrap()).unwrap();
+        }
+        for &(id, dept_id, salary) in [(1, "1", 100), (2, "1", 200), (3, "2", 150), (4, "NULL", 50)].iter() {
+            engine.excecute_stmt(sql_expression(&format!("INSERT INTO EMP(ID, DEPT_ID, SALARY) VALUES ({}, {}, {})", id, dept_id, salary)).unwrap()).unwrap();
+        }
+        let int = |i: i64| LiteralValue::Integer(i);
+        let text = |s: &str| LiteralValue::Text(s.to_string());
+        let ids = |rows: Vec<Vec<LiteralValue>>| -> Vec<i64> {
+            rows.iter().map(|row| row[0].as_integer().unwrap()).collect()
+        };
+
+        assert_eq!(select_rows(&mut engine, "SELECT D.DEPT_ID, D.TOTAL FROM (SELECT DEPT_ID, SUM(SALARY) AS TOTAL FROM EMP GROUP BY DEPT_ID) AS D \
+                                             WHERE D.TOTAL > 100 ORDER BY D.DEPT_ID"),
+                   vec![vec![int(1), int(300)], vec![int(2), int(150)]]);
+        assert_eq!(ids(select_rows(&mut engine, "SELECT ID FROM EMP WHERE SALARY > (SELECT AVG(SALARY) FROM EMP) ORDER BY ID")), vec![2, 3]);
+
+        // Columns of the outer query are visible unless a table of the subquery has them
+        assert_eq!(select_rows(&mut engine, "SELECT ID, (SELECT NAME FROM DEPT WHERE ID = DEPT_ID) FROM EMP ORDER BY ID"), vec![
+            vec![int(1), text("eng")], vec![int(2), text("eng")], vec![int(3), text("ops")], vec![int(4), LiteralValue::Null]
+        ]);
+        assert_eq!(ids(select_rows(&mut engine, "SELECT ID FROM EMP E WHERE SALARY > (SELECT AVG(SALARY) FROM EMP WHERE DEPT_ID = E.DEPT_ID)")), vec![2]);
+        assert_eq!(select_rows(&mut engine, "SELECT (SELECT NAME FROM DEPT WHERE ID = DEPT_ID), COUNT(*) FROM EMP GROUP BY DEPT_ID ORDER BY 2 DESC, 1"), vec![
+            vec![text("eng"), int(2)], vec![text("ops"), int(1)], vec![LiteralValue::Null, int(1)]
+        ]);
+
+        // NULLs returned by an IN subquery make non matching values unknown
+        assert_eq!(ids(select_rows(&mut engine, "SELECT ID FROM DEPT WHERE ID IN (SELECT DEPT_ID FROM EMP)")), vec![1, 2]);
+        assert_eq!(ids(select_rows(&mut engine, "SELECT ID FROM DEPT WHERE ID NOT IN (SELECT DEPT_ID FROM EMP)")), Vec::<i64>::new());
+        assert_eq!(ids(select_rows(&mut engine, "SELECT ID FROM DEPT WHERE ID NOT IN (SELECT DEPT_ID FROM EMP WHERE DEPT_ID IS NOT NULL)")), vec![3]);
+        // Values are matched like with =, NaN equals no value, not even NaN
+        let nan = "(1e400 - 1e400)";
+        assert_eq!(select_rows(&mut engine, &format!("SELECT {0} IN (SELECT {0} FROM DEPT), ID IN (SELECT {0} FROM DEPT), \
+                                                      ID IN (SELECT ID + 0.0 FROM DEPT) FROM DEPT WHERE ID = 1", nan)),
+                   vec![vec![LiteralValue::Null, LiteralValue::Null, LiteralValue::Bool(true)]]);
+        assert_eq!(ids(select_rows(&mut engine, "SELECT ID FROM DEPT D WHERE NOT EXISTS (SELECT * FROM EMP WHERE DEPT_ID = D.ID)")), vec![3]);
+        assert_eq!(ids(select_rows(&mut engine, "SELECT ID FROM DEPT D WHERE EXISTS (SELECT * FROM EMP WHERE DEPT_ID = D.ID AND \
+                                                   SALARY = (SELECT MAX(SALARY) FROM EMP E WHERE E.DEPT_ID = D.ID AND SALARY > 100))")), vec![1, 2]);
+
+        // Subqueries of data changing statements see the tables as they were before
+        engine.excecute_stmt(sql_expression("INSERT INTO DEPT(ID, NAME) VALUES ((SELECT MAX(ID) FROM DEPT) + 1, 'new')").unwrap()).unwrap();
+        assert_eq!(ids(select_rows(&mut engine, "SELECT ID FROM DEPT WHERE NAME = 'new'")), vec![4]);
+        let result = engine.excecute_stmt(sql_expression("UPDATE EMP SET SALARY = (SELECT MAX(SALARY) FROM EMP) WHERE DEPT_ID IS NULL").unwrap()).unwrap();
+        assert_eq!(result, SqlResult::RowsUpdated(1));
+        let result = engine.excecute_stmt(sql_expression("DELETE FROM EMP WHERE SALARY = (SELECT MAX(SALARY) FROM EMP)").unwrap()).unwrap();
+        assert_eq!(result, SqlResult::RowsUpdated(2));
+
+        let error_code = |engine: &mut SqlEngine, sql: &str| engine.excecute_stmt(sql_expression(sql).unwrap()).unwrap_err().code();
+        assert_eq!(error_code(&mut engine, "SELECT (SELECT ID FROM EMP) FROM DEPT"), "21000");
+        assert_eq!(error_code(&mut engine, "SELECT * FROM (SELECT ID FROM EMP)"), "42601");
+        assert_eq!(error_code(&mut engine, "SELECT ID FROM DEPT WHERE ID IN (SELECT ID, SALARY FROM EMP)"), "42601");
+        assert_eq!(error_code(&mut engine, "SELECT ID FROM DEPT WHERE NAME IN (SELECT ID FROM EMP)"), "42804");
+        assert_eq!(error_code(&mut engine, "SELECT * FROM DEPT D, (SELECT * FROM EMP WHERE DEPT_ID = D.ID) AS E"), "42P01");
+    }
+
+    #[test]
+    fn parser_ctes() {
+        let query = |sql: &str| match sql_expression(sql).unwrap() {
+            SqlStmt::Select(s) => Query::Select(s),
+            _ => panic!("Expected a select statement")
+        };
+
+        match sql_expression("WITH RECURSIVE r(id, depth) AS (SELECT id, 0 FROM t UNION ALL SELECT t.id, depth + 1 FROM t JOIN r ON t.parent = r.id), \
+                              s AS (SELECT a FROM u) SELECT * FROM r ORDER BY depth").unwrap() {
+            SqlStmt::With(with) => {
+                assert!(with.recursive);
+                assert_eq!(with.ctes.iter().map(|cte| cte.name.clone()).collect::<Vec<_>>(), vec!["r", "s"]);
+                assert_eq!(with.ctes[0].column_names, vec!["id", "depth"]);
+                assert_eq!(with.ctes[1].column_names, Vec::<String>::new());
+                assert_eq!(with.ctes[1].query, query("SELECT a FROM u"));
+                assert_eq!(*with.query, query("SELECT * FROM r ORDER BY depth"));
+            },
+            stmt => panic!("Expected a WITH statement, got {:?}", stmt)
+        }
+
+        // WITH queries can be nested like any other query
+        let with = match sql_expression("WITH s AS (SELECT a FROM u) SELECT a FROM s").unwrap() {
+            SqlStmt::With(with) => Query::With(with),
+            stmt => panic!("Expected a WITH statement, got {:?}", stmt)
+        };
+        match sql_expression("SELECT a FROM t WHERE a IN (WITH s AS (SELECT a FROM u) SELECT a FROM s)").unwrap() {
+            SqlStmt::Select(select) => {
+                assert_eq!(select.filter.unwrap(), Expr::InSubquery {
+                    expr: Box::new(Expr::Column("a".to_string())),
+                    negated: false,
+                    query: Box::new(with)
+                });
+            },
+            stmt => panic!("Expected a select statement, got {:?}", stmt)
+        }
+    }
+
+    #[test]
+    fn ctes() {
+        let mut engine = SqlEngine::new();
+        engine.excecute_stmt(sql_expression("CREATE TABLE EMP(ID INTEGER, MANAGER_ID INTEGER, NAME TEXT)").unwrap()).unwrap();
+        engine.excecute_stmt(sql_expression("CREATE TABLE LINKS(SRC INTEGER, DST INTEGER)").unwrap()).unwrap();
+        for &(id, manager_id, name) in [(1, "NULL", "ceo"), (2, "1", "cto"), (3, "1", "cfo"), (4, "2", "dev"), (5, "4", "intern")].iter() {
+            engine.excecute_stmt(sql_expression(&format!("INSERT INTO EMP(ID, MANAGER_ID, NAME) VALUES ({}, {}, '{}')", id, manager_id, name)).unwrap()).unwrap();
+        }
+        for &(src, dst) in [(1, 2), (2, 3), (3, 1)].iter() {
+            engine.excecute_stmt(sql_expression(&format!("INSERT INTO LINKS(SRC, DST) VALUES ({}, {})", src, dst)).unwrap()).unwrap();
+        }
+        let int = |i: i64| LiteralValue::Integer(i);
+        let ids = |rows: Vec<Vec<LiteralValue>>| -> Vec<i64> {
+            rows.iter().map(|row| row[0].as_integer().unwrap()).collect()
+        };
+
+        assert_eq!(ids(select_rows(&mut engine, "WITH MANAGERS AS (SELECT DISTINCT MANAGER_ID AS ID FROM EMP WHERE MANAGER_ID IS NOT NULL) \
+                                                 SELECT E.ID FROM EMP E JOIN MANAGERS M ON E.ID = M.ID ORDER BY E.ID")), vec![1, 2, 4]);
+        assert_eq!(ids(select_rows(&mut engine, "WITH A AS (SELECT ID FROM EMP WHERE ID < 4), B(N) AS (SELECT ID FROM A WHERE ID > 1) \
+                                                 SELECT A.ID FROM A, B WHERE A.ID = B.N ORDER BY A.ID")), vec![2, 3]);
+        // The query of a common table reads the stored table it hides
+        assert_eq!(ids(select_rows(&mut engine, "WITH EMP AS (SELECT ID FROM EMP WHERE ID = 1) SELECT ID FROM EMP")), vec![1]);
+
+        // Everyone reporting to the cto, directly or not
+        assert_eq!(select_rows(&mut engine, "WITH RECURSIVE REPORTS(ID, DEPTH) AS (SELECT ID, 0 FROM EMP WHERE ID = 2 UNION ALL \
+                                             SELECT E.ID, R.DEPTH + 1 FROM EMP E JOIN REPORTS R ON E.MANAGER_ID = R.ID) \
+                                             SELECT ID, DEPTH FROM REPORTS ORDER BY ID"),
+                   vec![vec![int(2), int(0)], vec![int(4), int(1)], vec![int(5), int(2)]]);
+        assert_eq!(ids(select_rows(&mut engine, "SELECT ID FROM EMP WHERE ID IN (WITH RECURSIVE UP(ID) AS (SELECT MANAGER_ID FROM EMP WHERE ID = 5 UNION \
+                                                 SELECT E.MANAGER_ID FROM EMP E JOIN UP ON E.ID = UP.ID) SELECT ID FROM UP) ORDER BY ID")), vec![1, 2, 4]);
+
+        // UNION stops at cycles, UNION ALL runs until the recursion depth guard
+        assert_eq!(ids(select_rows(&mut engine, "WITH RECURSIVE R(N) AS (SELECT SRC FROM LINKS WHERE SRC = 1 UNION \
+                                                 SELECT DST FROM LINKS JOIN R ON SRC = N) SELECT N FROM R ORDER BY N")), vec![1, 2, 3]);
+        engine.options_mut().max_recursion_depth = 10;
+        let error_code = |engine: &mut SqlEngine, sql: &str| engine.excecute_stmt(sql_expression(sql).unwrap()).unwrap_err().code();
+        assert_eq!(error_code(&mut engine, "WITH RECURSIVE R(N) AS (SELECT SRC FROM LINKS WHERE SRC = 1 UNION ALL \
+                                            SELECT DST FROM LINKS JOIN R ON SRC = N) SELECT N FROM R"), "54001");
+
+        assert_eq!(error_code(&mut engine, "WITH R AS (SELECT * FROM R) SELECT * FROM R"), "42P01");
+        assert_eq!(error_code(&mut engine, "WITH RECURSIVE R(N) AS (SELECT SRC FROM LINKS UNION \
+                                            SELECT DST FROM LINKS WHERE SRC IN (SELECT N FROM R)) SELECT N FROM R"), "42601");
+        assert_eq!(error_code(&mut engine, "WITH R(A, B) AS (SELECT ID FROM EMP) SELECT * FROM R"), "42601");
+        assert_eq!(error_code(&mut engine, "WITH R AS (SELECT ID FROM EMP), R AS (SELECT ID FROM EMP) SELECT * FROM R"), "42712");
+    }
+
+    #[test]
+    fn parser_windows() {
+        let stmt = match sql_expression("SELECT SUM(b) OVER (PARTITION BY a ORDER BY c DESC ROWS BETWEEN 2 PRECEDING AND CURRENT ROW), \
+                                         ROW_NUMBER() OVER () FROM t").unwrap() {
+            SqlStmt::Select(s) => s,
+            _ => panic!("Expected a select statement")
+        };
+        let column = |name: &str| Expr::Column(name.to_string());
+        assert_eq!(stmt.projection, vec![
+            SelectProjectionColumn::Expr(Expr::Window {
+                function: WindowFunction::Aggregate(AggregateFunction::Sum),
+                args: vec![column("b")],
+                window: WindowSpec {
+                    partition_by: vec![column("a")],
+                    order_by: vec![OrderByItem { expr: column("c"), descending: true, nulls_first: None }],
+                    frame: Some(WindowFrame {
+                        units: FrameUnits::Rows,
+                        start: FrameBound::Preceding(Box::new(Expr::LiteralValue(LiteralValue::Integer(2)))),
+                        end: FrameBound::CurrentRow
+                    })
+                }
+            }),
+            SelectProjectionColumn::Expr(Expr::Window {
+                function: WindowFunction::RowNumber,
+                args: Vec::new(),
+                window: WindowSpec { partition_by: Vec::new(), order_by: Vec::new(), frame: None }
+            })
+        ]);
+        assert!(sql_expression("SELECT RANK() FROM t").is_err());
+        assert!(sql_expression("SELECT SUM(b) OVER (ROWS BETWEEN 1 PRECEDING) FROM t").is_err());
+    }
+
+    #[test]
+    fn windows() {
+        let mut engine = SqlEngine::new();
+        engine.excecute_stmt(sql_expression("CREATE TABLE SALES(ID INTEGER, REGION TEXT, AMOUNT INTEGER)").unwrap()).unwrap();
+        for &(id, region, amount) in [(1, "e", 10), (2, "e", 20), (3, "e", 20), (4, "e", 40), (5, "w", 5), (6, "w", 15)].iter() {
+            engine.excecute_stmt(sql_expression(&format!("INSERT INTO SALES(ID, REGION, AMOUNT) VALUES ({}, '{}', {})", id, region, amount)).unwrap()).unwrap();
+        }
+        let int = |i: i64| LiteralValue::Integer(i);
+        let column = |rows: Vec<Vec<LiteralValue>>, index: usize| -> Vec<LiteralValue> {
+            rows.into_iter().map(|row| row[index].clone()).collect()
+        };
+        let ints = |values: &[i64]| -> Vec<LiteralValue> { values.iter().map(|&i| LiteralValue::Integer(i)).collect() };
+
+        let rows = select_rows(&mut engine, "SELECT ID, ROW_NUMBER() OVER (PARTITION BY REGION ORDER BY AMOUNT, ID), \
+                                             RANK() OVER (PARTITION BY REGION ORDER BY AMOUNT), \
+                                             DENSE_RANK() OVER (PARTITION BY REGION ORDER BY AMOUNT) FROM SALES ORDER BY ID");
+        assert_eq!(column(rows.clone(), 1), ints(&[1, 2, 3, 4, 1, 2]));
+        assert_eq!(column(rows.clone(), 2), ints(&[1, 2, 2, 4, 1, 2]));
+        assert_eq!(column(rows, 3), ints(&[1, 2, 2, 3, 1, 2]));
+
+        let rows = select_rows(&mut engine, "SELECT ID, LAG(AMOUNT) OVER (ORDER BY ID), LEAD(AMOUNT, 2, 0) OVER (ORDER BY ID) FROM SALES ORDER BY ID");
+        assert_eq!(column(rows.clone(), 1), vec![LiteralValue::Null, int(10), int(20), int(20), int(40), int(5)]);
+        assert_eq!(column(rows, 2), ints(&[20, 40, 5, 15, 0, 0]));
+
+        // The default frame ends with the last row sorting equal to the current one
+        assert_eq!(column(select_rows(&mut engine, "SELECT ID, SUM(AMOUNT) OVER (PARTITION BY REGION ORDER BY AMOUNT) FROM SALES ORDER BY ID"), 1),
+                   ints(&[10, 50, 50, 90, 5, 20]));
+        assert_eq!(column(select_rows(&mut engine, "SELECT ID, SUM(AMOUNT) OVER (ORDER BY ID ROWS BETWEEN 1 PRECEDING AND CURRENT ROW) \
+                                                    FROM SALES ORDER BY ID"), 1),
+                   ints(&[10, 30, 40, 60, 45, 20]));
+        assert_eq!(column(select_rows(&mut engine, "SELECT ID, COUNT(*) OVER (ORDER BY AMOUNT RANGE BETWEEN 5 PRECEDING AND 5 FOLLOWING) \
+                                                    FROM SALES ORDER BY ID"), 1),
+                   ints(&[3, 3, 3, 1, 2, 4]));
+        assert_eq!(column(select_rows(&mut engine, "SELECT ID, COUNT(*) OVER (ORDER BY AMOUNT DESC RANGE BETWEEN CURRENT ROW AND 10 FOLLOWING) \
+                                                    FROM SALES ORDER BY ID"), 1),
+                   ints(&[2, 4, 4, 1, 1, 3]));
+
+        let rows = select_rows(&mut engine, "SELECT ID, FIRST_VALUE(ID) OVER (PARTITION BY REGION ORDER BY AMOUNT DESC, ID), \
+                                             LAST_VALUE(AMOUNT) OVER (PARTITION BY REGION ORDER BY ID ROWS BETWEEN CURRENT ROW AND 1 FOLLOWING) \
+                                             FROM SALES ORDER BY ID");
+        assert_eq!(column(rows.clone(), 1), ints(&[4, 4, 4, 4, 6, 6]));
+        assert_eq!(column(rows, 2), ints(&[20, 20, 40, 40, 15, 15]));
+
+        // Sliding frames take the rows leaving them out of their aggregate
+        let rows = select_rows(&mut engine, "SELECT ID, MIN(AMOUNT) OVER (ORDER BY ID ROWS BETWEEN 1 PRECEDING AND 1 FOLLOWING), \
+                                             MAX(AMOUNT) OVER (ORDER BY ID ROWS BETWEEN CURRENT ROW AND 2 FOLLOWING), \
+                                             SUM(AMOUNT) OVER (ORDER BY ID ROWS BETWEEN 1 FOLLOWING AND 2 FOLLOWING) FROM SALES ORDER BY ID");
+        assert_eq!(column(rows.clone(), 1), ints(&[10, 10, 20, 5, 5, 5]));
+        assert_eq!(column(rows.clone(), 2), ints(&[20, 40, 40, 40, 15, 15]));
+        assert_eq!(column(rows, 3), vec![int(40), int(60), int(45), int(20), int(15), LiteralValue::Null]);
+        let rows = select_rows(&mut engine, "SELECT generate_series, SUM(generate_series) OVER (ORDER BY generate_series RANGE BETWEEN 2 PRECEDING AND 2 FOLLOWING) \
+                                             FROM generate_series(1, 20000) ORDER BY 1");
+        assert_eq!(rows.len(), 20000);
+        assert_eq!(rows[0][1], int(6));
+        assert_eq!(rows[100][1], int(505));
+        assert_eq!(rows[19999][1], int(59997));
+        // Rows leave a frame before new ones enter it, and REAL sums are taken
+        // again over the frame instead of losing the small values
+        engine.excecute_stmt(sql_expression("CREATE TABLE EXTREMES(ID INTEGER, N INTEGER, X REAL)").unwrap()).unwrap();
+        for &(id, n, x) in [(1, 9223372036854775807, 1e20), (2, 0, 1.0), (3, 1, 1.0)].iter() {
+            engine.excecute_stmt(sql_expression(&format!("INSERT INTO EXTREMES(ID, N, X) VALUES ({}, {}, {:e})", id, n, x)).unwrap()).unwrap();
+        }
+        let rows = select_rows(&mut engine, "SELECT ID, SUM(N) OVER (ORDER BY ID ROWS 1 PRECEDING), SUM(X) OVER (ORDER BY ID ROWS 1 PRECEDING), \
+                                             AVG(X) OVER (ORDER BY ID ROWS 1 PRECEDING) FROM EXTREMES ORDER BY ID");
+        assert_eq!(column(rows.clone(), 1), ints(&[9223372036854775807, 9223372036854775807, 1]));
+        assert_eq!(rows[2][2], LiteralValue::Real(2.0));
+        assert_eq!(rows[2][3], LiteralValue::Real(1.0));
+
+        // Windows are computed on grouped rows and can order the result
+        assert_eq!(select_rows(&mut engine, "SELECT REGION, SUM(AMOUNT), RANK() OVER (ORDER BY SUM(AMOUNT)) FROM SALES GROUP BY REGION ORDER BY REGION"),
+                   vec![vec![LiteralValue::Text("e".to_string()), int(90), int(2)],
+                        vec![LiteralValue::Text("w".to_string()), int(20), int(1)]]);
+        assert_eq!(column(select_rows(&mut engine, "SELECT ID FROM SALES ORDER BY ROW_NUMBER() OVER (ORDER BY AMOUNT DESC, ID)"), 0),
+                   ints(&[4, 2, 3, 6, 1, 5]));
+
+        let error_code = |engine: &mut SqlEngine, sql: &str| engine.excecute_stmt(sql_expression(sql).unwrap()).unwrap_err().code();
+        assert_eq!(error_code(&mut engine, "SELECT ID FROM SALES WHERE ROW_NUMBER() OVER () > 1"), "42P20");
+        assert_eq!(error_code(&mut engine, "SELECT SUM(AMOUNT) OVER (ORDER BY ID, REGION RANGE 1 PRECEDING) FROM SALES"), "42P20");
+        assert_eq!(error_code(&mut engine, "SELECT SUM(AMOUNT) OVER (ORDER BY ID ROWS -1 PRECEDING) FROM SALES"), "42P20");
+        assert_eq!(error_code(&mut engine, "SELECT SUM(AMOUNT) OVER (ROWS BETWEEN CURRENT ROW AND 1 PRECEDING) FROM SALES"), "42P20");
+        assert_eq!(error_code(&mut engine, "SELECT FIRST_VALUE(ID, 2) OVER () FROM SALES"), "42601");
+    }
+
+    #[test]
+    fn table_functions() {
+        let mut engine = SqlEngine::new();
+        engine.excecute_stmt(sql_expression("CREATE TABLE NUMS(N INTEGER)").unwrap()).unwrap();
+        for n in [2, 4].iter() {
+            engine.excecute_stmt(sql_expression(&format!("INSERT INTO NUMS(N) VALUES ({})", n)).unwrap()).unwrap();
+        }
+        let int = |i: i64| LiteralValue::Integer(i);
+        let text = |s: &str| LiteralValue::Text(s.to_string());
+        let ids = |rows: Vec<Vec<LiteralValue>>| -> Vec<i64> {
+            rows.iter().map(|row| row[0].as_integer().unwrap()).collect()
+        };
+
+        assert_eq!(ids(select_rows(&mut engine, "SELECT * FROM generate_series(1, 5)")), vec![1, 2, 3, 4, 5]);
+        assert_eq!(ids(select_rows(&mut engine, "SELECT S.GENERATE_SERIES FROM generate_series(10, 1, -3) S")), vec![10, 7, 4, 1]);
+        assert_eq!(ids(select_rows(&mut engine, "SELECT N FROM NUMS JOIN generate_series(1, 3) S ON N = S.GENERATE_SERIES")), vec![2]);
+        assert_eq!(select_rows(&mut engine, "SELECT * FROM generate_series(1, NULL)"), Vec::<Vec<LiteralValue>>::new());
+
+        let path = ::std::env::temp_dir().join("rustql_csv_table_test.csv");
+        {
+            use std::io::Write;
+            let mut file = ::std::fs::File::create(&path).unwrap();
+            file.write_all(b"Id,Name\r\n1,\"a, \"\"b\"\"\"\n2,\n\n").unwrap();
+        }
+        let path = path.to_str().unwrap().to_string();
+        assert_eq!(select_rows(&mut engine, &format!("SELECT ID, NAME FROM csv_table('{}', true) ORDER BY NAME", path)),
+                   vec![vec![text("1"), text("a, \"b\"")], vec![text("2"), LiteralValue::Null]]);
+        assert_eq!(select_rows(&mut engine, &format!("SELECT COLUMN1 FROM csv_table('{}', false)", path)),
+                   vec![vec![text("Id")], vec![text("1")], vec![text("2")]]);
+        ::std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(select_rows(&mut engine, "SELECT KEY, VALUE FROM json_each('{\"a\": 1, \"b\": \"x\", \"c\": null, \"d\": [1.5, {\"e\": true}]}')"),
+                   vec![vec![text("a"), text("1")], vec![text("b"), text("x")], vec![text("c"), LiteralValue::Null],
+                        vec![text("d"), text("[1.5,{\"e\":true}]")]]);
+        assert_eq!(select_rows(&mut engine, "SELECT * FROM json_each('[\"p\\u0021\", -2e3]') J WHERE J.KEY = '0'"),
+                   vec![vec![text("0"), text("p!")]]);
+
+        // Custom functions are registered with the engine
+        struct Squares;
+        impl ::TableFunction for Squares {
+            fn columns(&self, _args: &[LiteralValue]) -> SqlError<Vec<ColumnDefinition>> {
+                Ok(vec![ColumnDefinition { name: "square".to_string(), ctype: ColumnType::Integer }])
+            }
+            fn rows(&self, args: &[LiteralValue]) -> SqlError<::FunctionRows> {
+                let count = try!(args[0].as_integer());
+                Ok(Box::new((1..count + 1).map(|i| -> SqlError<Vec<LiteralValue>> { Ok(vec![LiteralValue::Integer(i * i)]) })))
+            }
+        }
+        engine.register_table_function("squares", Squares);
+        assert_eq!(select_rows(&mut engine, "SELECT SUM(SQUARE) FROM squares(3)"), vec![vec![int(14)]]);
+
+        let error_code = |engine: &mut SqlEngine, sql: &str| engine.excecute_stmt(sql_expression(sql).unwrap()).unwrap_err().code();
+        assert_eq!(error_code(&mut engine, "SELECT * FROM no_such_function(1)"), "42883");
+        assert_eq!(error_code(&mut engine, "SELECT * FROM generate_series(1, 5, 0)"), "42601");
+        assert_eq!(error_code(&mut engine, "SELECT * FROM generate_series(1, 'a')"), "42804");
+        assert_eq!(error_code(&mut engine, "SELECT * FROM generate_series(1, n)"), "42601");
+        assert_eq!(error_code(&mut engine, "SELECT * FROM csv_table('/nonexistent/rustql.csv', true)"), "58030");
+        assert_eq!(error_code(&mut engine, "SELECT * FROM json_each('{\"a\": }')"), "22P02");
+        assert_eq!(error_code(&mut engine, "SELECT * FROM json_each('1')"), "22P02");
+        let nested = |depth: usize| -> String {
+            let open: String = ::std::iter::repeat("[").take(depth).collect();
+            let close: String = ::std::iter::repeat("]").take(depth).collect();
+            format!("SELECT * FROM json_each('{}{}')", open, close)
+        };
+        assert_eq!(select_rows(&mut engine, &nested(512)).len(), 1);
+        assert_eq!(error_code(&mut engine, &nested(513)), "22P02");
+    }
+
+    #[test]
+    fn custom_tables() {
+        use std::cell::{Cell,RefCell};
+        use std::rc::Rc;
+        use ::{Table,RowId};
+
+        // Rows kept by the application, found by their first column through an index
+        struct SharedTable {
+            columns: Vec<ColumnDefinition>,
+            rows: Rc<RefCell<Vec<Option<Vec<LiteralValue>>>>>,
+            lookups: Rc<Cell<usize>>,
+            copies: Rc<Cell<usize>>
+        }
+        impl Table for SharedTable {
+            fn columns(&self) -> &[ColumnDefinition] {
+                &self.columns[..]
+            }
+            fn len(&self) -> usize {
+                self.rows.borrow().iter().filter(|row| row.is_some()).count()
+            }
+            fn scan(&self) -> SqlError<Box<Iterator<Item=RowId>>> {
+                let rowids: Vec<RowId> = self.rows.borrow().iter().enumerate().filter(|&(_, row)| row.is_some()).map(|(i, _)| i).collect();
+                Ok(Box::new(rowids.into_iter()))
+            }
+            fn get_row(&self, rowid: RowId) -> SqlError<Vec<LiteralValue>> {
+                match self.rows.borrow().get(rowid) {
+                    Some(&Some(ref row)) => Ok(row.clone()),
+                    _ => Err(Error::Internal(format!("Row {} doesn't exist", rowid)))
+                }
+            }
+            fn insert(&mut self, row: Vec<LiteralValue>) -> SqlError<RowId> {
+                let mut rows = self.rows.borrow_mut();
+                rows.push(Some(row));
+                let rowid = rows.len() - 1;
+                Ok(rowid)
+            }
+            fn update(&mut self, rowid: RowId, row: Vec<LiteralValue>) -> SqlError<()> {
+                self.rows.borrow_mut()[rowid] = Some(row);
+                Ok(())
+            }
+            fn delete(&mut self, rowid: RowId) -> SqlError<()> {
+                self.rows.borrow_mut()[rowid] = None;
+                Ok(())
+            }
+            fn lookup(&self, column: usize, value: &LiteralValue) -> SqlError<Option<Vec<RowId>>> {
+                if column != 0 {
+                    return Ok(None);
+                }
+                self.lookups.set(self.lookups.get() + 1);
+                let rowids = self.rows.borrow().iter().enumerate().filter(|&(_, row)| {
+                    row.as_ref().map_or(false, |row| row[0] == *value)
+                }).map(|(i, _)| i).collect();
+                Ok(Some(rowids))
+            }
+            fn box_clone(&self) -> Box<Table> {
+                self.copies.set(self.copies.get() + 1);
+                Box::new(SharedTable {
+                    columns: self.columns.clone(),
+                    rows: self.rows.clone(),
+                    lookups: self.lookups.clone(),
+                    copies: self.copies.clone()
+                })
+            }
+        }
+
+        // A read-only table of the digits
+        struct Digits {
+            columns: Vec<ColumnDefinition>
+        }
+        impl Table for Digits {
+            fn columns(&self) -> &[ColumnDefinition] {
+                &self.columns[..]
+            }
+            fn len(&self) -> usize {
+                10
+            }
+            fn scan(&self) -> SqlError<Box<Iterator<Item=RowId>>> {
+                Ok(Box::new(0..10))
+            }
+            fn get_row(&self, rowid: RowId) -> SqlError<Vec<LiteralValue>> {
+                Ok(vec![LiteralValue::Integer(rowid as i64)])
+            }
+            fn box_clone(&self) -> Box<Table> {
+                Box::new(Digits { columns: self.columns.clone() })
+            }
+        }
+
+        let int = |i: i64| LiteralValue::Integer(i);
+        let text = |s: &str| LiteralValue::Text(s.to_string());
+        let column = |name: &str, ctype: ColumnType| ColumnDefinition { name: name.to_string(), ctype: ctype };
+        let rows = Rc::new(RefCell::new(vec![Some(vec![int(1), text("ann")]), Some(vec![int(2), text("bob")])]));
+        let lookups = Rc::new(Cell::new(0));
+        let copies = Rc::new(Cell::new(0));
+
+        let mut engine = SqlEngine::new();
+        engine.register_table("People", SharedTable {
+            columns: vec![column("id", ColumnType::Integer), column("name", ColumnType::Text)],
+            rows: rows.clone(),
+            lookups: lookups.clone(),
+            copies: copies.clone()
+        }).unwrap();
+        engine.register_table("digits", Digits { columns: vec![column("d", ColumnType::Integer)] }).unwrap();
+        engine.excecute_stmt(sql_expression("CREATE TABLE PETS(OWNER INTEGER, PET TEXT)").unwrap()).unwrap();
+        engine.excecute_stmt(sql_expression("INSERT INTO PETS(OWNER, PET) VALUES (2, 'cat')").unwrap()).unwrap();
+        engine.excecute_stmt(sql_expression("INSERT INTO PETS(OWNER, PET) VALUES (3, 'dog')").unwrap()).unwrap();
+
+        assert_eq!(select_rows(&mut engine, "SELECT NAME FROM PEOPLE WHERE ID = 2"), vec![vec![text("bob")]]);
+        assert_eq!(lookups.get(), 1);
+        // Columns without an index are scanned
+        assert_eq!(select_rows(&mut engine, "SELECT ID FROM PEOPLE WHERE NAME = 'ann'"), vec![vec![int(1)]]);
+        assert_eq!(lookups.get(), 1);
+        assert_eq!(select_rows(&mut engine, "SELECT PET, NAME FROM PETS LEFT JOIN PEOPLE ON OWNER = ID ORDER BY PET"),
+                   vec![vec![text("cat"), text("bob")], vec![text("dog"), LiteralValue::Null]]);
+        assert_eq!(select_rows(&mut engine, "SELECT COUNT(*) FROM DIGITS WHERE D > 6"), vec![vec![int(3)]]);
+
+        // Changes made with SQL go through the backend
+        engine.excecute_stmt(sql_expression("INSERT INTO PEOPLE(ID, NAME) VALUES (3, 'cy')").unwrap()).unwrap();
+        engine.excecute_stmt(sql_expression("UPDATE PEOPLE SET NAME = 'bo' WHERE ID = 2").unwrap()).unwrap();
+        engine.excecute_stmt(sql_expression("DELETE FROM PEOPLE WHERE ID = 1").unwrap()).unwrap();
+        assert_eq!(*rows.borrow(), vec![None, Some(vec![int(2), text("bo")]), Some(vec![int(3), text("cy")])]);
+
+        // Statements without a database file change the tables in place
+        for i in 0..1000 {
+            let sql = format!("INSERT INTO PEOPLE(ID, NAME) VALUES ({}, 'p{}')", i + 10, i);
+            engine.excecute_stmt(sql_expression(&sql).unwrap()).unwrap();
+        }
+        assert_eq!(select_rows(&mut engine, "SELECT COUNT(*) FROM PEOPLE"), vec![vec![int(1002)]]);
+        assert_eq!(copies.get(), 0);
+
+        let error_code = |engine: &mut SqlEngine, sql: &str| engine.excecute_stmt(sql_expression(sql).unwrap()).unwrap_err().code();
+        assert_eq!(error_code(&mut engine, "INSERT INTO DIGITS(D) VALUES (10)"), "0A000");
+        assert_eq!(engine.register_table("pets", Digits { columns: Vec::new() }).unwrap_err().code(), "42P07");
+
+        // Values of another type than their column's cannot be compared
+        rows.borrow_mut().push(Some(vec![text("x"), text("x")]));
+        assert_eq!(error_code(&mut engine, "SELECT PET FROM PETS WHERE OWNER IN (SELECT ID FROM PEOPLE)"), "42804");
+    }
+
+    #[test]
+    fn persistence() {
+        use std::env;
+        use std::fs::{self,File,OpenOptions};
+        use std::io::{Seek,SeekFrom,Write};
+
+        use ::{Table,RowId};
+
+        // A table whose rows are kept by the application
+        struct Empty {
+            columns: Vec<ColumnDefinition>
+        }
+        impl Table for Empty {
+            fn columns(&self) -> &[ColumnDefinition] {
+                &self.columns[..]
+            }
+            fn len(&self) -> usize {
+                0
+            }
+            fn scan(&self) -> SqlError<Box<Iterator<Item=RowId>>> {
+                Ok(Box::new(0..0))
+            }
+            fn get_row(&self, rowid: RowId) -> SqlError<Vec<LiteralValue>> {
+                Err(Error::Internal(format!("No row {}", rowid)))
+            }
+            fn box_clone(&self) -> Box<Table> {
+                Box::new(Empty { columns: self.columns.clone() })
+            }
+        }
+
+        let path = env::temp_dir().join(format!("rustql-persistence-test-{}.db", unsafe { ::libc::getpid() }));
+        let _ = fs::remove_file(&path);
+        let int = |i: i64| LiteralValue::Integer(i);
+        let text = |s: &str| LiteralValue::Text(s.to_string());
+        let execute = |engine: &mut SqlEngine, sql: &str| { engine.excecute_stmt(sql_expression(sql).unwrap()).unwrap(); };
+        let ids = |rows: Vec<Vec<LiteralValue>>| -> Vec<i64> {
+            rows.iter().map(|row| row[0].as_integer().unwrap()).collect()
+        };
+
+        // Opening a missing file creates an empty database
+        let mut engine = SqlEngine::open(&path).unwrap();
+        assert!(engine.table_names().is_empty());
+        execute(&mut engine, "CREATE TABLE ITEMS(ID INTEGER, NAME TEXT, PRICE REAL, SOLD BOOL)");
+        execute(&mut engine, "CREATE TABLE OLD(X INTEGER)");
+        execute(&mut engine, "INSERT INTO ITEMS(ID, NAME, PRICE, SOLD) VALUES (1, 'pen', 1.25, FALSE)");
+        execute(&mut engine, "INSERT INTO ITEMS(ID, NAME, PRICE, SOLD) VALUES (2, 'ink', NULL, TRUE)");
+        execute(&mut engine, "INSERT INTO ITEMS(ID, NAME, PRICE, SOLD) VALUES (3, 'cap', -0.5, NULL)");
+        execute(&mut engine, "UPDATE ITEMS SET NAME = 'nib' WHERE ID = 1");
+        execute(&mut engine, "DELETE FROM ITEMS WHERE ID = 2");
+        execute(&mut engine, "DROP TABLE OLD");
+        drop(engine);
+
+        let mut engine = SqlEngine::open(&path).unwrap();
+        assert_eq!(engine.table_names(), vec!["items".to_string()]);
+        assert_eq!(select_rows(&mut engine, "SELECT ID, NAME, PRICE, SOLD FROM ITEMS ORDER BY ID"),
+                   vec![vec![int(1), text("nib"), LiteralValue::Real(1.25), LiteralValue::Bool(false)],
+                        vec![int(3), text("cap"), LiteralValue::Real(-0.5), LiteralValue::Null]]);
+        // Tables of other backends are not saved
+        engine.register_table("empty", Empty { columns: Vec::new() }).unwrap();
+        execute(&mut engine, "INSERT INTO ITEMS(ID, NAME, PRICE, SOLD) VALUES (4, 'pad', 3.0, TRUE)");
+        // A statement failing part way leaves no change, in memory or in the file
+        let err = engine.excecute_stmt(sql_expression("UPDATE ITEMS SET ID = 10 / (ID - 3)").unwrap()).unwrap_err();
+        assert_eq!(err.code(), "22012");
+        assert_eq!(ids(select_rows(&mut engine, "SELECT ID FROM ITEMS ORDER BY ID")), vec![1, 3, 4]);
+        drop(engine);
+        let mut engine = SqlEngine::open(&path).unwrap();
+        assert_eq!(engine.table_names(), vec!["items".to_string()]);
+        assert_eq!(ids(select_rows(&mut engine, "SELECT ID FROM ITEMS ORDER BY ID")), vec![1, 3, 4]);
+        drop(engine);
+
+        // A changed byte is caught by the page checksum
+        {
+            let mut file = OpenOptions::new().read(true).write(true).open(&path).unwrap();
+            file.seek(SeekFrom::Start(4096 + 20)).unwrap();
+            file.write_all(&[0xFF]).unwrap();
+        }
+        assert_eq!(SqlEngine::open(&path).err().unwrap().code(), "XX001");
+        File::create(&path).unwrap().write_all(b"not a database").unwrap();
+        assert_eq!(SqlEngine::open(&path).err().unwrap().code(), "XX001");
+        fs::remove_file(&path).unwrap();
+    }
+
 }